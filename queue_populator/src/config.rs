@@ -6,6 +6,8 @@ pub struct QueuePopulatorConfig {
     pub cache_url: String,
     pub max_connections: u32,
     pub poll_interval_seconds: u64,
+    pub claimant_id: String,
+    pub batch_size: i64,
 }
 
 impl QueuePopulatorConfig {
@@ -15,6 +17,8 @@ impl QueuePopulatorConfig {
             cache_url: config.redis_url.clone(),
             max_connections: 10, // Default max connections
             poll_interval_seconds: 1, // Default poll interval
+            claimant_id: format!("queue_populator-{}", uuid::Uuid::new_v4()),
+            batch_size: 100, // Jobs claimed per poll
         }
     }
 }
@@ -1,74 +1,119 @@
-use scheduler_core::{cache::Cache, db::Database, task::TaskManager, JobStatus};
-use tracing::error;
+use scheduler_core::{
+    cache::{Cache, JOB_QUEUE},
+    db::{Database, JobUpdate},
+    task::TaskManager,
+    workflow::WorkflowManager,
+    Job, JobStatus,
+};
+use tracing::{error, info};
 
 use crate::error::{QueuePopulatorError, Result};
 
 pub struct JobProcessor {
     cache: Cache,
     task_manager: TaskManager,
+    workflow_manager: WorkflowManager,
+    claimant_id: String,
+    batch_size: i64,
 }
 
 impl JobProcessor {
-    pub async fn new(cache: Cache, database_url: &str) -> Result<Self> {
+    pub async fn new(
+        cache: Cache,
+        database_url: &str,
+        claimant_id: String,
+        batch_size: i64,
+    ) -> Result<Self> {
         let db = Database::new(database_url)
             .await
             .map_err(QueuePopulatorError::from)?;
-        let task_manager = TaskManager::new(db);
+        let task_manager = TaskManager::new(db.clone());
+        let workflow_manager = WorkflowManager::new(db);
         Ok(Self {
             cache,
             task_manager,
+            workflow_manager,
+            claimant_id,
+            batch_size,
         })
     }
 
     pub async fn process_jobs(&self) -> Result<()> {
-        let due_jobs = self.fetch_due_jobs().await?;
+        // Release or skip conditional workflow steps before claiming
+        self.workflow_manager.advance(self.batch_size).await?;
+        self.cancel_jobs_with_failed_upstream().await?;
 
-        for job in due_jobs {
+        // Jobs whose upstream jobs have not all completed are not claimed
+        let claimed_jobs = self.claim_due_jobs().await?;
+
+        for job in claimed_jobs {
             if let Err(e) = self.push_job_to_queue(&job).await {
                 error!("Failed to push job {} to queue: {}", job.id, e);
-                continue;
+                // Release the claim so the job is picked up on a later poll
+                if let Err(e) = self.release_job(&job).await {
+                    error!("Failed to release job {}: {}", job.id, e);
+                }
             }
+        }
+
+        Ok(())
+    }
+
+    /// Cancels pending jobs with the cascade-cancel policy once an upstream
+    /// job is cancelled, dead-lettered or deleted. Their own downstream jobs
+    /// follow on later polls.
+    async fn cancel_jobs_with_failed_upstream(&self) -> Result<()> {
+        let jobs = self
+            .task_manager
+            .get_jobs_with_failed_upstream(self.batch_size)
+            .await?;
 
-            if let Err(e) = self.update_job_status(&job).await {
-                error!("Failed to update job {} status: {}", job.id, e);
+        for job in jobs {
+            let update = JobUpdate::new().last_error(Some(
+                "Cancelled because an upstream job was cancelled, dead-lettered or deleted".to_string(),
+            ));
+            match self
+                .task_manager
+                .transition_job_with(
+                    &job.id.to_string(),
+                    JobStatus::Pending,
+                    JobStatus::Cancelled,
+                    &update,
+                )
+                .await
+            {
+                Ok(()) => info!("Cancelled job {} after its upstream failed", job.id),
+                Err(e) => error!("Failed to cancel job {}: {}", job.id, e),
             }
         }
 
         Ok(())
     }
 
-    async fn fetch_due_jobs(&self) -> Result<Vec<scheduler_core::task::Job>> {
+    async fn claim_due_jobs(&self) -> Result<Vec<Job>> {
         self.task_manager
-            .get_due_jobs(100)
+            .claim_due_jobs(self.batch_size, &self.claimant_id)
             .await
             .map_err(QueuePopulatorError::from)
     }
 
-    async fn push_job_to_queue(&self, job: &scheduler_core::task::Job) -> Result<()> {
-        let job_json = serde_json::to_string(job)?;
-        let queue_name = format!("jobs:{}", job.priority);
-
+    async fn push_job_to_queue(&self, job: &Job) -> Result<()> {
         self.cache
-            .push_to_queue(&queue_name, &job_json)
+            .push_to_queue(JOB_QUEUE, &job.id.to_string())
             .await
             .map_err(QueuePopulatorError::from)
     }
 
-    async fn update_job_status(&self, job: &scheduler_core::task::Job) -> Result<()> {
+    async fn release_job(&self, job: &Job) -> Result<()> {
+        let update = JobUpdate::new().claimed_by(None).claimed_at(None);
         self.task_manager
-            .update_job_status(&job.id, JobStatus::Pending)
+            .transition_job_with(
+                &job.id.to_string(),
+                JobStatus::Queued,
+                JobStatus::Pending,
+                &update,
+            )
             .await?;
-
-        if let Some(queue_name) = self.get_queue_name(&job) {
-            self.cache
-                .push_to_priority_queue(&queue_name, &job.id, job.priority)
-                .await?;
-        }
-
         Ok(())
     }
-
-    fn get_queue_name(&self, job: &scheduler_core::task::Job) -> Option<String> {
-        None
-    }
 }
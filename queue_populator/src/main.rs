@@ -29,29 +29,35 @@ async fn main() -> Result<()> {
     };
     let cache = Cache::new(cache_config).await?;
 
-    let job_processor = JobProcessor::new(cache, &config.database_url).await?;
+    let job_processor = JobProcessor::new(
+        cache,
+        &config.database_url,
+        config.claimant_id.clone(),
+        config.batch_size,
+    )
+    .await?;
 
-    info!("Queue populator service started");
+    info!("Queue populator service {} started", config.claimant_id);
 
     // Main loop with graceful shutdown
-    let mut shutdown = false;
-    while !shutdown {
-        match job_processor.process_jobs().await {
-            Ok(_) => {
-                // Sleep for configured interval before next iteration
-                sleep(Duration::from_secs(config.poll_interval_seconds)).await;
-            }
+    loop {
+        let delay = match job_processor.process_jobs().await {
+            // Sleep for configured interval before next iteration
+            Ok(_) => Duration::from_secs(config.poll_interval_seconds),
             Err(e) => {
                 error!("Error processing jobs: {}", e);
                 // Sleep for a shorter interval on error to prevent tight loops
-                sleep(Duration::from_secs(1)).await;
+                Duration::from_secs(1)
             }
-        }
+        };
 
-        // Check for shutdown signal
-        if let Ok(_) = signal::ctrl_c().await {
-            info!("Received shutdown signal, stopping gracefully...");
-            shutdown = true;
+        // Stop on shutdown signal, otherwise poll again after the delay
+        tokio::select! {
+            _ = signal::ctrl_c() => {
+                info!("Received shutdown signal, stopping gracefully...");
+                break;
+            }
+            _ = sleep(delay) => {}
         }
     }
 
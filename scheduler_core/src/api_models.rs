@@ -1,4 +1,5 @@
-use crate::models::JobType;
+use crate::models::{JobType, UpstreamFailurePolicy};
+use crate::retry::RetryPolicy;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -13,6 +14,18 @@ pub struct JobCreate {
     pub payload: Value,
     pub max_retries: Option<u32>,
     pub template_id: Option<i32>,
+    /// Optional; jobs created with an API key belong to its merchant, which
+    /// this must match.
+    pub merchant_id: Option<Uuid>,
+    /// Idempotency key; the `Idempotency-Key` header takes the same role.
+    pub reference_id: Option<String>,
+    /// Jobs that must complete before this one runs (one-time jobs only).
+    #[serde(default)]
+    pub depends_on: Vec<Uuid>,
+    pub on_upstream_failure: Option<UpstreamFailurePolicy>,
+    /// Backoff between attempts; exponential from one minute up to an hour
+    /// if not given.
+    pub retry_policy: Option<RetryPolicy>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -25,6 +38,12 @@ pub struct JobUpdate {
     pub max_retries: Option<u32>,
 }
 
+/// Body of a dead letter replay; the job keeps its payload unless one is given.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct DeadLetterReplay {
+    pub payload: Option<Value>,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct TemplateCreate {
     pub cron: Option<String>,
@@ -44,12 +63,24 @@ pub struct JobResponse {
     pub job_id: Uuid,
 }
 
+#[derive(Debug, Serialize)]
+pub struct WorkflowResponse {
+    pub message: String,
+    pub workflow_run_id: Uuid,
+}
+
 #[derive(Debug, Serialize)]
 pub struct TemplateResponse {
     pub message: String,
     pub template_id: i32,
 }
 
+#[derive(Debug, Serialize)]
+pub struct WorkerResponse {
+    pub message: String,
+    pub worker_id: String,
+}
+
 #[derive(Debug, Serialize)]
 pub struct DeleteResponse {
     pub message: String,
@@ -1,14 +1,92 @@
 use anyhow::Result;
-use redis::{AsyncCommands, Client, aio::MultiplexedConnection};
+use redis::{
+    AsyncCommands, Client, Script,
+    aio::{MultiplexedConnection, PubSub},
+};
 use std::time::Duration;
 
+/// Queue the executors consume job ids from.
+pub const JOB_QUEUE: &str = "jobs";
+
+/// Pub/sub channel carrying the ids of running jobs that were cancelled.
+pub const CANCEL_CHANNEL: &str = "jobs:cancel";
+
+/// Moves the oldest message into the worker's processing list and leases it
+/// until `now + ARGV[1]` milliseconds, using the Redis clock.
+const RESERVE_SCRIPT: &str = r#"
+local value = redis.call('RPOPLPUSH', KEYS[1], KEYS[2])
+if value then
+    local t = redis.call('TIME')
+    local now = tonumber(t[1]) * 1000 + math.floor(tonumber(t[2]) / 1000)
+    redis.call('ZADD', KEYS[3], now + tonumber(ARGV[1]), ARGV[2] .. '|' .. value)
+end
+return value
+"#;
+
+const ACK_SCRIPT: &str = r#"
+local removed = redis.call('LREM', KEYS[1], 1, ARGV[1])
+redis.call('ZREM', KEYS[2], ARGV[2])
+return removed
+"#;
+
+const NACK_SCRIPT: &str = r#"
+local removed = redis.call('LREM', KEYS[1], 1, ARGV[1])
+redis.call('ZREM', KEYS[2], ARGV[2])
+if removed > 0 then
+    redis.call('LPUSH', KEYS[3], ARGV[1])
+end
+return removed
+"#;
+
+/// Pushes an existing lease out to `now + ARGV[1]` milliseconds.
+const EXTEND_SCRIPT: &str = r#"
+local t = redis.call('TIME')
+local now = tonumber(t[1]) * 1000 + math.floor(tonumber(t[2]) / 1000)
+return redis.call('ZADD', KEYS[1], 'XX', 'CH', now + tonumber(ARGV[1]), ARGV[2])
+"#;
+
+/// Lists the `worker|value` members whose lease has expired.
+const EXPIRED_LEASES_SCRIPT: &str = r#"
+local t = redis.call('TIME')
+local now = tonumber(t[1]) * 1000 + math.floor(tonumber(t[2]) / 1000)
+return redis.call('ZRANGEBYSCORE', KEYS[1], '-inf', now)
+"#;
+
+/// Returns those of ARGV that wait in the queue or are reserved by a worker.
+const ENQUEUED_SCRIPT: &str = r#"
+local present = {}
+for _, value in ipairs(redis.call('LRANGE', KEYS[1], 0, -1)) do
+    present[value] = true
+end
+for _, member in ipairs(redis.call('ZRANGE', KEYS[2], 0, -1)) do
+    local sep = string.find(member, '|', 1, true)
+    if sep then
+        present[string.sub(member, sep + 1)] = true
+    end
+end
+local found = {}
+for _, value in ipairs(ARGV) do
+    if present[value] then
+        table.insert(found, value)
+    end
+end
+return found
+"#;
+
+/// A message held in a worker's processing list.
+#[derive(Debug, Clone)]
+pub struct Reservation {
+    pub worker_id: String,
+    pub value: String,
+}
+
 #[derive(Debug)]
 pub struct CacheConfig {
     pub url: String,
     pub max_connections: u32,
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub struct Cache {
     client: Client,
 }
@@ -35,6 +113,128 @@ impl Cache {
         Ok(value)
     }
 
+    /// Removes every copy of `value` still waiting in the queue.
+    pub async fn remove_from_queue(&self, queue_name: &str, value: &str) -> Result<bool> {
+        let mut conn = self.get_conn().await?;
+        let removed: i64 = conn.lrem(queue_name, 0, value).await?;
+        Ok(removed > 0)
+    }
+
+    pub async fn publish(&self, channel: &str, message: &str) -> Result<()> {
+        let mut conn = self.get_conn().await?;
+        let _: i64 = conn.publish(channel, message).await?;
+        Ok(())
+    }
+
+    pub async fn subscribe(&self, channel: &str) -> Result<PubSub> {
+        let mut pubsub = self.client.get_async_pubsub().await?;
+        pubsub.subscribe(channel).await?;
+        Ok(pubsub)
+    }
+
+    /// Pops the next message and parks it in the worker's processing list until
+    /// it is acknowledged. If neither `ack` nor `nack` is called before
+    /// `visibility_timeout` elapses, it shows up in `expired_reservations`.
+    pub async fn reserve_from_queue(
+        &self,
+        queue_name: &str,
+        worker_id: &str,
+        visibility_timeout: Duration,
+    ) -> Result<Option<String>> {
+        let mut conn = self.get_conn().await?;
+        let value: Option<String> = Script::new(RESERVE_SCRIPT)
+            .key(queue_name)
+            .key(processing_list(queue_name, worker_id))
+            .key(lease_set(queue_name))
+            .arg(visibility_timeout.as_millis() as u64)
+            .arg(worker_id)
+            .invoke_async(&mut conn)
+            .await?;
+        Ok(value)
+    }
+
+    /// Drops a reserved message once it has been fully handled.
+    pub async fn ack(&self, queue_name: &str, worker_id: &str, value: &str) -> Result<bool> {
+        let mut conn = self.get_conn().await?;
+        let removed: i64 = Script::new(ACK_SCRIPT)
+            .key(processing_list(queue_name, worker_id))
+            .key(lease_set(queue_name))
+            .arg(value)
+            .arg(lease_member(worker_id, value))
+            .invoke_async(&mut conn)
+            .await?;
+        Ok(removed > 0)
+    }
+
+    /// Gives a reserved message back to the queue straight away.
+    pub async fn nack(&self, queue_name: &str, worker_id: &str, value: &str) -> Result<bool> {
+        let mut conn = self.get_conn().await?;
+        let removed: i64 = Script::new(NACK_SCRIPT)
+            .key(processing_list(queue_name, worker_id))
+            .key(lease_set(queue_name))
+            .key(queue_name)
+            .arg(value)
+            .arg(lease_member(worker_id, value))
+            .invoke_async(&mut conn)
+            .await?;
+        Ok(removed > 0)
+    }
+
+    /// Restarts the visibility timeout of a message the worker still holds,
+    /// so long-running jobs are not handed out again.
+    pub async fn extend_reservation(
+        &self,
+        queue_name: &str,
+        worker_id: &str,
+        value: &str,
+        visibility_timeout: Duration,
+    ) -> Result<bool> {
+        let mut conn = self.get_conn().await?;
+        let changed: i64 = Script::new(EXTEND_SCRIPT)
+            .key(lease_set(queue_name))
+            .arg(visibility_timeout.as_millis() as u64)
+            .arg(lease_member(worker_id, value))
+            .invoke_async(&mut conn)
+            .await?;
+        Ok(changed > 0)
+    }
+
+    /// Reservations whose visibility timeout has passed without an ack. The
+    /// caller decides whether to `nack` (requeue) or `ack` (drop) each one.
+    pub async fn expired_reservations(&self, queue_name: &str) -> Result<Vec<Reservation>> {
+        let mut conn = self.get_conn().await?;
+        let members: Vec<String> = Script::new(EXPIRED_LEASES_SCRIPT)
+            .key(lease_set(queue_name))
+            .invoke_async(&mut conn)
+            .await?;
+        Ok(members
+            .into_iter()
+            .filter_map(|member| {
+                let (worker_id, value) = member.split_once('|')?;
+                Some(Reservation {
+                    worker_id: worker_id.to_string(),
+                    value: value.to_string(),
+                })
+            })
+            .collect())
+    }
+
+    /// Which of `values` are still in the queue or reserved by a worker,
+    /// checked in one step so a message cannot move between the two unseen.
+    pub async fn enqueued(&self, queue_name: &str, values: &[String]) -> Result<Vec<String>> {
+        if values.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut conn = self.get_conn().await?;
+        let found: Vec<String> = Script::new(ENQUEUED_SCRIPT)
+            .key(queue_name)
+            .key(lease_set(queue_name))
+            .arg(values)
+            .invoke_async(&mut conn)
+            .await?;
+        Ok(found)
+    }
+
     pub async fn push_to_priority_queue(
         &self,
         queue_name: &str,
@@ -54,7 +254,7 @@ impl Cache {
 
     pub async fn set_with_ttl(&self, key: &str, value: &str, ttl: Duration) -> Result<()> {
         let mut conn = self.get_conn().await?;
-        let _: () = conn.set_ex(key, value, ttl.as_secs() as u64).await?;
+        let _: () = conn.set_ex(key, value, ttl.as_secs()).await?;
         Ok(())
     }
 
@@ -88,3 +288,16 @@ impl Cache {
         Ok(result)
     }
 }
+
+/// List holding the messages `worker_id` has reserved from `queue_name`.
+pub fn processing_list(queue_name: &str, worker_id: &str) -> String {
+    format!("{}:processing:{}", queue_name, worker_id)
+}
+
+fn lease_set(queue_name: &str) -> String {
+    format!("{}:leases", queue_name)
+}
+
+fn lease_member(worker_id: &str, value: &str) -> String {
+    format!("{}|{}", worker_id, value)
+}
@@ -1,4 +1,5 @@
 use crate::error::Error;
+use crate::models::PartitionInterval;
 use regex::Regex;
 use serde::Deserialize;
 use serde_yaml::Value;
@@ -8,6 +9,7 @@ use std::collections::HashMap;
 use std::env;
 use std::fs::File;
 use std::io::Read;
+use std::str::FromStr;
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
@@ -15,6 +17,43 @@ pub struct Config {
     pub redis_url: String,
     pub max_retries: u32,
     pub queue_names: Vec<String>,
+    pub visibility_timeout_secs: u64,
+    /// How long a worker's and its jobs' leases last without a heartbeat.
+    pub lease_ttl_secs: u64,
+    /// How long a stopping executor waits for running jobs before killing
+    /// them and requeueing their jobs.
+    pub shutdown_grace_secs: u64,
+    /// How long a job's processes get to exit after SIGTERM before they are
+    /// killed with SIGKILL.
+    pub kill_grace_secs: u64,
+    /// cgroup v2 directory the executor creates per-job cgroups under. Unset,
+    /// or not writable, means jobs are limited with rlimits instead.
+    pub cgroup_root: Option<String>,
+    /// Sandbox profiles the executor lets jobs ask for, comma-separated in
+    /// the environment. Empty means jobs asking for any are refused.
+    pub sandbox_profiles: Vec<String>,
+    /// User and group sandboxed jobs run as.
+    pub sandbox_uid: u32,
+    pub sandbox_gid: u32,
+    /// Interpreters script jobs may ask for, comma-separated in the
+    /// environment. Empty means script jobs are refused.
+    pub script_interpreters: Vec<String>,
+    /// Width of each partition of the `jobs` table.
+    pub partition_interval: PartitionInterval,
+    /// How many partitions past the current one to create ahead of time.
+    pub partition_premake: u32,
+    /// Partitions whose range ended longer ago than this are expired, once
+    /// archival has emptied them.
+    pub partition_retention_days: i64,
+    /// Drop expired partitions instead of only detaching them.
+    pub drop_expired_partitions: bool,
+    /// Terminal jobs older than this are moved to `jobs_archive`.
+    pub archive_after_days: i64,
+    /// Archived jobs older than this are deleted for good.
+    pub archive_retention_days: i64,
+    /// Queued jobs whose claim is older than this and that are neither in
+    /// the queue nor reserved are handed back to the queue populator.
+    pub stale_claim_secs: u64,
 }
 
 impl Config {
@@ -40,6 +79,27 @@ impl Config {
                 .parse()
                 .map_err(|_| Error::ConfigError("Invalid MAX_RETRIES".to_string()))?,
             queue_names,
+            visibility_timeout_secs: env_or("VISIBILITY_TIMEOUT_SECS", 600)?,
+            lease_ttl_secs: env_or("LEASE_TTL_SECS", 30)?,
+            shutdown_grace_secs: env_or("SHUTDOWN_GRACE_SECS", 30)?,
+            kill_grace_secs: env_or("KILL_GRACE_SECS", 10)?,
+            cgroup_root: env::var("CGROUP_ROOT").ok(),
+            sandbox_profiles: env::var("SANDBOX_PROFILES")
+                .map(|profiles| comma_separated(&profiles))
+                .unwrap_or_default(),
+            // nobody:nogroup
+            sandbox_uid: env_or("SANDBOX_UID", 65534)?,
+            sandbox_gid: env_or("SANDBOX_GID", 65534)?,
+            script_interpreters: comma_separated(
+                &env::var("SCRIPT_INTERPRETERS").unwrap_or_else(|_| "bash,sh,python3".into()),
+            ),
+            partition_interval: env_or("PARTITION_INTERVAL", PartitionInterval::Monthly)?,
+            partition_premake: env_or("PARTITION_PREMAKE", 3)?,
+            partition_retention_days: env_or("PARTITION_RETENTION_DAYS", 90)?,
+            drop_expired_partitions: env_or("DROP_EXPIRED_PARTITIONS", false)?,
+            archive_after_days: env_or("ARCHIVE_AFTER_DAYS", 30)?,
+            archive_retention_days: env_or("ARCHIVE_RETENTION_DAYS", 365)?,
+            stale_claim_secs: env_or("STALE_CLAIM_SECS", 300)?,
         })
     }
 
@@ -90,3 +150,22 @@ impl Config {
         .to_string()
     }
 }
+
+/// Splits a list like `a, b,c`, skipping empty entries.
+fn comma_separated(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Reads an optional variable, falling back to `default` when it is unset.
+fn env_or<T: FromStr>(name: &str, default: T) -> Result<T, Error> {
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|_| Error::ConfigError(format!("Invalid {}", name))),
+        Err(_) => Ok(default),
+    }
+}
@@ -1,11 +1,17 @@
-use crate::models::Template;
+use crate::error::Error;
+use crate::models::{
+    DeadLetterEntry, DeadLetterReason, Job, JobPartition, JobRun, Template, UpstreamFailurePolicy,
+    Worker,
+};
+use crate::retry::RetryPolicy;
+use crate::workflow::{PlannedJob, WorkflowDefinition, WorkflowRun, WorkflowStepJob};
 use crate::{JobStatus, JobType};
 use anyhow::Result;
 use chrono::{DateTime, Utc};
 use serde_json::Value;
-use sqlx::postgres::{PgPool, PgRow};
-use sqlx::{Column, Row};
-use std::collections::HashMap;
+use sqlx::postgres::{PgPool, Postgres};
+use sqlx::{QueryBuilder, Row, Transaction};
+use std::time::Duration;
 use uuid::Uuid;
 
 #[derive(Debug, Clone)]
@@ -29,6 +35,303 @@ pub struct JobData {
     pub max_attempts: i32,
     pub metadata: Option<Value>,
     pub active: bool,
+    pub merchant_id: Option<Uuid>,
+    /// Client-supplied idempotency key, unique per merchant.
+    pub reference_id: Option<String>,
+    pub dependencies: JobDependencies,
+    pub retry_policy: RetryPolicy,
+}
+
+/// Upstream jobs a new job waits on, and what it does if one of them fails.
+#[derive(Debug, Clone, Default)]
+pub struct JobDependencies {
+    pub depends_on: Vec<Uuid>,
+    pub on_upstream_failure: UpstreamFailurePolicy,
+}
+
+/// How often a new job may be retried and how long it waits in between.
+#[derive(Debug, Clone)]
+pub struct JobRetry {
+    pub max_retries: i32,
+    pub policy: RetryPolicy,
+}
+
+impl Default for JobRetry {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            policy: RetryPolicy::default(),
+        }
+    }
+}
+
+/// Who submitted a new job, and the idempotency key it was submitted with.
+#[derive(Debug, Clone, Default)]
+pub struct JobOrigin {
+    pub merchant_id: Option<Uuid>,
+    pub reference_id: Option<String>,
+}
+
+/// The row a create call resolved to. `replayed` is set when the idempotency
+/// key matched an earlier request and nothing new was inserted.
+#[derive(Debug, Clone, Copy)]
+pub struct CreatedJob {
+    pub id: Uuid,
+    pub replayed: bool,
+}
+
+/// What an executor registers itself with in `workers`.
+#[derive(Debug, Clone)]
+pub struct WorkerData {
+    pub id: String,
+    pub hostname: String,
+    pub version: String,
+    /// Job kinds the executor can run.
+    pub capabilities: Vec<String>,
+    pub concurrency_limit: i32,
+}
+
+/// What `Database::heartbeat` learned about the worker.
+#[derive(Debug, Clone, Default)]
+pub struct Heartbeat {
+    /// Jobs whose lease could not be renewed because they were taken away
+    /// from the worker.
+    pub lost: Vec<Uuid>,
+    /// Set when an operator asked the worker to stop taking new jobs.
+    pub draining: bool,
+}
+
+/// Captured stdout/stderr beyond this many bytes is cut off in `job_runs`.
+pub const MAX_RUN_OUTPUT_BYTES: usize = 64 * 1024;
+
+/// How an attempt ended, recorded by `Database::finish_job_run`.
+#[derive(Debug, Default)]
+pub struct JobRunOutcome {
+    pub exit_code: Option<i32>,
+    pub stdout: Option<String>,
+    pub stderr: Option<String>,
+    pub error_class: Option<String>,
+    pub error_message: Option<String>,
+    pub peak_memory_bytes: Option<i64>,
+    pub cpu_time_ms: Option<i64>,
+    pub http_status: Option<i32>,
+    pub response_body: Option<String>,
+    pub result: Option<Value>,
+    pub script_hash: Option<String>,
+}
+
+/// A typed set of column assignments for `Database::update_job`.
+///
+/// Each method maps to a real `jobs` column, so an update can only ever
+/// reference columns that exist and binds values with their SQL types.
+#[derive(Debug, Clone, Default)]
+pub struct JobUpdate {
+    assignments: Vec<Assignment>,
+    fencing_token: Option<i64>,
+}
+
+#[derive(Debug, Clone)]
+enum Assignment {
+    Description(Option<String>),
+    ParentJobId(Option<Uuid>),
+    ReferenceId(Option<String>),
+    Status(JobStatus),
+    Priority(i32),
+    MaxRetries(i32),
+    Retries(i32),
+    IncrementRetries,
+    IncrementBounces,
+    LastError(Option<String>),
+    LastErrorClass(Option<String>),
+    Payload(Value),
+    NextRunAt(Option<DateTime<Utc>>),
+    LastRunAt(Option<DateTime<Utc>>),
+    CompletedAt(Option<DateTime<Utc>>),
+    ScheduledAt(DateTime<Utc>),
+    Metadata(Option<Value>),
+    MergeMetadata(Value),
+    TemplateId(Option<Uuid>),
+    MerchantId(Option<Uuid>),
+    ClaimedBy(Option<String>),
+    ClaimedAt(Option<DateTime<Utc>>),
+    LeaseOwner(Option<String>),
+    LeaseExpiresAt(Option<DateTime<Utc>>),
+    IssueFencingToken,
+}
+
+impl JobUpdate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.assignments.is_empty()
+    }
+
+    fn set(mut self, assignment: Assignment) -> Self {
+        self.assignments.push(assignment);
+        self
+    }
+
+    pub fn description(self, description: Option<String>) -> Self {
+        self.set(Assignment::Description(description))
+    }
+
+    pub fn parent_job_id(self, parent_job_id: Option<Uuid>) -> Self {
+        self.set(Assignment::ParentJobId(parent_job_id))
+    }
+
+    pub fn reference_id(self, reference_id: Option<String>) -> Self {
+        self.set(Assignment::ReferenceId(reference_id))
+    }
+
+    /// Status only changes through [`Database::transition_job`].
+    fn status(self, status: JobStatus) -> Self {
+        self.set(Assignment::Status(status))
+    }
+
+    pub fn priority(self, priority: i32) -> Self {
+        self.set(Assignment::Priority(priority))
+    }
+
+    pub fn max_retries(self, max_retries: i32) -> Self {
+        self.set(Assignment::MaxRetries(max_retries))
+    }
+
+    pub fn retries(self, retries: i32) -> Self {
+        self.set(Assignment::Retries(retries))
+    }
+
+    /// Sets `retries = retries + 1` relative to the stored value.
+    pub fn increment_retries(self) -> Self {
+        self.set(Assignment::IncrementRetries)
+    }
+
+    /// Sets `bounces = bounces + 1` relative to the stored value.
+    pub fn increment_bounces(self) -> Self {
+        self.set(Assignment::IncrementBounces)
+    }
+
+    pub fn last_error(self, last_error: Option<String>) -> Self {
+        self.set(Assignment::LastError(last_error))
+    }
+
+    pub fn last_error_class(self, last_error_class: Option<String>) -> Self {
+        self.set(Assignment::LastErrorClass(last_error_class))
+    }
+
+    pub fn payload(self, payload: Value) -> Self {
+        self.set(Assignment::Payload(payload))
+    }
+
+    pub fn next_run_at(self, next_run_at: Option<DateTime<Utc>>) -> Self {
+        self.set(Assignment::NextRunAt(next_run_at))
+    }
+
+    pub fn last_run_at(self, last_run_at: Option<DateTime<Utc>>) -> Self {
+        self.set(Assignment::LastRunAt(last_run_at))
+    }
+
+    pub fn completed_at(self, completed_at: Option<DateTime<Utc>>) -> Self {
+        self.set(Assignment::CompletedAt(completed_at))
+    }
+
+    pub fn scheduled_at(self, scheduled_at: DateTime<Utc>) -> Self {
+        self.set(Assignment::ScheduledAt(scheduled_at))
+    }
+
+    pub fn metadata(self, metadata: Option<Value>) -> Self {
+        self.set(Assignment::Metadata(metadata))
+    }
+
+    /// Shallow-merges `metadata` into the stored object, keeping other keys.
+    pub fn merge_metadata(self, metadata: Value) -> Self {
+        self.set(Assignment::MergeMetadata(metadata))
+    }
+
+    pub fn template_id(self, template_id: Option<Uuid>) -> Self {
+        self.set(Assignment::TemplateId(template_id))
+    }
+
+    pub fn merchant_id(self, merchant_id: Option<Uuid>) -> Self {
+        self.set(Assignment::MerchantId(merchant_id))
+    }
+
+    pub fn claimed_by(self, claimed_by: Option<String>) -> Self {
+        self.set(Assignment::ClaimedBy(claimed_by))
+    }
+
+    pub fn claimed_at(self, claimed_at: Option<DateTime<Utc>>) -> Self {
+        self.set(Assignment::ClaimedAt(claimed_at))
+    }
+
+    pub fn lease_owner(self, lease_owner: Option<String>) -> Self {
+        self.set(Assignment::LeaseOwner(lease_owner))
+    }
+
+    pub fn lease_expires_at(self, lease_expires_at: Option<DateTime<Utc>>) -> Self {
+        self.set(Assignment::LeaseExpiresAt(lease_expires_at))
+    }
+
+    /// Claims the job anew, invalidating the fencing token of any earlier
+    /// claim. The new token is the current one plus one.
+    pub fn issue_fencing_token(self) -> Self {
+        self.set(Assignment::IssueFencingToken)
+    }
+
+    /// Only applies the update while the job still carries `token`, so a
+    /// worker whose claim was superseded cannot overwrite the newer attempt.
+    pub fn fenced_by(mut self, token: i64) -> Self {
+        self.fencing_token = Some(token);
+        self
+    }
+
+    /// Appends the comma separated `SET` list, always bumping `updated_at`.
+    fn push_assignments(&self, builder: &mut QueryBuilder<'_, Postgres>) {
+        let mut set = builder.separated(", ");
+        for assignment in &self.assignments {
+            match assignment.clone() {
+                Assignment::Description(v) => set.push("description = ").push_bind_unseparated(v),
+                Assignment::ParentJobId(v) => set.push("parent_job_id = ").push_bind_unseparated(v),
+                Assignment::ReferenceId(v) => set.push("reference_id = ").push_bind_unseparated(v),
+                Assignment::Status(v) => set.push("status = ").push_bind_unseparated(v),
+                Assignment::Priority(v) => set.push("priority = ").push_bind_unseparated(v),
+                Assignment::MaxRetries(v) => set.push("max_retries = ").push_bind_unseparated(v),
+                Assignment::Retries(v) => set.push("retries = ").push_bind_unseparated(v),
+                Assignment::IncrementRetries => set.push("retries = retries + 1"),
+                Assignment::IncrementBounces => set.push("bounces = bounces + 1"),
+                Assignment::LastError(v) => set.push("last_error = ").push_bind_unseparated(v),
+                Assignment::LastErrorClass(v) => {
+                    set.push("last_error_class = ").push_bind_unseparated(v)
+                }
+                Assignment::Payload(v) => set.push("payload = ").push_bind_unseparated(v),
+                Assignment::NextRunAt(v) => set.push("next_run_at = ").push_bind_unseparated(v),
+                Assignment::LastRunAt(v) => set.push("last_run_at = ").push_bind_unseparated(v),
+                Assignment::CompletedAt(v) => set.push("completed_at = ").push_bind_unseparated(v),
+                Assignment::ScheduledAt(v) => set.push("scheduled_at = ").push_bind_unseparated(v),
+                Assignment::Metadata(v) => set.push("metadata = ").push_bind_unseparated(v),
+                Assignment::MergeMetadata(v) => set
+                    .push("metadata = COALESCE(metadata, '{}'::jsonb) || ")
+                    .push_bind_unseparated(v),
+                Assignment::TemplateId(v) => set.push("template_id = ").push_bind_unseparated(v),
+                Assignment::MerchantId(v) => set.push("merchant_id = ").push_bind_unseparated(v),
+                Assignment::ClaimedBy(v) => set.push("claimed_by = ").push_bind_unseparated(v),
+                Assignment::ClaimedAt(v) => set.push("claimed_at = ").push_bind_unseparated(v),
+                Assignment::LeaseOwner(v) => set.push("lease_owner = ").push_bind_unseparated(v),
+                Assignment::LeaseExpiresAt(v) => {
+                    set.push("lease_expires_at = ").push_bind_unseparated(v)
+                }
+                Assignment::IssueFencingToken => set.push("fencing_token = fencing_token + 1"),
+            };
+        }
+        set.push("updated_at = NOW()");
+    }
+
+    fn push_fence(&self, builder: &mut QueryBuilder<'_, Postgres>) {
+        if let Some(token) = self.fencing_token {
+            builder.push(" AND fencing_token = ").push_bind(token);
+        }
+    }
 }
 
 impl Database {
@@ -37,39 +340,58 @@ impl Database {
         Ok(Self { pool })
     }
 
-    pub async fn create_job(&self, job_data: JobData) -> Result<String> {
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    pub async fn create_job(&self, job_data: JobData) -> Result<CreatedJob> {
         let id = Uuid::new_v4();
-        let query = r#"
-            INSERT INTO jobs (status, priority, scheduled_at, parent_job_id, max_retries, retries, payload, id, created_at, updated_at)
-                    VALUES ($1, $2, $3::timestamp with time zone, $4, $5, $6, $7, $8, NOW(), NOW())
-            RETURNING id
-        "#;
+        let mut tx = self.pool.begin().await?;
 
-        let result = sqlx::query(query)
-            .bind(job_data.status)
-            .bind(job_data.priority)
-            .bind(job_data.schedule_at.unwrap())
-            .bind(job_data.parent_job_id)
-            .bind(job_data.max_retries)
-            .bind(job_data.retries)
-            .bind(job_data.payload)
-            .bind(&id)
-            .fetch_one(&self.pool)
-            .await?
-            .get::<Uuid, _>("id");
+        if let Some(key) = &job_data.reference_id
+            && let Some(existing) =
+                claim_idempotency_key(&mut tx, job_data.merchant_id, key, id).await?
+        {
+            return Ok(CreatedJob {
+                id: existing,
+                replayed: true,
+            });
+        }
+
+        insert_job(&mut tx, id, job_data).await?;
 
-        Ok(result.to_string())
+        tx.commit().await?;
+        Ok(CreatedJob {
+            id,
+            replayed: false,
+        })
     }
 
-    pub async fn create_template(&self, job_data: JobData, job_type: JobType) -> Result<String> {
+    pub async fn create_template(
+        &self,
+        job_data: JobData,
+        job_type: JobType,
+    ) -> Result<CreatedJob> {
         let id = Uuid::new_v4();
+        let mut tx = self.pool.begin().await?;
+
+        if let Some(key) = &job_data.reference_id
+            && let Some(existing) =
+                claim_idempotency_key(&mut tx, job_data.merchant_id, key, id).await?
+        {
+            return Ok(CreatedJob {
+                id: existing,
+                replayed: true,
+            });
+        }
+
         let query = r#"
-            INSERT INTO templates (id, name, description, job_type, priority, max_retries, interval, cron, schedule_at, max_attempts, payload, active, created_at, updated_at)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, NOW(), NOW())
+            INSERT INTO templates (id, name, description, job_type, priority, max_retries, interval, cron, schedule_at, max_attempts, payload, active, retry_policy, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, NOW(), NOW())
             RETURNING id
         "#;
         let result = sqlx::query(query)
-            .bind(&id)
+            .bind(id)
             .bind(job_data.name)
             .bind(job_data.description)
             .bind(job_type)
@@ -77,70 +399,106 @@ impl Database {
             .bind(job_data.max_retries)
             .bind(job_data.interval.unwrap_or(0) as i32)
             .bind(job_data.cron)
-            .bind(job_data.schedule_at.unwrap())
+            .bind(job_data.schedule_at.unwrap_or_else(Utc::now))
             .bind(job_data.max_attempts)
             .bind(job_data.payload)
             .bind(true)
-            .fetch_one(&self.pool)
+            .bind(sqlx::types::Json(&job_data.retry_policy))
+            .fetch_one(&mut *tx)
             .await?
             .get::<Uuid, _>("id");
 
-        Ok(result.to_string())
+        tx.commit().await?;
+        Ok(CreatedJob {
+            id: result,
+            replayed: false,
+        })
     }
 
-    pub async fn get_job(&self, id: &str) -> Result<Option<HashMap<String, String>>> {
-        let uuid =
-            Uuid::parse_str(id).map_err(|e| anyhow::anyhow!("Invalid UUID format: {}", e))?;
-        let row = sqlx::query("SELECT * FROM jobs WHERE id = $1")
-            .bind(uuid)
+    pub async fn get_job(&self, id: &str) -> Result<Option<Job>> {
+        let job = sqlx::query_as::<_, Job>("SELECT * FROM jobs WHERE id = $1")
+            .bind(parse_job_id(id)?)
             .fetch_optional(&self.pool)
             .await?;
 
-        Ok(row.map(|r| row_to_hashmap(&r)))
+        Ok(job)
     }
 
-    pub async fn update_job(&self, id: &str, updates: &HashMap<&str, String>) -> Result<bool> {
-        let set_clauses = updates
-            .iter()
-            .enumerate()
-            .map(|(i, (k, _))| {
-                if *k == "status" {
-                    format!("{} = ${}::job_status", k, i + 2)
-                } else {
-                    format!("{} = ${}", k, i + 2)
-                }
-            })
-            .collect::<Vec<_>>()
-            .join(", ");
-
-        let query = format!(
-            "UPDATE jobs SET {}, updated_at = NOW() WHERE id = $1",
-            set_clauses
-        );
-
-        let mut query_builder = sqlx::query(&query).bind(id);
-        for value in updates.values() {
-            query_builder = query_builder.bind(value);
-        }
+    pub async fn update_job(&self, id: &str, update: &JobUpdate) -> Result<bool> {
+        let mut builder = QueryBuilder::<Postgres>::new("UPDATE jobs SET ");
+        update.push_assignments(&mut builder);
+        builder.push(" WHERE id = ").push_bind(parse_job_id(id)?);
+        update.push_fence(&mut builder);
 
-        let result = query_builder.execute(&self.pool).await?;
+        let result = builder.build().execute(&self.pool).await?;
         Ok(result.rows_affected() > 0)
     }
 
+    /// Moves a job from `from` to `to` with a compare-and-set on the current
+    /// status, applying `update` in the same statement. Fails with
+    /// [`Error::InvalidTransition`] if the state machine forbids the move,
+    /// with [`Error::TransitionConflict`] if another writer got there first
+    /// and with [`Error::StaleFencingToken`] if the update is fenced by a
+    /// token that a newer claim has replaced.
+    pub async fn transition_job(
+        &self,
+        id: &str,
+        from: JobStatus,
+        to: JobStatus,
+        update: &JobUpdate,
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        transition_job_in(&mut tx, id, from, to, update).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Counts a failed attempt and puts the job back to `Pending`, due at
+    /// `run_at`, in one transaction. The populator picks it up once it is due.
+    /// Fenced by `fencing_token`, the token of the attempt that failed.
+    pub async fn schedule_retry(
+        &self,
+        id: &str,
+        fencing_token: i64,
+        run_at: DateTime<Utc>,
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        transition_job_in(
+            &mut tx,
+            id,
+            JobStatus::Failed,
+            JobStatus::Retrying,
+            &JobUpdate::new()
+                .increment_retries()
+                .fenced_by(fencing_token),
+        )
+        .await?;
+        transition_job_in(
+            &mut tx,
+            id,
+            JobStatus::Retrying,
+            JobStatus::Pending,
+            &JobUpdate::new()
+                .scheduled_at(run_at)
+                .next_run_at(Some(run_at))
+                .claimed_by(None)
+                .claimed_at(None),
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
     pub async fn delete_job(&self, id: &str) -> Result<bool> {
         let result = sqlx::query("DELETE FROM jobs WHERE id = $1")
-            .bind(id)
+            .bind(parse_job_id(id)?)
             .execute(&self.pool)
             .await?;
 
         Ok(result.rows_affected() > 0)
     }
 
-    pub async fn get_due_jobs(
-        &self,
-        limit: i64,
-        _job_types: &[&str],
-    ) -> Result<Vec<HashMap<String, String>>> {
+    pub async fn get_due_jobs(&self, limit: i64, _job_types: &[&str]) -> Result<Vec<Job>> {
         let query = r#"
             SELECT * FROM jobs 
             WHERE status::job_status = 'pending'::job_status 
@@ -149,62 +507,996 @@ impl Database {
             LIMIT $1
         "#;
 
-        let rows = sqlx::query(query).bind(limit).fetch_all(&self.pool).await?;
+        let jobs = sqlx::query_as::<_, Job>(query)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(jobs)
+    }
+
+    /// Atomically claims up to `limit` due jobs for `claimant`, moving them from
+    /// `pending` to `queued`. Rows locked by a concurrent claimer are skipped,
+    /// so several populators never hand out the same job twice.
+    pub async fn claim_due_jobs(&self, limit: i64, claimant: &str) -> Result<Vec<Job>> {
+        let query = r#"
+            WITH due AS (
+                SELECT id, created_at FROM jobs
+                WHERE status = 'pending'::job_status
+                AND scheduled_at <= NOW()
+                AND NOT EXISTS (
+                    SELECT 1 FROM workflow_steps ws
+                    WHERE ws.job_id = jobs.id AND ws.awaiting_condition
+                )
+                AND NOT EXISTS (
+                    SELECT 1 FROM job_dependencies d
+                    LEFT JOIN jobs upstream ON upstream.id = d.depends_on
+                    WHERE d.job_id = jobs.id
+                    AND upstream.status IS DISTINCT FROM 'completed'::job_status
+                    AND NOT (
+                        jobs.on_upstream_failure = 'run_anyway'::upstream_failure_policy
+                        AND (
+                            upstream.id IS NULL
+                            OR upstream.status IN ('cancelled'::job_status, 'dead_lettered'::job_status)
+                        )
+                    )
+                )
+                ORDER BY priority DESC, scheduled_at ASC
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            UPDATE jobs
+            SET status = 'queued'::job_status, claimed_by = $2, claimed_at = NOW(),
+                fencing_token = jobs.fencing_token + 1, updated_at = NOW()
+            FROM due
+            WHERE jobs.id = due.id AND jobs.created_at = due.created_at
+            RETURNING jobs.*
+        "#;
+
+        let mut tx = self.pool.begin().await?;
+        let jobs = sqlx::query_as::<_, Job>(query)
+            .bind(limit)
+            .bind(claimant)
+            .fetch_all(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(jobs)
+    }
+
+    /// Pending jobs with the cascade-cancel policy whose upstream job was
+    /// cancelled, dead-lettered or is gone, so they can never become runnable.
+    pub async fn get_jobs_with_failed_upstream(&self, limit: i64) -> Result<Vec<Job>> {
+        let query = r#"
+            SELECT * FROM jobs
+            WHERE status = 'pending'::job_status
+            AND on_upstream_failure = 'cascade_cancel'::upstream_failure_policy
+            AND EXISTS (
+                SELECT 1 FROM job_dependencies d
+                LEFT JOIN jobs upstream ON upstream.id = d.depends_on
+                WHERE d.job_id = jobs.id
+                AND (
+                    upstream.id IS NULL
+                    OR upstream.status IN ('cancelled'::job_status, 'dead_lettered'::job_status)
+                )
+            )
+            LIMIT $1
+        "#;
+
+        let jobs = sqlx::query_as::<_, Job>(query)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?;
 
-        Ok(rows.iter().map(row_to_hashmap).collect())
+        Ok(jobs)
     }
 
-    pub async fn get_jobs_by_status(&self, status: &str) -> Result<Vec<HashMap<String, String>>> {
+    pub async fn get_jobs_by_status(&self, status: JobStatus) -> Result<Vec<Job>> {
         let query = r#"
             SELECT * FROM jobs 
-            WHERE status::job_status = $1::job_status
+            WHERE status = $1
             ORDER BY priority DESC, scheduled_at ASC
         "#;
 
-        let rows = sqlx::query(query)
+        let jobs = sqlx::query_as::<_, Job>(query)
             .bind(status)
             .fetch_all(&self.pool)
             .await?;
 
-        Ok(rows.iter().map(row_to_hashmap).collect())
+        Ok(jobs)
     }
 
-    pub async fn get_jobs_older_than(
-        &self,
-        cutoff_time: &str,
-    ) -> Result<Vec<HashMap<String, String>>> {
+    /// Moves up to `limit` terminal jobs that finished before `cutoff` into
+    /// `jobs_archive`, in one statement so a job is never in both tables or
+    /// neither. Jobs that a live dependency or a running workflow still reads,
+    /// and jobs waiting in the dead letter queue, stay put. Returns how many
+    /// jobs were archived.
+    pub async fn archive_jobs(&self, cutoff: DateTime<Utc>, limit: i64) -> Result<u64> {
+        // Rows go through jsonb so columns are matched by name, not position.
         let query = r#"
-            SELECT * FROM jobs 
-            WHERE created_at < $1::timestamp with time zone
-            ORDER BY created_at ASC
+            WITH batch AS (
+                SELECT id, created_at FROM jobs
+                WHERE status IN ('completed'::job_status, 'cancelled'::job_status, 'dead_lettered'::job_status)
+                AND COALESCE(completed_at, updated_at) < $1
+                AND NOT EXISTS (
+                    SELECT 1 FROM job_dependencies d
+                    JOIN jobs downstream ON downstream.id = d.job_id
+                    WHERE d.depends_on = jobs.id
+                    AND downstream.status NOT IN ('completed'::job_status, 'cancelled'::job_status, 'dead_lettered'::job_status)
+                )
+                AND NOT EXISTS (
+                    SELECT 1 FROM dead_letter_entries e WHERE e.job_id = jobs.id
+                )
+                AND NOT EXISTS (
+                    SELECT 1 FROM workflow_steps ws
+                    JOIN workflow_runs r ON r.id = ws.workflow_run_id
+                    WHERE ws.job_id = jobs.id
+                    AND r.status = 'running'::workflow_status
+                )
+                ORDER BY created_at ASC
+                LIMIT $2
+                FOR UPDATE SKIP LOCKED
+            ),
+            moved AS (
+                DELETE FROM jobs
+                USING batch
+                WHERE jobs.id = batch.id AND jobs.created_at = batch.created_at
+                RETURNING jobs.*
+            )
+            INSERT INTO jobs_archive
+            SELECT (jsonb_populate_record(
+                NULL::jobs_archive,
+                to_jsonb(moved) || jsonb_build_object('archived_at', NOW())
+            )).*
+            FROM moved
         "#;
 
-        let rows = sqlx::query(query)
-            .bind(cutoff_time)
-            .fetch_all(&self.pool)
+        let mut tx = self.pool.begin().await?;
+        let archived = sqlx::query(query)
+            .bind(cutoff)
+            .bind(limit)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        tx.commit().await?;
+
+        Ok(archived)
+    }
+
+    /// Deletes up to `limit` archived jobs archived before `cutoff`.
+    pub async fn purge_archived_jobs(&self, cutoff: DateTime<Utc>, limit: i64) -> Result<u64> {
+        let query = r#"
+            DELETE FROM jobs_archive
+            WHERE id IN (
+                SELECT id FROM jobs_archive
+                WHERE archived_at < $1
+                ORDER BY archived_at ASC
+                LIMIT $2
+            )
+        "#;
+        let result = sqlx::query(query)
+            .bind(cutoff)
+            .bind(limit)
+            .execute(&self.pool)
             .await?;
 
-        Ok(rows.iter().map(row_to_hashmap).collect())
+        Ok(result.rows_affected())
+    }
+
+    pub async fn get_archived_job(&self, id: &str) -> Result<Option<Job>> {
+        let job = sqlx::query_as::<_, Job>("SELECT * FROM jobs_archive WHERE id = $1")
+            .bind(parse_job_id(id)?)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(job)
     }
 
     pub async fn get_jobs_by_status_and_time(
         &self,
-        status: &str,
-        cutoff_time: &str,
-    ) -> Result<Vec<HashMap<String, String>>> {
+        status: JobStatus,
+        cutoff_time: DateTime<Utc>,
+    ) -> Result<Vec<Job>> {
         let query = r#"
             SELECT * FROM jobs 
-            WHERE status::job_status = $1::job_status AND created_at < $2
+            WHERE status = $1 AND created_at < $2
             ORDER BY created_at ASC
         "#;
 
-        let rows = sqlx::query(query)
+        let jobs = sqlx::query_as::<_, Job>(query)
             .bind(status)
             .bind(cutoff_time)
             .fetch_all(&self.pool)
             .await?;
 
-        Ok(rows.iter().map(row_to_hashmap).collect())
+        Ok(jobs)
+    }
+
+    /// Opens the run record for an attempt and returns its id.
+    pub async fn start_job_run(&self, job_id: &str, attempt: i32, worker_id: &str) -> Result<Uuid> {
+        let query = r#"
+            INSERT INTO job_runs (job_id, attempt, worker_id, started_at)
+            VALUES ($1, $2, $3, NOW())
+            RETURNING id
+        "#;
+        let id = sqlx::query_scalar::<_, Uuid>(query)
+            .bind(parse_job_id(job_id)?)
+            .bind(attempt)
+            .bind(worker_id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(id)
+    }
+
+    pub async fn finish_job_run(&self, run_id: Uuid, outcome: JobRunOutcome) -> Result<()> {
+        let query = r#"
+            UPDATE job_runs
+            SET finished_at = NOW(),
+                duration_ms = (EXTRACT(EPOCH FROM (NOW() - started_at)) * 1000)::BIGINT,
+                exit_code = $2,
+                stdout = $3,
+                stderr = $4,
+                error_class = $5,
+                error_message = $6,
+                peak_memory_bytes = $7,
+                cpu_time_ms = $8,
+                http_status = $9,
+                response_body = $10,
+                result = $11,
+                script_hash = $12
+            WHERE id = $1
+        "#;
+        sqlx::query(query)
+            .bind(run_id)
+            .bind(outcome.exit_code)
+            .bind(outcome.stdout.map(truncate_output))
+            .bind(outcome.stderr.map(truncate_output))
+            .bind(outcome.error_class)
+            .bind(outcome.error_message)
+            .bind(outcome.peak_memory_bytes)
+            .bind(outcome.cpu_time_ms)
+            .bind(outcome.http_status)
+            .bind(outcome.response_body.map(truncate_output))
+            .bind(outcome.result)
+            .bind(outcome.script_hash)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Records the progress a running attempt last reported.
+    pub async fn update_job_run_progress(&self, run_id: Uuid, progress: &Value) -> Result<()> {
+        sqlx::query("UPDATE job_runs SET progress = $2 WHERE id = $1")
+            .bind(run_id)
+            .bind(progress)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_job_runs(&self, job_id: &str) -> Result<Vec<JobRun>> {
+        let query = r#"
+            SELECT * FROM job_runs
+            WHERE job_id = $1
+            ORDER BY started_at ASC
+        "#;
+        let runs = sqlx::query_as::<_, JobRun>(query)
+            .bind(parse_job_id(job_id)?)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(runs)
+    }
+
+    /// Adds an executor to `workers`, or refreshes it after a restart.
+    pub async fn register_worker(&self, worker: &WorkerData, ttl: Duration) -> Result<()> {
+        let query = r#"
+            INSERT INTO workers (id, hostname, version, capabilities, concurrency_limit,
+                                 started_at, last_heartbeat_at, lease_expires_at)
+            VALUES ($1, $2, $3, $4, $5, NOW(), NOW(), NOW() + make_interval(secs => $6))
+            ON CONFLICT (id) DO UPDATE
+            SET hostname = EXCLUDED.hostname,
+                version = EXCLUDED.version,
+                capabilities = EXCLUDED.capabilities,
+                concurrency_limit = EXCLUDED.concurrency_limit,
+                last_heartbeat_at = EXCLUDED.last_heartbeat_at,
+                lease_expires_at = EXCLUDED.lease_expires_at
+        "#;
+        sqlx::query(query)
+            .bind(&worker.id)
+            .bind(&worker.hostname)
+            .bind(&worker.version)
+            .bind(&worker.capabilities)
+            .bind(worker.concurrency_limit)
+            .bind(ttl.as_secs_f64())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Removes an executor that is shutting down.
+    pub async fn deregister_worker(&self, id: &str) -> Result<()> {
+        sqlx::query("DELETE FROM workers WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Renews one running job's lease ahead of the worker's next heartbeat.
+    /// Returns `false` when `worker_id` no longer holds the job under
+    /// `fencing_token`.
+    pub async fn renew_job_lease(
+        &self,
+        job_id: &str,
+        worker_id: &str,
+        fencing_token: i64,
+        ttl: Duration,
+    ) -> Result<bool> {
+        let query = r#"
+            UPDATE jobs
+            SET lease_expires_at = NOW() + make_interval(secs => $4)
+            WHERE id = $1
+            AND lease_owner = $2
+            AND fencing_token = $3
+            AND status = 'running'::job_status
+        "#;
+        let result = sqlx::query(query)
+            .bind(parse_job_id(job_id)?)
+            .bind(worker_id)
+            .bind(fencing_token)
+            .bind(ttl.as_secs_f64())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Renews the worker's lease and the leases of the running jobs it still
+    /// owns by `ttl`, and records `running_jobs` as its current load. Each
+    /// job comes with the fencing token of the worker's claim, so a lease is
+    /// only renewed for the attempt that holds it, as in `renew_job_lease`.
+    pub async fn heartbeat(
+        &self,
+        worker_id: &str,
+        leases: &[(Uuid, i64)],
+        running_jobs: i32,
+        ttl: Duration,
+    ) -> Result<Heartbeat> {
+        let mut tx = self.pool.begin().await?;
+        let draining = sqlx::query_scalar::<_, bool>(
+            r#"
+            UPDATE workers
+            SET last_heartbeat_at = NOW(),
+                lease_expires_at = NOW() + make_interval(secs => $2),
+                running_jobs = $3
+            WHERE id = $1
+            RETURNING draining
+            "#,
+        )
+        .bind(worker_id)
+        .bind(ttl.as_secs_f64())
+        .bind(running_jobs)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let renewed = sqlx::query_scalar::<_, Uuid>(
+            r#"
+            UPDATE jobs
+            SET lease_expires_at = NOW() + make_interval(secs => $4)
+            FROM UNNEST($2::uuid[], $3::bigint[]) AS lease(id, fencing_token)
+            WHERE jobs.id = lease.id
+            AND jobs.fencing_token = lease.fencing_token
+            AND jobs.lease_owner = $1
+            AND jobs.status = 'running'::job_status
+            RETURNING jobs.id
+            "#,
+        )
+        .bind(worker_id)
+        .bind(leases.iter().map(|(id, _)| *id).collect::<Vec<_>>())
+        .bind(leases.iter().map(|(_, token)| *token).collect::<Vec<_>>())
+        .bind(ttl.as_secs_f64())
+        .fetch_all(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(Heartbeat {
+            lost: leases
+                .iter()
+                .map(|(id, _)| *id)
+                .filter(|id| !renewed.contains(id))
+                .collect(),
+            draining: draining.unwrap_or(false),
+        })
+    }
+
+    pub async fn get_workers(&self) -> Result<Vec<Worker>> {
+        let workers = sqlx::query_as::<_, Worker>("SELECT * FROM workers ORDER BY started_at ASC")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(workers)
+    }
+
+    pub async fn get_worker(&self, id: &str) -> Result<Option<Worker>> {
+        let worker = sqlx::query_as::<_, Worker>("SELECT * FROM workers WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(worker)
+    }
+
+    /// Whether a live worker other than `except`, which is not draining,
+    /// advertises `capability`.
+    pub async fn has_capable_worker(&self, capability: &str, except: &str) -> Result<bool> {
+        let query = r#"
+            SELECT EXISTS (
+                SELECT 1 FROM workers
+                WHERE $1 = ANY(capabilities)
+                AND id <> $2
+                AND NOT draining
+                AND lease_expires_at > NOW()
+            )
+        "#;
+        let capable = sqlx::query_scalar(query)
+            .bind(capability)
+            .bind(except)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(capable)
+    }
+
+    /// Running jobs leased by any of `worker_ids`.
+    pub async fn get_leased_jobs(&self, worker_ids: &[String]) -> Result<Vec<Job>> {
+        let query = r#"
+            SELECT * FROM jobs
+            WHERE status = 'running'::job_status
+            AND lease_owner = ANY($1)
+            ORDER BY last_run_at ASC
+        "#;
+        let jobs = sqlx::query_as::<_, Job>(query)
+            .bind(worker_ids)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(jobs)
+    }
+
+    /// Asks a worker to stop taking new jobs; it learns about it with its
+    /// next heartbeat. Returns `false` if the worker is not registered.
+    pub async fn drain_worker(&self, id: &str) -> Result<bool> {
+        let result = sqlx::query("UPDATE workers SET draining = TRUE WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Forgets workers whose lease ran out before `cutoff`, i.e. executors
+    /// that died without deregistering.
+    pub async fn prune_workers(&self, cutoff: DateTime<Utc>) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM workers WHERE lease_expires_at < $1")
+            .bind(cutoff)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Running jobs whose lease ran out, oldest expiry first.
+    pub async fn get_expired_leases(&self, limit: i64) -> Result<Vec<Job>> {
+        let query = r#"
+            SELECT * FROM jobs
+            WHERE status = 'running'::job_status
+            AND lease_expires_at < NOW()
+            ORDER BY lease_expires_at ASC
+            LIMIT $1
+        "#;
+        let jobs = sqlx::query_as::<_, Job>(query)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(jobs)
+    }
+
+    /// Queued jobs claimed before `cutoff` that have not changed since,
+    /// oldest claim first. The requeues of the lease monitor and of a
+    /// stopping executor keep the original claim but bump `updated_at`.
+    pub async fn get_stale_claims(&self, cutoff: DateTime<Utc>, limit: i64) -> Result<Vec<Job>> {
+        let query = r#"
+            SELECT * FROM jobs
+            WHERE status = 'queued'::job_status
+            AND claimed_at < $1
+            AND updated_at < $1
+            ORDER BY claimed_at ASC
+            LIMIT $2
+        "#;
+        let jobs = sqlx::query_as::<_, Job>(query)
+            .bind(cutoff)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(jobs)
+    }
+
+    /// Closes the run records `worker_id` left open for a job it lost.
+    pub async fn abandon_job_runs(
+        &self,
+        job_id: &str,
+        worker_id: &str,
+        reason: &str,
+    ) -> Result<()> {
+        let query = r#"
+            UPDATE job_runs
+            SET finished_at = NOW(),
+                duration_ms = (EXTRACT(EPOCH FROM (NOW() - started_at)) * 1000)::BIGINT,
+                error_class = 'lease_expired',
+                error_message = $3
+            WHERE job_id = $1 AND worker_id = $2 AND finished_at IS NULL
+        "#;
+        sqlx::query(query)
+            .bind(parse_job_id(job_id)?)
+            .bind(worker_id)
+            .bind(reason)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Stores a workflow run and every job it expands into in one transaction.
+    pub async fn create_workflow_run(
+        &self,
+        definition: &WorkflowDefinition,
+        jobs: &[PlannedJob],
+    ) -> Result<Uuid> {
+        let run_id = Uuid::new_v4();
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO workflow_runs (id, name, merchant_id, input, definition)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+        )
+        .bind(run_id)
+        .bind(&definition.name)
+        .bind(definition.merchant_id)
+        .bind(&definition.input)
+        .bind(serde_json::to_value(definition)?)
+        .execute(&mut *tx)
+        .await?;
+
+        for job in jobs {
+            let job_data = JobData {
+                name: None,
+                status: JobStatus::Pending,
+                parent_job_id: None,
+                description: None,
+                priority: job.priority,
+                max_retries: job.max_retries,
+                retries: 0,
+                payload: job.payload.clone(),
+                interval: None,
+                cron: None,
+                schedule_at: None,
+                max_attempts: 1,
+                metadata: None,
+                active: true,
+                merchant_id: definition.merchant_id,
+                reference_id: None,
+                dependencies: JobDependencies {
+                    depends_on: job.depends_on.clone(),
+                    on_upstream_failure: job.on_upstream_failure,
+                },
+                retry_policy: job.retry_policy.clone(),
+            };
+            insert_job(&mut tx, job.job_id, job_data).await?;
+
+            sqlx::query(
+                r#"
+                INSERT INTO workflow_steps (workflow_run_id, step_name, job_id, fan_out_index, awaiting_condition)
+                VALUES ($1, $2, $3, $4, $5)
+                "#,
+            )
+            .bind(run_id)
+            .bind(&job.step_name)
+            .bind(job.job_id)
+            .bind(job.fan_out_index)
+            .bind(job.awaiting_condition)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(run_id)
+    }
+
+    pub async fn get_workflow_run(&self, id: Uuid) -> Result<Option<WorkflowRun>> {
+        let run = sqlx::query_as::<_, WorkflowRun>("SELECT * FROM workflow_runs WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(run)
+    }
+
+    pub async fn get_workflow_steps(&self, run_id: Uuid) -> Result<Vec<WorkflowStepJob>> {
+        let query = r#"
+            SELECT ws.*, j.status FROM workflow_steps ws
+            JOIN jobs j ON j.id = ws.job_id
+            WHERE ws.workflow_run_id = $1
+            ORDER BY j.created_at ASC, ws.fan_out_index ASC NULLS FIRST
+        "#;
+        let steps = sqlx::query_as::<_, WorkflowStepJob>(query)
+            .bind(run_id)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(steps)
+    }
+
+    /// Conditional steps still pending whose upstream jobs are all terminal.
+    pub async fn get_conditional_steps_ready(&self, limit: i64) -> Result<Vec<WorkflowStepJob>> {
+        let query = r#"
+            SELECT ws.*, j.status FROM workflow_steps ws
+            JOIN jobs j ON j.id = ws.job_id
+            WHERE ws.awaiting_condition
+            AND j.status = 'pending'::job_status
+            AND NOT EXISTS (
+                SELECT 1 FROM job_dependencies d
+                JOIN jobs upstream ON upstream.id = d.depends_on
+                WHERE d.job_id = j.id
+                AND upstream.status NOT IN ('completed'::job_status, 'cancelled'::job_status, 'dead_lettered'::job_status)
+            )
+            LIMIT $1
+        "#;
+        let steps = sqlx::query_as::<_, WorkflowStepJob>(query)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(steps)
+    }
+
+    /// Status, exit code and stdout of the latest attempt of a step's job.
+    pub async fn get_step_result(
+        &self,
+        run_id: Uuid,
+        step_name: &str,
+    ) -> Result<Option<(JobStatus, Option<i32>, Option<String>)>> {
+        let query = r#"
+            SELECT j.status, r.exit_code, r.stdout FROM workflow_steps ws
+            JOIN jobs j ON j.id = ws.job_id
+            LEFT JOIN LATERAL (
+                SELECT exit_code, stdout FROM job_runs
+                WHERE job_runs.job_id = j.id
+                ORDER BY started_at DESC
+                LIMIT 1
+            ) r ON true
+            WHERE ws.workflow_run_id = $1 AND ws.step_name = $2
+            LIMIT 1
+        "#;
+        let result = sqlx::query_as::<_, (JobStatus, Option<i32>, Option<String>)>(query)
+            .bind(run_id)
+            .bind(step_name)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(result)
+    }
+
+    pub async fn release_workflow_step(&self, job_id: Uuid) -> Result<()> {
+        sqlx::query("UPDATE workflow_steps SET awaiting_condition = false WHERE job_id = $1")
+            .bind(job_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn skip_workflow_step(&self, job_id: Uuid) -> Result<()> {
+        sqlx::query(
+            "UPDATE workflow_steps SET awaiting_condition = false, skipped = true WHERE job_id = $1",
+        )
+        .bind(job_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Settles running workflow runs whose jobs are all terminal: failed if any
+    /// job was dead-lettered, completed otherwise. Returns how many finished.
+    pub async fn finish_workflow_runs(&self) -> Result<u64> {
+        let query = r#"
+            UPDATE workflow_runs r
+            SET status = CASE
+                    WHEN EXISTS (
+                        SELECT 1 FROM workflow_steps ws
+                        JOIN jobs j ON j.id = ws.job_id
+                        WHERE ws.workflow_run_id = r.id
+                        AND j.status = 'dead_lettered'::job_status
+                    ) THEN 'failed'::workflow_status
+                    ELSE 'completed'::workflow_status
+                END,
+                completed_at = NOW(),
+                updated_at = NOW()
+            WHERE r.status = 'running'::workflow_status
+            AND NOT EXISTS (
+                SELECT 1 FROM workflow_steps ws
+                JOIN jobs j ON j.id = ws.job_id
+                WHERE ws.workflow_run_id = r.id
+                AND j.status NOT IN ('completed'::job_status, 'cancelled'::job_status, 'dead_lettered'::job_status)
+            )
+        "#;
+        let result = sqlx::query(query).execute(&self.pool).await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Moves a job from `from` to `DeadLettered` and records why, in one
+    /// transaction. A worker dead-letters straight from `Running`, passing
+    /// the fencing token of its claim in `update`, so it cannot dead-letter
+    /// a job that was reclaimed from it and the job is never seen `Failed`
+    /// with attempts left in between.
+    pub async fn dead_letter_job(
+        &self,
+        id: &str,
+        from: JobStatus,
+        reason: DeadLetterReason,
+        update: &JobUpdate,
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        transition_job_in(&mut tx, id, from, JobStatus::DeadLettered, update).await?;
+
+        let query = r#"
+            INSERT INTO dead_letter_entries (job_id, merchant_id, reason, last_error, attempts)
+            SELECT id, merchant_id, $2, last_error, retries FROM jobs WHERE id = $1
+        "#;
+        sqlx::query(query)
+            .bind(parse_job_id(id)?)
+            .bind(reason)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Lists dead letter entries, newest first, optionally for one merchant.
+    pub async fn get_dead_letters(
+        &self,
+        merchant_id: Option<Uuid>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<DeadLetterEntry>> {
+        let query = r#"
+            SELECT * FROM dead_letter_entries
+            WHERE ($1::uuid IS NULL OR merchant_id = $1)
+            ORDER BY dead_lettered_at DESC
+            LIMIT $2 OFFSET $3
+        "#;
+        let entries = sqlx::query_as::<_, DeadLetterEntry>(query)
+            .bind(merchant_id)
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(entries)
+    }
+
+    pub async fn get_dead_letter(
+        &self,
+        job_id: &str,
+        merchant_id: Option<Uuid>,
+    ) -> Result<Option<DeadLetterEntry>> {
+        let query = r#"
+            SELECT * FROM dead_letter_entries
+            WHERE job_id = $1 AND ($2::uuid IS NULL OR merchant_id = $2)
+        "#;
+        let entry = sqlx::query_as::<_, DeadLetterEntry>(query)
+            .bind(parse_job_id(job_id)?)
+            .bind(merchant_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(entry)
+    }
+
+    /// Sends a dead-lettered job back to `Pending` with a fresh retry budget,
+    /// optionally with a new payload, and removes its entry. Returns false if
+    /// there is no entry for the job.
+    pub async fn replay_dead_letter(
+        &self,
+        job_id: &str,
+        merchant_id: Option<Uuid>,
+        payload: Option<Value>,
+    ) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        let query = r#"
+            DELETE FROM dead_letter_entries
+            WHERE job_id = $1 AND ($2::uuid IS NULL OR merchant_id = $2)
+        "#;
+        let removed = sqlx::query(query)
+            .bind(parse_job_id(job_id)?)
+            .bind(merchant_id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        if removed == 0 {
+            return Ok(false);
+        }
+
+        let mut update = JobUpdate::new()
+            .retries(0)
+            .last_error(None)
+            .last_error_class(None)
+            .completed_at(None)
+            .scheduled_at(Utc::now())
+            .claimed_by(None)
+            .claimed_at(None);
+        if let Some(payload) = payload {
+            update = update.payload(payload);
+        }
+        transition_job_in(
+            &mut tx,
+            job_id,
+            JobStatus::DeadLettered,
+            JobStatus::Pending,
+            &update,
+        )
+        .await?;
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Deletes one dead letter entry, leaving the job itself dead-lettered.
+    pub async fn purge_dead_letter(&self, job_id: &str, merchant_id: Option<Uuid>) -> Result<bool> {
+        let query = r#"
+            DELETE FROM dead_letter_entries
+            WHERE job_id = $1 AND ($2::uuid IS NULL OR merchant_id = $2)
+        "#;
+        let result = sqlx::query(query)
+            .bind(parse_job_id(job_id)?)
+            .bind(merchant_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Deletes every dead letter entry, or every one for a merchant.
+    pub async fn purge_dead_letters(&self, merchant_id: Option<Uuid>) -> Result<u64> {
+        let query = r#"
+            DELETE FROM dead_letter_entries
+            WHERE ($1::uuid IS NULL OR merchant_id = $1)
+        "#;
+        let result = sqlx::query(query)
+            .bind(merchant_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Lists the partitions attached to `jobs` with their bounds and sizes.
+    pub async fn get_job_partitions(&self) -> Result<Vec<JobPartition>> {
+        let query = r#"
+            SELECT c.relname::TEXT AS name,
+                   b.bounds[1]::timestamptz AS range_start,
+                   b.bounds[2]::timestamptz AS range_end,
+                   pg_total_relation_size(c.oid) AS total_bytes,
+                   GREATEST(c.reltuples, 0)::BIGINT AS estimated_rows
+            FROM pg_inherits i
+            JOIN pg_class c ON c.oid = i.inhrelid
+            CROSS JOIN LATERAL (
+                SELECT regexp_match(
+                    pg_get_expr(c.relpartbound, c.oid),
+                    'FROM \(''([^'']+)''\) TO \(''([^'']+)''\)'
+                ) AS bounds
+            ) b
+            WHERE i.inhparent = 'jobs'::regclass
+            ORDER BY range_start ASC NULLS FIRST
+        "#;
+        let partitions = sqlx::query_as::<_, JobPartition>(query)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(partitions)
+    }
+
+    /// Creates and attaches the `jobs` partition for `[from, to)`. Rows that
+    /// already landed in the default partition for that range are moved into
+    /// the new partition first, since Postgres refuses to attach otherwise.
+    /// Returns how many rows were moved.
+    pub async fn create_job_partition(
+        &self,
+        name: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<u64> {
+        let table = quote_ident(name);
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(&format!(
+            "CREATE TABLE {} (LIKE jobs INCLUDING DEFAULTS)",
+            table
+        ))
+        .execute(&mut *tx)
+        .await?;
+
+        let moved = sqlx::query(&format!(
+            r#"
+            WITH moved AS (
+                DELETE FROM jobs_default
+                WHERE created_at >= $1 AND created_at < $2
+                RETURNING *
+            )
+            INSERT INTO {} SELECT * FROM moved
+            "#,
+            table
+        ))
+        .bind(from)
+        .bind(to)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        sqlx::query(&format!(
+            "ALTER TABLE jobs ATTACH PARTITION {} FOR VALUES FROM ('{}') TO ('{}')",
+            table,
+            from.to_rfc3339(),
+            to.to_rfc3339()
+        ))
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(moved)
+    }
+
+    /// Detaches a `jobs` partition, leaving it behind as a standalone table
+    /// unless `drop` is set. Only an empty partition is detached: its rows
+    /// must all have been moved to `jobs_archive`, which only takes terminal
+    /// jobs nothing else reads any more. Returns whether it was detached.
+    pub async fn detach_job_partition(&self, name: &str, drop: bool) -> Result<bool> {
+        let table = quote_ident(name);
+        let mut tx = self.pool.begin().await?;
+
+        // Detaching locks `jobs`, so no row can arrive between the check
+        // and the commit
+        sqlx::query(&format!("ALTER TABLE jobs DETACH PARTITION {}", table))
+            .execute(&mut *tx)
+            .await?;
+        let occupied =
+            sqlx::query_scalar::<_, bool>(&format!("SELECT EXISTS (SELECT 1 FROM {})", table))
+                .fetch_one(&mut *tx)
+                .await?;
+        if occupied {
+            tx.rollback().await?;
+            return Ok(false);
+        }
+        if drop {
+            sqlx::query(&format!("DROP TABLE {}", table))
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(true)
     }
 
     pub async fn get_active_templates(&self) -> Result<Vec<Template>> {
@@ -222,12 +1514,197 @@ impl Database {
     }
 }
 
-fn row_to_hashmap(row: &PgRow) -> HashMap<String, String> {
-    let mut map = HashMap::new();
-    for column in row.columns() {
-        if let Ok(value) = row.try_get::<String, _>(column.name()) {
-            map.insert(column.name().to_string(), value);
+/// [`Database::transition_job`] inside the caller's transaction.
+async fn transition_job_in(
+    tx: &mut Transaction<'_, Postgres>,
+    id: &str,
+    from: JobStatus,
+    to: JobStatus,
+    update: &JobUpdate,
+) -> Result<()> {
+    if !from.can_transition_to(to) {
+        return Err(Error::InvalidTransition { from, to }.into());
+    }
+
+    let job_id = parse_job_id(id)?;
+    let update = update.clone().status(to);
+    let mut builder = QueryBuilder::<Postgres>::new("UPDATE jobs SET ");
+    update.push_assignments(&mut builder);
+    builder
+        .push(" WHERE id = ")
+        .push_bind(job_id)
+        .push(" AND status = ")
+        .push_bind(from);
+    update.push_fence(&mut builder);
+
+    let result = builder.build().execute(&mut **tx).await?;
+    if result.rows_affected() > 0 {
+        return Ok(());
+    }
+
+    let actual = sqlx::query_as::<_, (JobStatus, i64)>(
+        "SELECT status, fencing_token FROM jobs WHERE id = $1",
+    )
+    .bind(job_id)
+    .fetch_optional(&mut **tx)
+    .await?;
+    match actual {
+        Some((actual, current)) if actual == from => Err(Error::StaleFencingToken {
+            job_id: id.to_string(),
+            token: update.fencing_token.unwrap_or_default(),
+            current,
         }
+        .into()),
+        Some((actual, _)) => Err(Error::TransitionConflict {
+            job_id: id.to_string(),
+            expected: from,
+            actual,
+            target: to,
+        }
+        .into()),
+        None => Err(Error::NotFound(format!("Job {} not found", id)).into()),
+    }
+}
+
+/// Quotes a table name for DDL statements, which cannot take bind parameters.
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Inserts a job row along with its dependency edges.
+async fn insert_job(tx: &mut Transaction<'_, Postgres>, id: Uuid, job_data: JobData) -> Result<()> {
+    let query = r#"
+        INSERT INTO jobs (status, priority, scheduled_at, parent_job_id, max_retries, retries, payload, id, merchant_id, reference_id, on_upstream_failure, retry_policy, created_at, updated_at)
+                VALUES ($1, $2, $3::timestamp with time zone, $4, $5, $6, $7, $8, $9, $10, $11, $12, NOW(), NOW())
+    "#;
+
+    sqlx::query(query)
+        .bind(job_data.status)
+        .bind(job_data.priority)
+        .bind(job_data.schedule_at.unwrap_or_else(Utc::now))
+        .bind(job_data.parent_job_id)
+        .bind(job_data.max_retries)
+        .bind(job_data.retries)
+        .bind(job_data.payload)
+        .bind(id)
+        .bind(job_data.merchant_id)
+        .bind(job_data.reference_id)
+        .bind(job_data.dependencies.on_upstream_failure)
+        .bind(sqlx::types::Json(&job_data.retry_policy))
+        .execute(&mut **tx)
+        .await?;
+
+    add_dependencies(
+        tx,
+        id,
+        job_data.merchant_id,
+        &job_data.dependencies.depends_on,
+    )
+    .await
+}
+
+/// Records `key` as belonging to `resource_id`, or returns the resource an
+/// earlier request already stored under it. Keys are scoped to a merchant, so
+/// a resource without one cannot have a key. A concurrent insert of the same
+/// key blocks on the primary key until the first transaction finishes.
+async fn claim_idempotency_key(
+    tx: &mut Transaction<'_, Postgres>,
+    merchant_id: Option<Uuid>,
+    key: &str,
+    resource_id: Uuid,
+) -> Result<Option<Uuid>> {
+    let Some(merchant_id) = merchant_id else {
+        return Err(Error::ValidationError(
+            "Idempotency keys need a merchant to be scoped to".into(),
+        )
+        .into());
+    };
+    let inserted = sqlx::query(
+        r#"
+        INSERT INTO idempotency_keys (merchant_id, idempotency_key, resource_id)
+        VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(merchant_id)
+    .bind(key)
+    .bind(resource_id)
+    .execute(&mut **tx)
+    .await?
+    .rows_affected();
+
+    if inserted > 0 {
+        return Ok(None);
+    }
+
+    let existing = sqlx::query_scalar::<_, Uuid>(
+        "SELECT resource_id FROM idempotency_keys WHERE merchant_id = $1 AND idempotency_key = $2",
+    )
+    .bind(merchant_id)
+    .bind(key)
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(Some(existing))
+}
+
+/// Records that the new job `job_id` waits on `upstreams`, rejecting upstream
+/// jobs that do not exist or belong to another merchant. Edges only ever
+/// point from a new job to existing ones, so they cannot close a cycle.
+async fn add_dependencies(
+    tx: &mut Transaction<'_, Postgres>,
+    job_id: Uuid,
+    merchant_id: Option<Uuid>,
+    upstreams: &[Uuid],
+) -> Result<()> {
+    if upstreams.is_empty() {
+        return Ok(());
     }
-    map
+
+    let missing = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        SELECT upstream FROM UNNEST($1::uuid[]) AS upstream
+        WHERE NOT EXISTS (
+            SELECT 1 FROM jobs
+            WHERE jobs.id = upstream AND jobs.merchant_id IS NOT DISTINCT FROM $2
+        )
+        "#,
+    )
+    .bind(upstreams)
+    .bind(merchant_id)
+    .fetch_all(&mut **tx)
+    .await?;
+    if !missing.is_empty() {
+        return Err(Error::ValidationError(format!("Unknown upstream jobs: {:?}", missing)).into());
+    }
+
+    sqlx::query(
+        r#"
+        INSERT INTO job_dependencies (job_id, depends_on)
+        SELECT $1, UNNEST($2::uuid[])
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(job_id)
+    .bind(upstreams)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+fn truncate_output(mut output: String) -> String {
+    if output.len() > MAX_RUN_OUTPUT_BYTES {
+        let mut end = MAX_RUN_OUTPUT_BYTES;
+        while !output.is_char_boundary(end) {
+            end -= 1;
+        }
+        output.truncate(end);
+        output.push_str("\n[truncated]");
+    }
+    output
+}
+
+fn parse_job_id(id: &str) -> Result<Uuid> {
+    Uuid::parse_str(id).map_err(|e| anyhow::anyhow!("Invalid UUID format: {}", e))
 }
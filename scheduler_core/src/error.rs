@@ -1,3 +1,4 @@
+use crate::models::JobStatus;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -31,6 +32,24 @@ pub enum Error {
 
     #[error("Internal server error: {0}")]
     InternalServerError(String),
+
+    #[error("Invalid job state transition: {from} -> {to}")]
+    InvalidTransition { from: JobStatus, to: JobStatus },
+
+    #[error("Job {job_id} is no longer {expected} (now {actual}); cannot move it to {target}")]
+    TransitionConflict {
+        job_id: String,
+        expected: JobStatus,
+        actual: JobStatus,
+        target: JobStatus,
+    },
+
+    #[error("Job {job_id} was claimed again (fencing token {current}); token {token} is stale")]
+    StaleFencingToken {
+        job_id: String,
+        token: i64,
+        current: i64,
+    },
 }
 
 impl Error {
@@ -53,6 +72,9 @@ impl Error {
             Error::SerializationError(_) => 500,
             Error::MigrationError(_) => 500,
             Error::InternalServerError(_) => 500,
+            Error::InvalidTransition { .. } => 400,
+            Error::TransitionConflict { .. } => 409,
+            Error::StaleFencingToken { .. } => 409,
         }
     }
 }
@@ -5,7 +5,10 @@ pub mod db;
 pub mod error;
 pub mod init;
 pub mod models;
+pub mod retry;
+pub mod state_machine;
 pub mod task;
+pub mod workflow;
 
 pub use api_models::{
     DeleteResponse, JobCreate, JobResponse, JobUpdate, TemplateCreate, TemplateResponse,
@@ -16,5 +19,8 @@ pub use config::Config;
 pub use db::Database;
 pub use error::Error as SchedulerError;
 pub use init::{init_cache, init_database};
-pub use models::{Job, JobStatus, JobType, Template};
+pub use models::{
+    DeadLetterEntry, DeadLetterReason, Job, JobPartition, JobRun, JobStatus, JobType, Template,
+    Worker, WorkerDetails,
+};
 pub use task::TaskManager;
@@ -1,19 +1,199 @@
 use crate::error::Error;
-use chrono::{DateTime, Utc};
+use crate::retry::RetryPolicy;
+use chrono::{DateTime, Datelike, Duration, Months, NaiveTime, TimeZone, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::{FromRow, Type};
+use std::str::FromStr;
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+/// A row of the `jobs` table. This is the one job model shared by every service.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, FromRow)]
 pub struct Job {
-    pub id: String,
-    pub schedule: DateTime<Utc>,
-    pub payload: serde_json::Value,
+    pub id: Uuid,
+    pub description: Option<String>,
+    pub parent_job_id: Option<Uuid>,
+    pub reference_id: Option<String>,
     pub status: JobStatus,
+    pub priority: i32,
+    pub max_retries: i32,
+    pub retries: i32,
+    pub last_error: Option<String>,
+    pub payload: serde_json::Value,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
-    pub retries: i32,
-    pub max_retries: i32,
+    pub next_run_at: Option<DateTime<Utc>>,
+    pub last_run_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub scheduled_at: DateTime<Utc>,
+    pub metadata: Option<serde_json::Value>,
+    pub template_id: Option<Uuid>,
+    pub merchant_id: Option<Uuid>,
+    pub claimed_by: Option<String>,
+    pub claimed_at: Option<DateTime<Utc>>,
+    pub on_upstream_failure: UpstreamFailurePolicy,
+    #[sqlx(json)]
+    pub retry_policy: RetryPolicy,
+    /// Worker currently or last running the job.
+    pub lease_owner: Option<String>,
+    pub lease_expires_at: Option<DateTime<Utc>>,
+    /// Bumped by every claim; see [`crate::db::JobUpdate::fenced_by`].
+    pub fencing_token: i64,
+    /// How the last failed attempt ended, e.g. `timed_out`; the `error_class`
+    /// of its run.
+    pub last_error_class: Option<String>,
+    /// Times a worker that could not run the job handed it back to the queue.
+    pub bounces: i32,
+}
+
+/// A row of the `workers` table: an executor and its liveness lease.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Worker {
+    pub id: String,
+    pub hostname: String,
+    pub version: String,
+    pub capabilities: Vec<String>,
+    pub concurrency_limit: i32,
+    /// Jobs the worker was running at its last heartbeat.
+    pub running_jobs: i32,
+    pub draining: bool,
+    pub started_at: DateTime<Utc>,
+    pub last_heartbeat_at: DateTime<Utc>,
+    pub lease_expires_at: DateTime<Utc>,
+}
+
+/// A registered worker together with the jobs it is running.
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkerDetails {
+    #[serde(flatten)]
+    pub worker: Worker,
+    /// Whether the worker heartbeated within its lease.
+    pub alive: bool,
+    pub jobs: Vec<Job>,
+}
+
+impl WorkerDetails {
+    pub fn new(worker: Worker, jobs: Vec<Job>) -> Self {
+        Self {
+            alive: worker.lease_expires_at > Utc::now(),
+            worker,
+            jobs,
+        }
+    }
+}
+
+/// A row of the `job_runs` table: one execution attempt of a job.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct JobRun {
+    pub id: Uuid,
+    pub job_id: Uuid,
+    pub attempt: i32,
+    pub worker_id: String,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub duration_ms: Option<i64>,
+    pub exit_code: Option<i32>,
+    pub stdout: Option<String>,
+    pub stderr: Option<String>,
+    pub error_class: Option<String>,
+    pub error_message: Option<String>,
+    pub peak_memory_bytes: Option<i64>,
+    pub cpu_time_ms: Option<i64>,
+    pub http_status: Option<i32>,
+    pub response_body: Option<String>,
+    pub result: Option<serde_json::Value>,
+    pub progress: Option<serde_json::Value>,
+    pub script_hash: Option<String>,
+}
+
+/// Why a job was dead-lettered.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Type)]
+#[sqlx(type_name = "dead_letter_reason", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum DeadLetterReason {
+    /// The job failed on every attempt it was allowed.
+    RetriesExhausted,
+    /// The job's worker vanished and it never finished.
+    Orphaned,
+    /// The job failed in a way retrying cannot fix.
+    PermanentFailure,
+}
+
+/// A row of the `dead_letter_entries` table: a dead-lettered job awaiting
+/// inspection, replay or purge.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct DeadLetterEntry {
+    pub job_id: Uuid,
+    pub merchant_id: Option<Uuid>,
+    pub reason: DeadLetterReason,
+    pub last_error: Option<String>,
+    pub attempts: i32,
+    pub dead_lettered_at: DateTime<Utc>,
+}
+
+/// A dead letter entry together with the job it refers to.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeadLetterDetails {
+    #[serde(flatten)]
+    pub entry: DeadLetterEntry,
+    pub job: Job,
+}
+
+/// One attached partition of the range-partitioned `jobs` table. The default
+/// partition has no bounds.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct JobPartition {
+    pub name: String,
+    pub range_start: Option<DateTime<Utc>>,
+    pub range_end: Option<DateTime<Utc>>,
+    pub total_bytes: i64,
+    pub estimated_rows: i64,
+}
+
+/// Width of each `jobs` partition.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PartitionInterval {
+    #[default]
+    Monthly,
+    /// ISO weeks, starting on Monday.
+    Weekly,
+}
+
+impl PartitionInterval {
+    /// Start of the partition that contains `at`.
+    pub fn floor(self, at: DateTime<Utc>) -> DateTime<Utc> {
+        let date = at.date_naive();
+        let start = match self {
+            PartitionInterval::Monthly => date.with_day(1).unwrap(),
+            PartitionInterval::Weekly => {
+                date - Duration::days(date.weekday().num_days_from_monday() as i64)
+            }
+        };
+        Utc.from_utc_datetime(&start.and_time(NaiveTime::MIN))
+    }
+
+    /// Start of the partition following the one starting at `start`.
+    pub fn next(self, start: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            PartitionInterval::Monthly => start + Months::new(1),
+            PartitionInterval::Weekly => start + Duration::weeks(1),
+        }
+    }
+}
+
+impl FromStr for PartitionInterval {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "monthly" => Ok(PartitionInterval::Monthly),
+            "weekly" => Ok(PartitionInterval::Weekly),
+            _ => Err(Error::ConfigError(format!(
+                "Invalid partition interval: {}",
+                s
+            ))),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -24,17 +204,35 @@ pub struct Template {
     pub active: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    #[sqlx(json)]
+    pub retry_policy: RetryPolicy,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Type)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, Type)]
 #[sqlx(type_name = "job_status")]
 #[sqlx(rename_all = "lowercase")]
 pub enum JobStatus {
+    #[default]
     Pending,
+    Queued,
     Running,
     Completed,
     Failed,
     Retrying,
+    Cancelled,
+    #[sqlx(rename = "dead_lettered")]
+    DeadLettered,
+}
+
+/// What a job does when one of the jobs it depends on is cancelled or
+/// dead-lettered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, Type)]
+#[sqlx(type_name = "upstream_failure_policy", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum UpstreamFailurePolicy {
+    #[default]
+    CascadeCancel,
+    RunAnyway,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
@@ -78,7 +276,7 @@ impl From<JobType> for String {
 
 impl Job {
     pub fn validate(&self) -> Result<(), Error> {
-        DateTime::parse_from_rfc3339(&self.schedule.to_rfc3339()).map_err(|_| {
+        DateTime::parse_from_rfc3339(&self.scheduled_at.to_rfc3339()).map_err(|_| {
             Error::ValidationError("Invalid datetime format. Use ISO 8601 format".into())
         })?;
         Ok(())
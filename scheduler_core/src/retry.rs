@@ -0,0 +1,213 @@
+use crate::error::Error;
+use chrono::Duration;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// How long a failed job waits before its next attempt. Stored with the job,
+/// so the executor and the failure watcher schedule retries the same way.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "strategy", rename_all = "snake_case")]
+pub enum RetryPolicy {
+    /// The same delay before every retry.
+    Fixed { delay_secs: u64 },
+    /// Doubles the delay on every retry, up to `max_delay_secs`.
+    Exponential {
+        initial_delay_secs: u64,
+        max_delay_secs: u64,
+    },
+    /// A uniformly random delay between zero and the exponential delay
+    /// ("full jitter"), which spreads out retries of jobs that failed together.
+    ExponentialJitter {
+        initial_delay_secs: u64,
+        max_delay_secs: u64,
+    },
+    /// One delay per retry; the last one repeats once the list runs out.
+    Custom { delays_secs: Vec<u64> },
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::Exponential {
+            initial_delay_secs: 60,
+            max_delay_secs: 3600,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn validate(&self) -> Result<(), Error> {
+        match self {
+            RetryPolicy::Exponential {
+                initial_delay_secs,
+                max_delay_secs,
+            }
+            | RetryPolicy::ExponentialJitter {
+                initial_delay_secs,
+                max_delay_secs,
+            } if max_delay_secs < initial_delay_secs => Err(Error::ValidationError(
+                "max_delay_secs must not be less than initial_delay_secs".into(),
+            )),
+            RetryPolicy::Custom { delays_secs } if delays_secs.is_empty() => Err(
+                Error::ValidationError("Custom retry policy needs at least one delay".into()),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Delay before retry number `retry`, counting from 1.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let secs = match self {
+            RetryPolicy::Fixed { delay_secs } => *delay_secs,
+            RetryPolicy::Exponential {
+                initial_delay_secs,
+                max_delay_secs,
+            } => exponential(*initial_delay_secs, *max_delay_secs, retry),
+            RetryPolicy::ExponentialJitter {
+                initial_delay_secs,
+                max_delay_secs,
+            } => {
+                let ceiling = exponential(*initial_delay_secs, *max_delay_secs, retry);
+                rand::thread_rng().gen_range(0..=ceiling)
+            }
+            RetryPolicy::Custom { delays_secs } => {
+                let index = (retry.max(1) as usize - 1).min(delays_secs.len().saturating_sub(1));
+                delays_secs.get(index).copied().unwrap_or(0)
+            }
+        };
+        // Capped so that adding the delay to the current time cannot overflow
+        Duration::seconds(secs.min(i32::MAX as u64) as i64)
+    }
+}
+
+fn exponential(initial: u64, max: u64, retry: u32) -> u64 {
+    let factor = 2u64.saturating_pow(retry.saturating_sub(1));
+    initial.saturating_mul(factor).min(max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exponential_policy(initial_delay_secs: u64, max_delay_secs: u64) -> RetryPolicy {
+        RetryPolicy::Exponential {
+            initial_delay_secs,
+            max_delay_secs,
+        }
+    }
+
+    #[test]
+    fn fixed_delay_is_the_same_every_time() {
+        let policy = RetryPolicy::Fixed { delay_secs: 30 };
+        for retry in [1, 2, 10] {
+            assert_eq!(policy.delay(retry), Duration::seconds(30));
+        }
+    }
+
+    #[test]
+    fn exponential_delay_doubles_up_to_the_maximum() {
+        let policy = exponential_policy(10, 60);
+        let delays: Vec<_> = (1..=5)
+            .map(|retry| policy.delay(retry).num_seconds())
+            .collect();
+        assert_eq!(delays, [10, 20, 40, 60, 60]);
+    }
+
+    #[test]
+    fn exponential_delay_treats_retry_zero_as_the_first() {
+        assert_eq!(exponential_policy(10, 60).delay(0), Duration::seconds(10));
+    }
+
+    #[test]
+    fn exponential_delay_saturates_instead_of_overflowing() {
+        let policy = exponential_policy(10, 3600);
+        assert_eq!(policy.delay(64), Duration::seconds(3600));
+        assert_eq!(policy.delay(u32::MAX), Duration::seconds(3600));
+
+        let unbounded = exponential_policy(u64::MAX / 2, u64::MAX);
+        assert_eq!(
+            unbounded.delay(u32::MAX),
+            Duration::seconds(i32::MAX as i64)
+        );
+    }
+
+    #[test]
+    fn jittered_delay_stays_below_the_exponential_delay() {
+        let policy = RetryPolicy::ExponentialJitter {
+            initial_delay_secs: 10,
+            max_delay_secs: 60,
+        };
+        for retry in 1..=5 {
+            let ceiling = exponential_policy(10, 60).delay(retry);
+            for _ in 0..50 {
+                let delay = policy.delay(retry);
+                assert!(delay >= Duration::zero() && delay <= ceiling, "{delay}");
+            }
+        }
+        assert_eq!(
+            RetryPolicy::ExponentialJitter {
+                initial_delay_secs: 0,
+                max_delay_secs: 0,
+            }
+            .delay(u32::MAX),
+            Duration::zero()
+        );
+    }
+
+    #[test]
+    fn custom_delays_repeat_the_last_one() {
+        let policy = RetryPolicy::Custom {
+            delays_secs: vec![5, 30, 300],
+        };
+        let delays: Vec<_> = [0, 1, 2, 3, 4, u32::MAX]
+            .into_iter()
+            .map(|retry| policy.delay(retry).num_seconds())
+            .collect();
+        assert_eq!(delays, [5, 5, 30, 300, 300, 300]);
+    }
+
+    #[test]
+    fn delays_are_capped_to_keep_timestamps_in_range() {
+        let policy = RetryPolicy::Fixed {
+            delay_secs: u64::MAX,
+        };
+        assert_eq!(policy.delay(1), Duration::seconds(i32::MAX as i64));
+    }
+
+    #[test]
+    fn validates_exponential_bounds() {
+        assert!(exponential_policy(10, 10).validate().is_ok());
+        assert!(exponential_policy(0, 0).validate().is_ok());
+        assert!(matches!(
+            exponential_policy(60, 10).validate(),
+            Err(Error::ValidationError(_))
+        ));
+        assert!(matches!(
+            RetryPolicy::ExponentialJitter {
+                initial_delay_secs: 60,
+                max_delay_secs: 10,
+            }
+            .validate(),
+            Err(Error::ValidationError(_))
+        ));
+    }
+
+    #[test]
+    fn validates_custom_delays() {
+        assert!(
+            RetryPolicy::Custom {
+                delays_secs: vec![1]
+            }
+            .validate()
+            .is_ok()
+        );
+        assert!(matches!(
+            RetryPolicy::Custom {
+                delays_secs: Vec::new()
+            }
+            .validate(),
+            Err(Error::ValidationError(_))
+        ));
+        assert!(RetryPolicy::Fixed { delay_secs: 0 }.validate().is_ok());
+        assert!(RetryPolicy::default().validate().is_ok());
+    }
+}
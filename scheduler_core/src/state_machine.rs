@@ -0,0 +1,118 @@
+use crate::models::JobStatus;
+
+/// The job lifecycle shared by every service:
+///
+/// ```text
+/// Pending -> Queued -> Running -> Completed
+///                          \---> Failed -> Retrying -> Pending
+///                                     \--> DeadLettered
+/// ```
+///
+/// Queued jobs can be released back to Pending, Running jobs whose worker
+/// disappeared go back to Queued, Running jobs that failed permanently go
+/// straight to DeadLettered, and any non-terminal job can be Cancelled.
+/// DeadLettered jobs only leave that state when an operator replays them.
+impl JobStatus {
+    pub fn allowed_transitions(self) -> &'static [JobStatus] {
+        use JobStatus::*;
+        match self {
+            Pending => &[Queued, Cancelled],
+            Queued => &[Running, Pending, Cancelled],
+            Running => &[Completed, Failed, Queued, Cancelled, DeadLettered],
+            Failed => &[Retrying, DeadLettered, Cancelled],
+            Retrying => &[Pending, Cancelled],
+            DeadLettered => &[Pending],
+            Completed | Cancelled => &[],
+        }
+    }
+
+    pub fn can_transition_to(self, next: JobStatus) -> bool {
+        self.allowed_transitions().contains(&next)
+    }
+
+    /// Whether the job is finished. A dead-lettered job counts as finished
+    /// even though it can still be replayed.
+    pub fn is_terminal(self) -> bool {
+        matches!(
+            self,
+            JobStatus::Completed | JobStatus::Cancelled | JobStatus::DeadLettered
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use JobStatus::*;
+
+    const ALL: [JobStatus; 8] = [
+        Pending,
+        Queued,
+        Running,
+        Completed,
+        Failed,
+        Retrying,
+        Cancelled,
+        DeadLettered,
+    ];
+
+    #[test]
+    fn follows_the_happy_path() {
+        assert!(Pending.can_transition_to(Queued));
+        assert!(Queued.can_transition_to(Running));
+        assert!(Running.can_transition_to(Completed));
+    }
+
+    #[test]
+    fn retries_through_failed_and_retrying() {
+        assert!(Running.can_transition_to(Failed));
+        assert!(Failed.can_transition_to(Retrying));
+        assert!(Retrying.can_transition_to(Pending));
+        assert!(Failed.can_transition_to(DeadLettered));
+        assert!(DeadLettered.can_transition_to(Pending));
+    }
+
+    #[test]
+    fn dead_letters_permanent_failures_while_running() {
+        assert!(Running.can_transition_to(DeadLettered));
+        assert!(!Pending.can_transition_to(DeadLettered));
+        assert!(!Queued.can_transition_to(DeadLettered));
+        assert!(!Retrying.can_transition_to(DeadLettered));
+    }
+
+    #[test]
+    fn rejects_skipping_states() {
+        assert!(!Pending.can_transition_to(Running));
+        assert!(!Pending.can_transition_to(Completed));
+        assert!(!Queued.can_transition_to(Completed));
+        assert!(!Failed.can_transition_to(Pending));
+        assert!(!Retrying.can_transition_to(Running));
+        assert!(!DeadLettered.can_transition_to(Queued));
+    }
+
+    #[test]
+    fn never_leaves_completed_or_cancelled() {
+        for next in ALL {
+            assert!(!Completed.can_transition_to(next), "Completed -> {next}");
+            assert!(!Cancelled.can_transition_to(next), "Cancelled -> {next}");
+        }
+    }
+
+    #[test]
+    fn cancels_any_unfinished_job() {
+        for status in ALL {
+            assert_eq!(
+                status.can_transition_to(Cancelled),
+                !status.is_terminal(),
+                "{status} -> Cancelled"
+            );
+        }
+    }
+
+    #[test]
+    fn never_transitions_to_itself() {
+        for status in ALL {
+            assert!(!status.can_transition_to(status), "{status} -> {status}");
+        }
+    }
+}
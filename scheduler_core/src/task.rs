@@ -1,24 +1,16 @@
-use crate::{JobStatus, JobType, db::Database};
+use crate::{
+    DeadLetterEntry, DeadLetterReason, Job, JobPartition, JobRun, JobStatus, JobType,
+    db::{CreatedJob, Database, JobDependencies, JobOrigin, JobRetry, JobUpdate},
+    models::{DeadLetterDetails, WorkerDetails},
+};
 use anyhow::Result;
 use chrono::{DateTime, Utc};
 use cron_parser::parse;
-use serde::{Deserialize, Serialize};
-use serde_json::to_value;
+use serde::Serialize;
+use serde_json::{Value, to_value};
 use std::collections::HashMap;
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Job {
-    pub id: String,
-    pub status: JobStatus,
-    pub priority: i32,
-    pub scheduled_at: DateTime<Utc>,
-    pub parent_job_id: Option<String>,
-    pub max_retries: i32,
-    pub retries: i32,
-    pub payload: HashMap<String, String>,
-}
-
 #[derive(Debug, Clone)]
 pub struct TaskManager {
     db: Database,
@@ -33,14 +25,17 @@ impl TaskManager {
         &self,
         scheduled_at: Option<DateTime<Utc>>,
         priority: i32,
-        payload: HashMap<String, String>,
-    ) -> Result<String> {
+        payload: impl Serialize,
+        origin: JobOrigin,
+        dependencies: JobDependencies,
+        retry: JobRetry,
+    ) -> Result<CreatedJob> {
         let job_data = crate::db::JobData {
             status: JobStatus::Pending,
             priority,
             schedule_at: scheduled_at,
             parent_job_id: None,
-            max_retries: 3,
+            max_retries: retry.max_retries,
             retries: 0,
             payload: to_value(payload)?,
             cron: None,
@@ -50,6 +45,10 @@ impl TaskManager {
             name: None,
             max_attempts: 1,
             metadata: None,
+            merchant_id: origin.merchant_id,
+            reference_id: origin.reference_id,
+            dependencies,
+            retry_policy: retry.policy,
         };
 
         self.db.create_job(job_data).await
@@ -60,8 +59,10 @@ impl TaskManager {
         parent_job_id: Uuid,
         cron: Option<String>,
         priority: i32,
-        payload: HashMap<String, String>,
-    ) -> Result<String> {
+        payload: impl Serialize,
+        origin: JobOrigin,
+        retry: JobRetry,
+    ) -> Result<CreatedJob> {
         //based on the cron, calculate the next run time
         let schedule_at = cron.clone().map(|c| {
             parse(&c, &Utc::now())
@@ -72,18 +73,22 @@ impl TaskManager {
         let job_data = crate::db::JobData {
             status: JobStatus::Pending,
             priority,
-            cron: cron,
+            cron,
             parent_job_id: Some(parent_job_id),
-            max_retries: 3,
+            max_retries: retry.max_retries,
             retries: 0,
             payload: to_value(payload)?,
             interval: None,
-            schedule_at: schedule_at,
+            schedule_at,
             name: None,
             description: None,
             max_attempts: 1,
             active: true,
             metadata: None,
+            merchant_id: origin.merchant_id,
+            reference_id: origin.reference_id,
+            dependencies: JobDependencies::default(),
+            retry_policy: retry.policy,
         };
 
         self.db.create_template(job_data, JobType::Recurring).await
@@ -94,205 +99,245 @@ impl TaskManager {
         interval: Option<u32>,
         priority: i32,
         schedule_at: Option<DateTime<Utc>>,
-        max_retries: i32,
-        payload: HashMap<String, String>,
-    ) -> Result<String> {
+        payload: impl Serialize,
+        origin: JobOrigin,
+        retry: JobRetry,
+    ) -> Result<CreatedJob> {
         let job_data = crate::db::JobData {
             status: JobStatus::Pending,
             priority,
-            schedule_at: schedule_at,
+            schedule_at,
             parent_job_id: None,
-            max_retries,
+            max_retries: retry.max_retries,
             retries: 0,
             payload: to_value(payload)?,
             cron: None,
-            interval: interval,
+            interval,
             max_attempts: 3,
             metadata: None,
             active: true,
             description: None,
             name: None,
+            merchant_id: origin.merchant_id,
+            reference_id: origin.reference_id,
+            dependencies: JobDependencies::default(),
+            retry_policy: retry.policy,
         };
 
         self.db.create_template(job_data, JobType::Polling).await
     }
 
     pub async fn get_job(&self, id: &str) -> Result<Option<Job>> {
-        let job_data = self.db.get_job(id).await?;
-        Ok(job_data.map(|data| Job {
-            id: data.get("id").unwrap().clone(),
-            status: match data.get("status").unwrap().as_str() {
-                "pending" => JobStatus::Pending,
-                "running" => JobStatus::Running,
-                "completed" => JobStatus::Completed,
-                "failed" => JobStatus::Failed,
-                "retrying" => JobStatus::Retrying,
-                _ => panic!("Invalid job status"),
-            },
-            priority: data.get("priority").unwrap().parse().unwrap(),
-            scheduled_at: DateTime::parse_from_rfc3339(data.get("scheduled_at").unwrap())
-                .unwrap()
-                .with_timezone(&Utc),
-            parent_job_id: data.get("parent_job_id").map(|s| s.clone()),
-            max_retries: data.get("max_retries").unwrap().parse().unwrap(),
-            retries: data.get("retries").unwrap().parse().unwrap(),
-            payload: serde_json::from_str(data.get("payload").unwrap()).unwrap(),
-        }))
-    }
-
-    pub async fn update_job_status(&self, id: &str, status: JobStatus) -> Result<bool> {
-        let mut updates = HashMap::new();
-        updates.insert("status", status.to_string());
-        self.db.update_job(id, &updates).await
-    }
-
-    pub async fn increment_job_attempts(&self, id: &str) -> Result<bool> {
-        let mut updates = HashMap::new();
-        updates.insert("retries", "retries + 1".to_string());
-        self.db.update_job(id, &updates).await
+        self.db.get_job(id).await
+    }
+
+    pub async fn update_job(&self, id: &str, update: &JobUpdate) -> Result<bool> {
+        self.db.update_job(id, update).await
+    }
+
+    pub async fn transition_job(&self, id: &str, from: JobStatus, to: JobStatus) -> Result<()> {
+        self.db
+            .transition_job(id, from, to, &JobUpdate::new())
+            .await
+    }
+
+    pub async fn transition_job_with(
+        &self,
+        id: &str,
+        from: JobStatus,
+        to: JobStatus,
+        update: &JobUpdate,
+    ) -> Result<()> {
+        self.db.transition_job(id, from, to, update).await
+    }
+
+    /// Schedules the next attempt of a failed job according to its retry
+    /// policy and returns when it will run. Does not wait for it.
+    pub async fn schedule_retry(&self, job: &Job) -> Result<DateTime<Utc>> {
+        let run_at = Utc::now() + job.retry_policy.delay(job.retries as u32 + 1);
+        self.db
+            .schedule_retry(&job.id.to_string(), job.fencing_token, run_at)
+            .await?;
+        Ok(run_at)
     }
 
     pub async fn get_due_jobs(&self, limit: i64) -> Result<Vec<Job>> {
-        let job_data = self.db.get_due_jobs(limit, &[]).await?;
+        self.db.get_due_jobs(limit, &[]).await
+    }
 
-        Ok(job_data
-            .into_iter()
-            .map(|data| Job {
-                id: data.get("id").unwrap().clone(),
-                status: match data.get("status").unwrap().as_str() {
-                    "pending" => JobStatus::Pending,
-                    "running" => JobStatus::Running,
-                    "completed" => JobStatus::Completed,
-                    "failed" => JobStatus::Failed,
-                    "retrying" => JobStatus::Retrying,
-                    _ => panic!("Invalid job status"),
-                },
-                priority: data.get("priority").unwrap().parse().unwrap(),
-                scheduled_at: DateTime::parse_from_rfc3339(data.get("scheduled_at").unwrap())
-                    .unwrap()
-                    .with_timezone(&Utc),
-                parent_job_id: data.get("parent_job_id").map(|s| s.clone()),
-                max_retries: data.get("max_retries").unwrap().parse().unwrap(),
-                retries: data.get("retries").unwrap().parse().unwrap(),
-                payload: serde_json::from_str(data.get("payload").unwrap()).unwrap(),
-            })
-            .collect())
+    pub async fn claim_due_jobs(&self, limit: i64, claimant: &str) -> Result<Vec<Job>> {
+        self.db.claim_due_jobs(limit, claimant).await
     }
 
-    pub async fn get_jobs_by_status(&self, status: JobStatus) -> Result<Vec<Job>> {
-        let job_data = self.db.get_jobs_by_status(&status.to_string()).await?;
+    pub async fn get_jobs_with_failed_upstream(&self, limit: i64) -> Result<Vec<Job>> {
+        self.db.get_jobs_with_failed_upstream(limit).await
+    }
 
-        Ok(job_data
-            .into_iter()
-            .map(|data| Job {
-                id: data.get("id").unwrap().clone(),
-                status: match data.get("status").unwrap().as_str() {
-                    "pending" => JobStatus::Pending,
-                    "running" => JobStatus::Running,
-                    "completed" => JobStatus::Completed,
-                    "failed" => JobStatus::Failed,
-                    "retrying" => JobStatus::Retrying,
-                    _ => panic!("Invalid job status"),
-                },
-                priority: data.get("priority").unwrap().parse().unwrap(),
-                scheduled_at: DateTime::parse_from_rfc3339(data.get("scheduled_at").unwrap())
-                    .unwrap()
-                    .with_timezone(&Utc),
-                parent_job_id: data.get("parent_job_id").map(|s| s.clone()),
-                max_retries: data.get("max_retries").unwrap().parse().unwrap(),
-                retries: data.get("retries").unwrap().parse().unwrap(),
-                payload: serde_json::from_str(data.get("payload").unwrap()).unwrap(),
-            })
-            .collect())
+    pub async fn get_jobs_by_status(&self, status: JobStatus) -> Result<Vec<Job>> {
+        self.db.get_jobs_by_status(status).await
     }
 
-    pub async fn get_jobs_older_than(
+    pub async fn get_jobs_by_status_and_time(
         &self,
-        cutoff_time: chrono::DateTime<chrono::Utc>,
+        status: JobStatus,
+        cutoff_time: DateTime<Utc>,
     ) -> Result<Vec<Job>> {
-        let job_data = self
-            .db
-            .get_jobs_older_than(&cutoff_time.to_rfc3339())
-            .await?;
-        Ok(job_data
+        self.db
+            .get_jobs_by_status_and_time(status, cutoff_time)
+            .await
+    }
+
+    pub async fn get_expired_leases(&self, limit: i64) -> Result<Vec<Job>> {
+        self.db.get_expired_leases(limit).await
+    }
+
+    pub async fn get_stale_claims(&self, cutoff: DateTime<Utc>, limit: i64) -> Result<Vec<Job>> {
+        self.db.get_stale_claims(cutoff, limit).await
+    }
+
+    pub async fn abandon_job_runs(
+        &self,
+        job_id: &str,
+        worker_id: &str,
+        reason: &str,
+    ) -> Result<()> {
+        self.db.abandon_job_runs(job_id, worker_id, reason).await
+    }
+
+    /// Every registered worker with the jobs it is running.
+    pub async fn get_workers(&self) -> Result<Vec<WorkerDetails>> {
+        let workers = self.db.get_workers().await?;
+        let ids: Vec<String> = workers.iter().map(|w| w.id.clone()).collect();
+        let mut jobs_by_worker: HashMap<String, Vec<Job>> = HashMap::new();
+        for job in self.db.get_leased_jobs(&ids).await? {
+            if let Some(owner) = job.lease_owner.clone() {
+                jobs_by_worker.entry(owner).or_default().push(job);
+            }
+        }
+        Ok(workers
             .into_iter()
-            .map(|data| Job {
-                id: data.get("id").unwrap().clone(),
-                status: match data.get("status").unwrap().as_str() {
-                    "pending" => JobStatus::Pending,
-                    "running" => JobStatus::Running,
-                    "completed" => JobStatus::Completed,
-                    "failed" => JobStatus::Failed,
-                    "retrying" => JobStatus::Retrying,
-                    _ => panic!("Invalid job status"),
-                },
-                priority: data.get("priority").unwrap().parse().unwrap(),
-                scheduled_at: DateTime::parse_from_rfc3339(data.get("scheduled_at").unwrap())
-                    .unwrap()
-                    .with_timezone(&Utc),
-                parent_job_id: data.get("parent_job_id").map(|s| s.clone()),
-                max_retries: data.get("max_retries").unwrap().parse().unwrap(),
-                retries: data.get("retries").unwrap().parse().unwrap(),
-                payload: serde_json::from_str(data.get("payload").unwrap()).unwrap(),
+            .map(|worker| {
+                let jobs = jobs_by_worker.remove(&worker.id).unwrap_or_default();
+                WorkerDetails::new(worker, jobs)
             })
             .collect())
     }
 
-    pub async fn get_jobs_by_status_and_time(
-        &self,
-        status: JobStatus,
-        cutoff_time: chrono::DateTime<chrono::Utc>,
-    ) -> Result<Vec<Job>> {
-        let job_data = self
+    pub async fn get_worker(&self, id: &str) -> Result<Option<WorkerDetails>> {
+        let Some(worker) = self.db.get_worker(id).await? else {
+            return Ok(None);
+        };
+        let jobs = self
             .db
-            .get_jobs_by_status_and_time(&status.to_string(), &cutoff_time.to_rfc3339())
+            .get_leased_jobs(std::slice::from_ref(&worker.id))
             .await?;
-        Ok(job_data
-            .into_iter()
-            .map(|data| Job {
-                id: data.get("id").unwrap().clone(),
-                status: match data.get("status").unwrap().as_str() {
-                    "pending" => JobStatus::Pending,
-                    "running" => JobStatus::Running,
-                    "completed" => JobStatus::Completed,
-                    "failed" => JobStatus::Failed,
-                    "retrying" => JobStatus::Retrying,
-                    _ => panic!("Invalid job status"),
-                },
-                priority: data.get("priority").unwrap().parse().unwrap(),
-                scheduled_at: DateTime::parse_from_rfc3339(data.get("scheduled_at").unwrap())
-                    .unwrap()
-                    .with_timezone(&Utc),
-                parent_job_id: data.get("parent_job_id").map(|s| s.clone()),
-                max_retries: data.get("max_retries").unwrap().parse().unwrap(),
-                retries: data.get("retries").unwrap().parse().unwrap(),
-                payload: serde_json::from_str(data.get("payload").unwrap()).unwrap(),
-            })
-            .collect())
+        Ok(Some(WorkerDetails::new(worker, jobs)))
+    }
+
+    pub async fn drain_worker(&self, id: &str) -> Result<bool> {
+        self.db.drain_worker(id).await
+    }
+
+    pub async fn prune_workers(&self, cutoff: DateTime<Utc>) -> Result<u64> {
+        self.db.prune_workers(cutoff).await
+    }
+
+    pub async fn get_job_runs(&self, job_id: &str) -> Result<Vec<JobRun>> {
+        self.db.get_job_runs(job_id).await
+    }
+
+    pub async fn get_job_partitions(&self) -> Result<Vec<JobPartition>> {
+        self.db.get_job_partitions().await
+    }
+
+    pub async fn create_job_partition(
+        &self,
+        name: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<u64> {
+        self.db.create_job_partition(name, from, to).await
+    }
+
+    pub async fn detach_job_partition(&self, name: &str, drop: bool) -> Result<bool> {
+        self.db.detach_job_partition(name, drop).await
+    }
+
+    pub async fn dead_letter_job(&self, job_id: &str, reason: DeadLetterReason) -> Result<()> {
+        self.db
+            .dead_letter_job(job_id, JobStatus::Failed, reason, &JobUpdate::new())
+            .await
+    }
+
+    pub async fn get_dead_letters(
+        &self,
+        merchant_id: Option<Uuid>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<DeadLetterEntry>> {
+        self.db.get_dead_letters(merchant_id, limit, offset).await
+    }
+
+    /// The dead letter entry for a job together with the job itself.
+    pub async fn get_dead_letter(
+        &self,
+        job_id: &str,
+        merchant_id: Option<Uuid>,
+    ) -> Result<Option<DeadLetterDetails>> {
+        let Some(entry) = self.db.get_dead_letter(job_id, merchant_id).await? else {
+            return Ok(None);
+        };
+        let job = self.get_job_including_archived(job_id).await?;
+        Ok(job.map(|job| DeadLetterDetails { entry, job }))
     }
 
-    pub async fn move_to_dead_letter_queue(&self, job_id: &str, queue_name: &str) -> Result<bool> {
-        let mut updates = HashMap::new();
-        updates.insert("status", JobStatus::Failed.to_string());
-        updates.insert("dead_letter_queue", queue_name.to_string());
-        self.db.update_job(job_id, &updates).await
+    pub async fn replay_dead_letter(
+        &self,
+        job_id: &str,
+        merchant_id: Option<Uuid>,
+        payload: Option<Value>,
+    ) -> Result<bool> {
+        self.db
+            .replay_dead_letter(job_id, merchant_id, payload)
+            .await
     }
 
-    pub async fn archive_job(&self, job_id: &str) -> Result<bool> {
-        let mut updates: HashMap<&str, String> = HashMap::new();
-        updates.insert("archived", "true".to_string());
-        self.db.update_job(job_id, &updates).await
+    pub async fn purge_dead_letter(&self, job_id: &str, merchant_id: Option<Uuid>) -> Result<bool> {
+        self.db.purge_dead_letter(job_id, merchant_id).await
+    }
+
+    pub async fn purge_dead_letters(&self, merchant_id: Option<Uuid>) -> Result<u64> {
+        self.db.purge_dead_letters(merchant_id).await
+    }
+
+    /// Looks a job up in the live table first, then in the archive.
+    pub async fn get_job_including_archived(&self, id: &str) -> Result<Option<Job>> {
+        match self.db.get_job(id).await? {
+            Some(job) => Ok(Some(job)),
+            None => self.db.get_archived_job(id).await,
+        }
+    }
+
+    pub async fn archive_jobs(&self, cutoff: DateTime<Utc>, limit: i64) -> Result<u64> {
+        self.db.archive_jobs(cutoff, limit).await
+    }
+
+    pub async fn purge_archived_jobs(&self, cutoff: DateTime<Utc>, limit: i64) -> Result<u64> {
+        self.db.purge_archived_jobs(cutoff, limit).await
     }
 }
 
-impl ToString for JobStatus {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for JobStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            JobStatus::Pending => "pending".to_string(),
-            JobStatus::Running => "running".to_string(),
-            JobStatus::Completed => "completed".to_string(),
-            JobStatus::Failed => "failed".to_string(),
-            JobStatus::Retrying => "retrying".to_string(),
+            JobStatus::Pending => write!(f, "pending"),
+            JobStatus::Queued => write!(f, "queued"),
+            JobStatus::Running => write!(f, "running"),
+            JobStatus::Completed => write!(f, "completed"),
+            JobStatus::Failed => write!(f, "failed"),
+            JobStatus::Retrying => write!(f, "retrying"),
+            JobStatus::Cancelled => write!(f, "cancelled"),
+            JobStatus::DeadLettered => write!(f, "dead_lettered"),
         }
     }
 }
@@ -0,0 +1,677 @@
+use crate::{
+    db::{Database, JobUpdate},
+    error::Error,
+    models::{JobStatus, UpstreamFailurePolicy},
+    retry::RetryPolicy,
+};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sqlx::{FromRow, Type};
+use std::collections::{HashMap, HashSet, VecDeque};
+use tracing::{error, info};
+use uuid::Uuid;
+
+/// A workflow as submitted through the API: named steps, the edges between
+/// them, and the input that fan-out steps iterate over.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowDefinition {
+    pub name: String,
+    pub merchant_id: Option<Uuid>,
+    #[serde(default)]
+    pub input: Value,
+    pub steps: Vec<WorkflowStep>,
+    #[serde(default)]
+    pub edges: Vec<WorkflowEdge>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowStep {
+    pub name: String,
+    pub payload: Value,
+    #[serde(default)]
+    pub priority: i32,
+    pub max_retries: Option<i32>,
+    pub retry_policy: Option<RetryPolicy>,
+    /// Only run this step if the condition holds for an upstream step's result.
+    pub when: Option<StepCondition>,
+    /// JSON pointer to an array in the workflow input; one job runs per item,
+    /// with the item added to the payload as `item`.
+    pub for_each: Option<String>,
+    pub on_upstream_failure: Option<UpstreamFailurePolicy>,
+}
+
+/// `to` runs after every job of `from` has finished.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowEdge {
+    pub from: String,
+    pub to: String,
+}
+
+/// A test against the result of a direct upstream step. The result looks like
+/// `{"status": "completed", "exit_code": 0, "output": ...}`, where `output` is
+/// the step's stdout, parsed as JSON when possible.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepCondition {
+    pub step: String,
+    /// JSON pointer into the upstream result, e.g. `/output/approved`.
+    pub path: String,
+    #[serde(flatten)]
+    pub test: ConditionTest,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConditionTest {
+    Equals(Value),
+    NotEquals(Value),
+    Exists(bool),
+}
+
+impl StepCondition {
+    pub fn evaluate(&self, result: &Value) -> bool {
+        let value = result.pointer(&self.path);
+        match &self.test {
+            ConditionTest::Equals(expected) => value == Some(expected),
+            ConditionTest::NotEquals(expected) => value != Some(expected),
+            ConditionTest::Exists(exists) => value.is_some() == *exists,
+        }
+    }
+}
+
+/// Builds the document a `StepCondition` is evaluated against.
+pub fn step_result(status: JobStatus, exit_code: Option<i32>, stdout: Option<&str>) -> Value {
+    let output = stdout.map(|stdout| {
+        serde_json::from_str(stdout.trim()).unwrap_or_else(|_| Value::String(stdout.to_string()))
+    });
+    json!({
+        "status": status.to_string(),
+        "exit_code": exit_code,
+        "output": output,
+    })
+}
+
+/// One job a workflow step expands into.
+#[derive(Debug, Clone)]
+pub struct PlannedJob {
+    pub step_name: String,
+    pub job_id: Uuid,
+    pub fan_out_index: Option<i32>,
+    pub payload: Value,
+    pub priority: i32,
+    pub max_retries: i32,
+    pub retry_policy: RetryPolicy,
+    pub depends_on: Vec<Uuid>,
+    pub on_upstream_failure: UpstreamFailurePolicy,
+    pub awaiting_condition: bool,
+}
+
+impl WorkflowDefinition {
+    /// Validates the definition and expands it into jobs, in dependency order.
+    pub fn plan(&self) -> Result<Vec<PlannedJob>, Error> {
+        if self.steps.is_empty() {
+            return Err(invalid("a workflow needs at least one step"));
+        }
+
+        let steps: HashMap<&str, &WorkflowStep> =
+            self.steps.iter().map(|s| (s.name.as_str(), s)).collect();
+        if steps.len() != self.steps.len() {
+            return Err(invalid("step names must be unique"));
+        }
+
+        let mut upstreams: HashMap<&str, Vec<&str>> = HashMap::new();
+        for edge in &self.edges {
+            if !steps.contains_key(edge.from.as_str()) || !steps.contains_key(edge.to.as_str()) {
+                return Err(invalid(&format!(
+                    "edge {} -> {} references an unknown step",
+                    edge.from, edge.to
+                )));
+            }
+            upstreams
+                .entry(edge.to.as_str())
+                .or_default()
+                .push(edge.from.as_str());
+        }
+
+        let mut planned = Vec::new();
+        let mut step_jobs: HashMap<&str, Vec<Uuid>> = HashMap::new();
+        for name in self.topological_order()? {
+            let step = steps[name];
+            let step_upstreams = upstreams.get(name).cloned().unwrap_or_default();
+            if let Some(policy) = &step.retry_policy {
+                policy.validate()?;
+            }
+
+            if let Some(condition) = &step.when {
+                if !step_upstreams.contains(&condition.step.as_str()) {
+                    return Err(invalid(&format!(
+                        "step {} has a condition on {}, which is not a direct upstream",
+                        name, condition.step
+                    )));
+                }
+                if steps[condition.step.as_str()].for_each.is_some() {
+                    return Err(invalid(&format!(
+                        "step {} cannot have a condition on fan-out step {}",
+                        name, condition.step
+                    )));
+                }
+            }
+
+            let depends_on: Vec<Uuid> = step_upstreams
+                .iter()
+                .flat_map(|upstream| step_jobs[upstream].iter().copied())
+                .collect();
+
+            let payloads = match &step.for_each {
+                Some(pointer) => self.fan_out(step, pointer)?,
+                None => vec![(None, step.payload.clone())],
+            };
+
+            let mut ids = Vec::with_capacity(payloads.len());
+            for (fan_out_index, payload) in payloads {
+                let job_id = Uuid::new_v4();
+                ids.push(job_id);
+                planned.push(PlannedJob {
+                    step_name: step.name.clone(),
+                    job_id,
+                    fan_out_index,
+                    payload,
+                    priority: step.priority,
+                    max_retries: step.max_retries.unwrap_or(3),
+                    retry_policy: step.retry_policy.clone().unwrap_or_default(),
+                    depends_on: depends_on.clone(),
+                    on_upstream_failure: step.on_upstream_failure.unwrap_or_default(),
+                    awaiting_condition: step.when.is_some(),
+                });
+            }
+            step_jobs.insert(name, ids);
+        }
+
+        Ok(planned)
+    }
+
+    /// Kahn's algorithm over the step graph; fails if the edges form a cycle.
+    fn topological_order(&self) -> Result<Vec<&str>, Error> {
+        let mut indegree: HashMap<&str, usize> =
+            self.steps.iter().map(|s| (s.name.as_str(), 0)).collect();
+        let mut downstreams: HashMap<&str, Vec<&str>> = HashMap::new();
+        let mut seen = HashSet::new();
+        for edge in &self.edges {
+            if !seen.insert((edge.from.as_str(), edge.to.as_str())) {
+                continue;
+            }
+            *indegree.get_mut(edge.to.as_str()).unwrap() += 1;
+            downstreams
+                .entry(edge.from.as_str())
+                .or_default()
+                .push(edge.to.as_str());
+        }
+
+        let mut ready: VecDeque<&str> = self
+            .steps
+            .iter()
+            .map(|s| s.name.as_str())
+            .filter(|name| indegree[name] == 0)
+            .collect();
+        let mut order = Vec::with_capacity(self.steps.len());
+        while let Some(name) = ready.pop_front() {
+            order.push(name);
+            for downstream in downstreams.get(name).into_iter().flatten() {
+                let degree = indegree.get_mut(downstream).unwrap();
+                *degree -= 1;
+                if *degree == 0 {
+                    ready.push_back(downstream);
+                }
+            }
+        }
+
+        if order.len() != self.steps.len() {
+            return Err(invalid("the workflow's edges form a cycle"));
+        }
+        Ok(order)
+    }
+
+    fn fan_out(
+        &self,
+        step: &WorkflowStep,
+        pointer: &str,
+    ) -> Result<Vec<(Option<i32>, Value)>, Error> {
+        let items = self
+            .input
+            .pointer(pointer)
+            .and_then(Value::as_array)
+            .ok_or_else(|| {
+                invalid(&format!(
+                    "for_each of step {} does not point to an array in the input",
+                    step.name
+                ))
+            })?;
+        let base = step.payload.as_object().ok_or_else(|| {
+            invalid(&format!(
+                "fan-out step {} needs an object payload",
+                step.name
+            ))
+        })?;
+
+        Ok(items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| {
+                let mut payload = base.clone();
+                payload.insert("item".to_string(), item.clone());
+                (Some(index as i32), Value::Object(payload))
+            })
+            .collect())
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::ValidationError(format!("Invalid workflow: {}", message))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Type)]
+#[sqlx(type_name = "workflow_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum WorkflowStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+/// A row of `workflow_runs`.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct WorkflowRun {
+    pub id: Uuid,
+    pub name: String,
+    pub merchant_id: Option<Uuid>,
+    pub status: WorkflowStatus,
+    pub input: Value,
+    pub definition: Value,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+/// A row of `workflow_steps` together with the current status of its job.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct WorkflowStepJob {
+    pub workflow_run_id: Uuid,
+    pub step_name: String,
+    pub job_id: Uuid,
+    pub fan_out_index: Option<i32>,
+    pub awaiting_condition: bool,
+    pub skipped: bool,
+    pub status: JobStatus,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkflowRunDetails {
+    #[serde(flatten)]
+    pub run: WorkflowRun,
+    pub steps: Vec<WorkflowStepJob>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WorkflowManager {
+    db: Database,
+}
+
+impl WorkflowManager {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    pub async fn create_workflow(&self, definition: &WorkflowDefinition) -> Result<Uuid> {
+        let jobs = definition.plan()?;
+        self.db.create_workflow_run(definition, &jobs).await
+    }
+
+    pub async fn get_workflow(&self, id: Uuid) -> Result<Option<WorkflowRunDetails>> {
+        let Some(run) = self.db.get_workflow_run(id).await? else {
+            return Ok(None);
+        };
+        let steps = self.db.get_workflow_steps(id).await?;
+        Ok(Some(WorkflowRunDetails { run, steps }))
+    }
+
+    /// Evaluates the conditions of steps whose upstream jobs have finished and
+    /// settles the aggregate status of runs whose jobs are all terminal.
+    pub async fn advance(&self, limit: i64) -> Result<()> {
+        for step in self.db.get_conditional_steps_ready(limit).await? {
+            if let Err(e) = self.evaluate_condition(&step).await {
+                error!(
+                    "Failed to evaluate condition of step {} (job {}): {}",
+                    step.step_name, step.job_id, e
+                );
+            }
+        }
+
+        let finished = self.db.finish_workflow_runs().await?;
+        if finished > 0 {
+            info!("Finished {} workflow run(s)", finished);
+        }
+        Ok(())
+    }
+
+    async fn evaluate_condition(&self, step: &WorkflowStepJob) -> Result<()> {
+        let run = self
+            .db
+            .get_workflow_run(step.workflow_run_id)
+            .await?
+            .ok_or_else(|| Error::NotFound(format!("Workflow run {}", step.workflow_run_id)))?;
+        let definition: WorkflowDefinition = serde_json::from_value(run.definition)?;
+        let Some(condition) = definition
+            .steps
+            .iter()
+            .find(|s| s.name == step.step_name)
+            .and_then(|s| s.when.as_ref())
+        else {
+            self.db.release_workflow_step(step.job_id).await?;
+            return Ok(());
+        };
+
+        let result = match self
+            .db
+            .get_step_result(step.workflow_run_id, &condition.step)
+            .await?
+        {
+            Some((status, exit_code, stdout)) => step_result(status, exit_code, stdout.as_deref()),
+            None => Value::Null,
+        };
+
+        let job_id = step.job_id.to_string();
+        if condition.evaluate(&result) {
+            self.db.release_workflow_step(step.job_id).await?;
+            info!(
+                "Condition of step {} holds; job {} released",
+                step.step_name, job_id
+            );
+        } else {
+            let update = JobUpdate::new().last_error(Some(format!(
+                "Skipped: condition on step {} did not hold",
+                condition.step
+            )));
+            self.db
+                .transition_job(&job_id, JobStatus::Pending, JobStatus::Cancelled, &update)
+                .await?;
+            self.db.skip_workflow_step(step.job_id).await?;
+            info!(
+                "Condition of step {} failed; job {} skipped",
+                step.step_name, job_id
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn definition(definition: Value) -> WorkflowDefinition {
+        serde_json::from_value(definition).unwrap()
+    }
+
+    fn step(name: &str) -> Value {
+        json!({"name": name, "payload": {"command": name}})
+    }
+
+    fn jobs_of<'a>(planned: &'a [PlannedJob], step: &str) -> Vec<&'a PlannedJob> {
+        planned.iter().filter(|job| job.step_name == step).collect()
+    }
+
+    fn rejects(workflow: Value, message: &str) {
+        let error = definition(workflow).plan().unwrap_err().to_string();
+        assert!(error.contains(message), "{error:?} lacks {message:?}");
+    }
+
+    #[test]
+    fn plans_steps_after_their_upstreams() {
+        let planned = definition(json!({
+            "name": "diamond",
+            "steps": [step("d"), step("c"), step("b"), step("a")],
+            "edges": [
+                {"from": "a", "to": "b"},
+                {"from": "a", "to": "c"},
+                {"from": "b", "to": "d"},
+                {"from": "c", "to": "d"},
+            ],
+        }))
+        .plan()
+        .unwrap();
+
+        let position = |step: &str| planned.iter().position(|job| job.step_name == step);
+        assert_eq!(position("a"), Some(0));
+        assert!(position("b") < position("d"));
+        assert!(position("c") < position("d"));
+
+        let a = jobs_of(&planned, "a")[0];
+        let b = jobs_of(&planned, "b")[0];
+        let c = jobs_of(&planned, "c")[0];
+        let d = jobs_of(&planned, "d")[0];
+        assert!(a.depends_on.is_empty());
+        assert_eq!(b.depends_on, [a.job_id]);
+        assert_eq!(c.depends_on, [a.job_id]);
+        let mut upstreams = d.depends_on.clone();
+        upstreams.sort();
+        let mut expected = vec![b.job_id, c.job_id];
+        expected.sort();
+        assert_eq!(upstreams, expected);
+    }
+
+    #[test]
+    fn applies_step_defaults() {
+        let planned = definition(json!({"name": "one", "steps": [step("a")]}))
+            .plan()
+            .unwrap();
+
+        assert_eq!(planned.len(), 1);
+        assert_eq!(planned[0].max_retries, 3);
+        assert_eq!(planned[0].fan_out_index, None);
+        assert_eq!(planned[0].payload, json!({"command": "a"}));
+        assert!(!planned[0].awaiting_condition);
+    }
+
+    #[test]
+    fn tolerates_duplicate_edges() {
+        let planned = definition(json!({
+            "name": "twice",
+            "steps": [step("a"), step("b")],
+            "edges": [{"from": "a", "to": "b"}, {"from": "a", "to": "b"}],
+        }))
+        .plan()
+        .unwrap();
+
+        assert_eq!(planned.len(), 2);
+        assert_eq!(planned[0].step_name, "a");
+    }
+
+    #[test]
+    fn rejects_cycles() {
+        rejects(
+            json!({
+                "name": "cycle",
+                "steps": [step("a"), step("b"), step("c")],
+                "edges": [
+                    {"from": "a", "to": "b"},
+                    {"from": "b", "to": "c"},
+                    {"from": "c", "to": "a"},
+                ],
+            }),
+            "form a cycle",
+        );
+        rejects(
+            json!({
+                "name": "self",
+                "steps": [step("a")],
+                "edges": [{"from": "a", "to": "a"}],
+            }),
+            "form a cycle",
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_graphs() {
+        rejects(json!({"name": "empty", "steps": []}), "at least one step");
+        rejects(
+            json!({"name": "dupe", "steps": [step("a"), step("a")]}),
+            "unique",
+        );
+        rejects(
+            json!({
+                "name": "dangling",
+                "steps": [step("a")],
+                "edges": [{"from": "a", "to": "b"}],
+            }),
+            "unknown step",
+        );
+    }
+
+    #[test]
+    fn fans_out_over_input_items() {
+        let planned = definition(json!({
+            "name": "fan",
+            "input": {"merchants": ["m1", "m2", "m3"]},
+            "steps": [
+                {"name": "each", "payload": {"command": "bill"}, "for_each": "/merchants"},
+                step("report"),
+            ],
+            "edges": [{"from": "each", "to": "report"}],
+        }))
+        .plan()
+        .unwrap();
+
+        let each = jobs_of(&planned, "each");
+        assert_eq!(each.len(), 3);
+        for (index, job) in each.iter().enumerate() {
+            assert_eq!(job.fan_out_index, Some(index as i32));
+            assert_eq!(job.payload["command"], "bill");
+            assert_eq!(job.payload["item"], json!(format!("m{}", index + 1)));
+        }
+
+        let report = jobs_of(&planned, "report")[0];
+        assert_eq!(report.depends_on.len(), 3);
+        assert!(
+            each.iter()
+                .all(|job| report.depends_on.contains(&job.job_id))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_fan_out() {
+        rejects(
+            json!({
+                "name": "fan",
+                "input": {"merchants": "m1"},
+                "steps": [{"name": "each", "payload": {}, "for_each": "/merchants"}],
+            }),
+            "does not point to an array",
+        );
+        rejects(
+            json!({
+                "name": "fan",
+                "input": {"merchants": ["m1"]},
+                "steps": [{"name": "each", "payload": "bill", "for_each": "/merchants"}],
+            }),
+            "needs an object payload",
+        );
+    }
+
+    #[test]
+    fn marks_conditional_steps() {
+        let planned = definition(json!({
+            "name": "approve",
+            "steps": [
+                step("check"),
+                {
+                    "name": "pay",
+                    "payload": {},
+                    "when": {"step": "check", "path": "/output/approved", "equals": true},
+                },
+            ],
+            "edges": [{"from": "check", "to": "pay"}],
+        }))
+        .plan()
+        .unwrap();
+
+        assert!(!jobs_of(&planned, "check")[0].awaiting_condition);
+        assert!(jobs_of(&planned, "pay")[0].awaiting_condition);
+    }
+
+    #[test]
+    fn rejects_conditions_on_other_steps() {
+        rejects(
+            json!({
+                "name": "indirect",
+                "steps": [
+                    step("a"),
+                    step("b"),
+                    {"name": "c", "payload": {}, "when": {"step": "a", "path": "/status", "exists": true}},
+                ],
+                "edges": [{"from": "a", "to": "b"}, {"from": "b", "to": "c"}],
+            }),
+            "not a direct upstream",
+        );
+        rejects(
+            json!({
+                "name": "fan",
+                "input": {"items": [1, 2]},
+                "steps": [
+                    {"name": "a", "payload": {}, "for_each": "/items"},
+                    {"name": "b", "payload": {}, "when": {"step": "a", "path": "/status", "exists": true}},
+                ],
+                "edges": [{"from": "a", "to": "b"}],
+            }),
+            "fan-out step",
+        );
+    }
+
+    /// Whether `test` holds for what `path` points at in `result`.
+    fn holds(result: &Value, path: &str, test: Value) -> bool {
+        let mut condition = json!({"step": "check", "path": path});
+        condition
+            .as_object_mut()
+            .unwrap()
+            .extend(test.as_object().unwrap().clone());
+        serde_json::from_value::<StepCondition>(condition)
+            .unwrap()
+            .evaluate(result)
+    }
+
+    #[test]
+    fn evaluates_conditions() {
+        let result = step_result(
+            JobStatus::Completed,
+            Some(0),
+            Some("{\"approved\": true}\n"),
+        );
+
+        let approved = |test| holds(&result, "/output/approved", test);
+        assert!(approved(json!({"equals": true})));
+        assert!(!approved(json!({"equals": false})));
+        assert!(approved(json!({"not_equals": false})));
+        assert!(!approved(json!({"not_equals": true})));
+        assert!(approved(json!({"exists": true})));
+        assert!(!approved(json!({"exists": false})));
+        assert!(holds(&result, "/status", json!({"equals": "completed"})));
+
+        let missing = |test| holds(&result, "/output/missing", test);
+        assert!(!missing(json!({"equals": null})));
+        assert!(missing(json!({"not_equals": null})));
+        assert!(missing(json!({"exists": false})));
+    }
+
+    #[test]
+    fn keeps_output_that_is_not_json_as_a_string() {
+        let result = step_result(JobStatus::Failed, Some(1), Some("not json"));
+
+        assert_eq!(result["status"], "failed");
+        assert_eq!(result["exit_code"], 1);
+        assert_eq!(result["output"], "not json");
+        assert_eq!(
+            step_result(JobStatus::Cancelled, None, None)["output"],
+            Value::Null
+        );
+    }
+}
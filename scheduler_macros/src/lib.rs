@@ -0,0 +1,171 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, spanned::Spanned, Error, FnArg, ItemFn, LitInt, LitStr};
+
+/// Declares an `async fn` as the handler for one job `kind`, and gives
+/// producers a typed way to enqueue it.
+///
+/// ```ignore
+/// #[task(name = "send_invoice", retries = 5)]
+/// async fn send_invoice(args: Invoice) -> anyhow::Result<Receipt> {
+///     ...
+/// }
+///
+/// // Consumer
+/// let handlers = send_invoice::register(TaskRegistry::new());
+///
+/// // Producer
+/// send_invoice::enqueue(&task_manager, invoice, Utc::now()).await?;
+/// ```
+///
+/// The function takes the job's arguments as its last parameter, optionally
+/// preceded by a [`TaskContext`](../task_executor/struct.TaskContext.html).
+/// It returns a `Result` whose value is recorded as the run's result and
+/// whose error converts into a `TaskError`.
+///
+/// Next to the function, the attribute generates a module of the same name
+/// with the job `NAME`, the `Handler` running it, `register` to add it to a
+/// `TaskRegistry`, and `enqueue` to create a one-time job whose payload holds
+/// the serialized arguments. `enqueue_with_origin` does the same for a
+/// `JobOrigin`, i.e. a merchant and an idempotency key. `name` defaults to
+/// the function's name and `retries` to the scheduler's default.
+#[proc_macro_attribute]
+pub fn task(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut name: Option<LitStr> = None;
+    let mut retries: Option<LitInt> = None;
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("name") {
+            name = Some(meta.value()?.parse()?);
+            Ok(())
+        } else if meta.path.is_ident("retries") {
+            retries = Some(meta.value()?.parse()?);
+            Ok(())
+        } else {
+            Err(meta.error("expected `name` or `retries`"))
+        }
+    });
+    parse_macro_input!(attr with parser);
+
+    let function = parse_macro_input!(item as ItemFn);
+    expand(function, name, retries)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(
+    function: ItemFn,
+    name: Option<LitStr>,
+    retries: Option<LitInt>,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let signature = &function.sig;
+    if signature.asyncness.is_none() {
+        return Err(Error::new(
+            signature.fn_token.span(),
+            "#[task] functions must be async",
+        ));
+    }
+    if !signature.generics.params.is_empty() {
+        return Err(Error::new(
+            signature.generics.span(),
+            "#[task] functions cannot be generic",
+        ));
+    }
+
+    let mut inputs = Vec::new();
+    for input in &signature.inputs {
+        match input {
+            FnArg::Typed(input) => inputs.push(input),
+            FnArg::Receiver(receiver) => {
+                return Err(Error::new(
+                    receiver.span(),
+                    "#[task] functions cannot take self",
+                ))
+            }
+        }
+    }
+    let (context, args) = match inputs.as_slice() {
+        [args] => (None, args),
+        [context, args] => (Some(context), args),
+        _ => {
+            return Err(Error::new(
+                signature.inputs.span(),
+                "#[task] functions take their arguments, optionally preceded by a TaskContext",
+            ))
+        }
+    };
+    if let Some(retries) = &retries {
+        retries.base10_parse::<i32>()?;
+    }
+
+    let ident = &signature.ident;
+    let vis = &function.vis;
+    let name = name.unwrap_or_else(|| LitStr::new(&ident.to_string(), ident.span()));
+    let args_type = &args.ty;
+    // The context is only bound when the function asks for it
+    let (ctx, context) = match context {
+        Some(_) => (quote!(ctx), Some(quote!(ctx,))),
+        None => (quote!(_ctx), None),
+    };
+    let retries = match retries {
+        Some(retries) => quote!(::core::option::Option::Some(#retries)),
+        None => quote!(::core::option::Option::None),
+    };
+    let doc = format!(
+        "Handler and producer for [`{}`](fn@super::{}) jobs.",
+        name.value(),
+        ident
+    );
+    let private = quote!(::task_executor::__private);
+
+    Ok(quote! {
+        #function
+
+        #[doc = #doc]
+        #vis mod #ident {
+            #[allow(unused_imports)]
+            use super::*;
+
+            /// The job `kind` this task runs.
+            pub const NAME: &str = #name;
+
+            pub struct Handler;
+
+            #[#private::async_trait]
+            impl ::task_executor::TaskHandler for Handler {
+                async fn handle(
+                    &self,
+                    #ctx: ::task_executor::TaskContext,
+                    payload: #private::serde_json::Value,
+                ) -> ::core::result::Result<#private::serde_json::Value, ::task_executor::TaskError> {
+                    let args: #args_type = #private::args(NAME, payload)?;
+                    #private::TaskOutput::into_output(super::#ident(#context args).await)
+                }
+            }
+
+            /// Runs jobs of this kind with [`Handler`].
+            pub fn register(registry: ::task_executor::TaskRegistry) -> ::task_executor::TaskRegistry {
+                registry.register(NAME, Handler)
+            }
+
+            /// Creates a one-time job of this kind, scheduled for `at`.
+            pub async fn enqueue(
+                client: &#private::scheduler_core::task::TaskManager,
+                args: #args_type,
+                at: #private::chrono::DateTime<#private::chrono::Utc>,
+            ) -> #private::anyhow::Result<#private::scheduler_core::db::CreatedJob> {
+                enqueue_with_origin(client, args, at, ::core::default::Default::default()).await
+            }
+
+            /// Like [`enqueue`], on behalf of `origin`'s merchant, and only
+            /// once per idempotency key if `origin` has one.
+            pub async fn enqueue_with_origin(
+                client: &#private::scheduler_core::task::TaskManager,
+                args: #args_type,
+                at: #private::chrono::DateTime<#private::chrono::Utc>,
+                origin: #private::scheduler_core::db::JobOrigin,
+            ) -> #private::anyhow::Result<#private::scheduler_core::db::CreatedJob> {
+                #private::enqueue(client, NAME, &args, at, #retries, origin).await
+            }
+        }
+    })
+}
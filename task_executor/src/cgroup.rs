@@ -0,0 +1,166 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io,
+    path::PathBuf,
+    time::Duration,
+};
+use tracing::warn;
+
+/// Scheduling period `cpu.max` quotas are expressed against.
+const CPU_PERIOD_USEC: u64 = 100_000;
+
+/// Controllers every job cgroup is limited by.
+const CONTROLLERS: [&str; 3] = ["cpu", "memory", "pids"];
+
+/// Limits applied to a job's cgroup. `None` leaves a resource unlimited.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CgroupLimits {
+    pub memory_max_bytes: Option<u64>,
+    /// Share of one CPU, in percent.
+    pub cpu_percent: Option<u32>,
+    pub pids_max: Option<u64>,
+}
+
+/// What a job's processes consumed, as accounted by its cgroup.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResourceUsage {
+    pub peak_memory_bytes: Option<u64>,
+    pub cpu_time: Option<Duration>,
+}
+
+/// Creates one cgroup v2 group per job run under a delegated directory.
+pub struct CgroupManager {
+    root: PathBuf,
+}
+
+impl CgroupManager {
+    /// Prepares `root` to hold job cgroups, enabling the cpu, memory and pids
+    /// controllers for them. Fails if `root` is not on a writable cgroup v2
+    /// hierarchy that offers those controllers.
+    pub fn new(root: impl Into<PathBuf>) -> io::Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(&root)?;
+
+        let available = fs::read_to_string(root.join("cgroup.controllers"))?;
+        for controller in CONTROLLERS {
+            if !available.split_whitespace().any(|c| c == controller) {
+                return Err(io::Error::other(format!(
+                    "{} controller is not available in {}",
+                    controller,
+                    root.display()
+                )));
+            }
+        }
+        let enable: Vec<String> = CONTROLLERS.iter().map(|c| format!("+{}", c)).collect();
+        fs::write(root.join("cgroup.subtree_control"), enable.join(" "))?;
+
+        Ok(Self { root })
+    }
+
+    pub fn create(&self, name: &str, limits: &CgroupLimits) -> io::Result<JobCgroup> {
+        let cgroup = JobCgroup {
+            path: self.root.join(name),
+        };
+        fs::create_dir(&cgroup.path)?;
+
+        let configured = cgroup.configure(limits);
+        if configured.is_err() {
+            let _ = fs::remove_dir(&cgroup.path);
+        }
+        configured.map(|()| cgroup)
+    }
+}
+
+/// The cgroup of a single job run.
+pub struct JobCgroup {
+    path: PathBuf,
+}
+
+impl JobCgroup {
+    fn configure(&self, limits: &CgroupLimits) -> io::Result<()> {
+        self.write(
+            "memory.max",
+            &limits
+                .memory_max_bytes
+                .map_or_else(|| "max".to_string(), |bytes| bytes.to_string()),
+        )?;
+        let quota = limits.cpu_percent.map_or_else(
+            || "max".to_string(),
+            |percent| {
+                (u64::from(percent) * CPU_PERIOD_USEC / 100)
+                    .max(1000)
+                    .to_string()
+            },
+        );
+        self.write("cpu.max", &format!("{} {}", quota, CPU_PERIOD_USEC))?;
+        self.write(
+            "pids.max",
+            &limits
+                .pids_max
+                .map_or_else(|| "max".to_string(), |pids| pids.to_string()),
+        )
+    }
+
+    /// Opens `cgroup.procs` for the child to join the cgroup with between
+    /// fork and exec, so everything it spawns is limited and accounted too.
+    pub fn procs_file(&self) -> io::Result<File> {
+        OpenOptions::new()
+            .write(true)
+            .open(self.path.join("cgroup.procs"))
+    }
+
+    /// Peak memory and CPU time so far. Values the kernel does not expose,
+    /// e.g. `memory.peak` before Linux 5.19, are left out.
+    pub fn usage(&self) -> ResourceUsage {
+        let peak_memory_bytes = fs::read_to_string(self.path.join("memory.peak"))
+            .ok()
+            .and_then(|peak| peak.trim().parse().ok());
+        let cpu_time = fs::read_to_string(self.path.join("cpu.stat"))
+            .ok()
+            .and_then(|stat| {
+                stat.lines()
+                    .find_map(|line| line.strip_prefix("usage_usec "))
+                    .and_then(|usec| usec.trim().parse().ok())
+            })
+            .map(Duration::from_micros);
+
+        ResourceUsage {
+            peak_memory_bytes,
+            cpu_time,
+        }
+    }
+
+    /// Kills every process in the cgroup, including those that left the
+    /// command's process group.
+    pub fn kill(&self) {
+        // cgroup.kill exists from Linux 5.14; the process group kill already
+        // covered everything that did not leave the group
+        let _ = self.write("cgroup.kill", "1");
+    }
+
+    /// Kills whatever is left in the cgroup and removes it.
+    pub async fn remove(self) {
+        self.kill();
+        for _ in 0..50 {
+            match fs::remove_dir(&self.path) {
+                Ok(()) => return,
+                // Killed processes take a moment to leave the cgroup
+                Err(e) if e.raw_os_error() == Some(libc::EBUSY) => {
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                }
+                Err(e) => {
+                    warn!("Failed to remove cgroup {}: {}", self.path.display(), e);
+                    return;
+                }
+            }
+        }
+        warn!(
+            "Cgroup {} still has processes; leaving it behind",
+            self.path.display()
+        );
+    }
+
+    fn write(&self, file: &str, value: &str) -> io::Result<()> {
+        fs::write(self.path.join(file), value)
+    }
+}
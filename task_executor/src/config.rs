@@ -0,0 +1,77 @@
+use crate::sandbox::SandboxProfile;
+use scheduler_core::config::Config;
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::warn;
+
+#[derive(Debug, Clone)]
+pub struct ExecutorConfig {
+    pub timeout: Duration,
+    /// Time between SIGTERM and SIGKILL when stopping a job's processes.
+    pub kill_grace: Duration,
+    pub max_memory_mb: u64,
+    pub max_cpu_percent: u32,
+    pub max_pids: u64,
+    /// Where per-job cgroups are created; `None` limits jobs with rlimits.
+    pub cgroup_root: Option<PathBuf>,
+    /// Sandbox profiles jobs may ask for; any other is refused.
+    pub sandbox_profiles: Vec<SandboxProfile>,
+    /// Interpreters script jobs may ask for; any other is refused.
+    pub script_interpreters: Vec<String>,
+    pub concurrency_limit: usize,
+    pub visibility_timeout: Duration,
+    pub lease_ttl: Duration,
+    /// How long `TaskExecutor::shutdown` waits for running jobs.
+    pub shutdown_grace: Duration,
+    /// Job kinds this executor registers as able to run.
+    pub capabilities: Vec<String>,
+}
+
+impl ExecutorConfig {
+    pub fn from_core_config(config: &Config) -> Self {
+        let sandbox_profiles: Vec<SandboxProfile> = config
+            .sandbox_profiles
+            .iter()
+            .filter_map(|name| {
+                let profile = SandboxProfile::builtin(name, config.sandbox_uid, config.sandbox_gid);
+                if profile.is_none() {
+                    warn!(
+                        "Ignoring unknown sandbox profile {}, expected one of {:?}",
+                        name,
+                        SandboxProfile::BUILTIN
+                    );
+                }
+                profile
+            })
+            .collect();
+        // Advertised so operators can see which workers run which profiles
+        let mut capabilities = vec!["shell".to_string(), "http".to_string()];
+        capabilities.extend(
+            sandbox_profiles
+                .iter()
+                .map(|profile| format!("sandbox:{}", profile.name)),
+        );
+        capabilities.extend(
+            config
+                .script_interpreters
+                .iter()
+                .map(|interpreter| format!("script:{}", interpreter)),
+        );
+
+        Self {
+            timeout: Duration::from_secs(300), // 5 minute timeout
+            kill_grace: Duration::from_secs(config.kill_grace_secs),
+            max_memory_mb: 1024, // 1GB memory limit
+            max_cpu_percent: 50, // 50% CPU limit
+            max_pids: 1024,      // 1024 processes per job
+            cgroup_root: config.cgroup_root.as_ref().map(PathBuf::from),
+            sandbox_profiles,
+            script_interpreters: config.script_interpreters.clone(),
+            concurrency_limit: 10, // 10 concurrent jobs
+            visibility_timeout: Duration::from_secs(config.visibility_timeout_secs),
+            lease_ttl: Duration::from_secs(config.lease_ttl_secs),
+            shutdown_grace: Duration::from_secs(config.shutdown_grace_secs),
+            capabilities,
+        }
+    }
+}
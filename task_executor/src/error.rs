@@ -11,21 +11,92 @@ pub enum Error {
     #[error("Process execution error: {0}")]
     Process(String),
 
-    #[error("Timeout error: {0}")]
-    Timeout(String),
+    #[error("Command exited unsuccessfully: {0}")]
+    ExitStatus(String),
+
+    #[error("Timed out: {0}")]
+    TimedOut(String),
+
+    #[error("Cancelled: {0}")]
+    Cancelled(String),
+
+    #[error("Interrupted: {0}")]
+    Interrupted(String),
 
     #[error("Resource limit exceeded: {0}")]
     ResourceLimit(String),
 
+    #[error("Task failed: {message}")]
+    Task { message: String, permanent: bool },
+
+    #[error("HTTP request error: {0}")]
+    Http(String),
+
+    #[error("Endpoint answered with status {status}")]
+    HttpStatus { status: u16, retryable: bool },
+
+    #[error("Sandbox error: {0}")]
+    Sandbox(String),
+
+    #[error("Script error: {0}")]
+    Script(String),
+
     #[error("State transition error: {0}")]
     StateTransition(String),
 
     #[error("Configuration error: {0}")]
     Config(String),
 
+    /// The job needs a kind, interpreter or sandbox profile this executor
+    /// does not offer; `capability` is the one a worker that can run it
+    /// advertises.
+    #[error("Unsupported job: {message}")]
+    Unsupported { capability: String, message: String },
+
     #[error("Serialization error: {0}")]
     Serialization(#[from] serde_json::Error),
 
     #[error("Internal error: {0}")]
     Internal(#[from] anyhow::Error),
 }
+
+impl Error {
+    /// Short, stable name recorded as a run's `error_class`.
+    pub fn class(&self) -> &'static str {
+        match self {
+            Error::Database(_) => "database",
+            Error::Cache(_) => "cache",
+            Error::Process(_) => "process",
+            Error::ExitStatus(_) => "exit_status",
+            Error::TimedOut(_) => "timed_out",
+            Error::Cancelled(_) => "cancelled",
+            Error::Interrupted(_) => "interrupted",
+            Error::ResourceLimit(_) => "resource_limit",
+            Error::Task { .. } => "task",
+            Error::Http(_) => "http",
+            Error::HttpStatus { .. } => "http_status",
+            Error::Sandbox(_) => "sandbox",
+            Error::Script(_) => "script",
+            Error::StateTransition(_) => "state_transition",
+            Error::Config(_) => "config",
+            Error::Unsupported { .. } => "unsupported",
+            Error::Serialization(_) => "serialization",
+            Error::Internal(_) => "internal",
+        }
+    }
+
+    /// Whether retrying cannot help, so the job should skip the attempts it
+    /// has left.
+    pub fn is_permanent(&self) -> bool {
+        matches!(
+            self,
+            Error::Task {
+                permanent: true,
+                ..
+            } | Error::HttpStatus {
+                retryable: false,
+                ..
+            }
+        )
+    }
+}
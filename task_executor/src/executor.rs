@@ -1,68 +1,203 @@
-use std::sync::Arc;
+use chrono::Utc;
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::time::Duration;
-use tokio::sync::Semaphore;
-use tracing::{error, info};
+use tokio::sync::{watch, Notify, Semaphore};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+use uuid::Uuid;
 
 use scheduler_core::{
-    cache::Cache,
-    db::Database,
-    models::{Job, JobStatus},
+    cache::{Cache, CANCEL_CHANNEL, JOB_QUEUE},
+    db::{Database, JobUpdate, WorkerData},
+    models::{DeadLetterReason, Job, JobStatus},
+    SchedulerError,
 };
 
-use crate::{error::Error, process::ProcessManager, state::ExecutionState};
+use crate::{
+    config::ExecutorConfig,
+    error::Error,
+    handler::{self, JobLease, TaskContext, TaskHandler, TaskRegistry},
+    http::{HttpClient, HttpRequest},
+    process::ProcessManager,
+    script::Script,
+    state::ExecutionState,
+};
+
+/// Times a job is handed back to the queue by workers that cannot run it
+/// before it is dead-lettered.
+const MAX_BOUNCES: i32 = 10;
 
 #[derive(Clone)]
 pub struct TaskExecutor {
     db: Arc<Database>,
     cache: Arc<Cache>,
     process_manager: Arc<ProcessManager>,
+    http_client: HttpClient,
+    handlers: Arc<TaskRegistry>,
+    /// How long a handler may run, see `handler::run`.
+    timeout: Duration,
+    kill_grace: Duration,
     concurrency_limit: usize,
     semaphore: Arc<Semaphore>,
+    worker_id: String,
+    registration: WorkerData,
+    visibility_timeout: Duration,
+    lease_ttl: Duration,
+    shutdown_grace: Duration,
+    draining: Arc<AtomicBool>,
+    shutdown: Arc<watch::Sender<bool>>,
+    background: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    running: Arc<Mutex<HashMap<String, Arc<JobSignals>>>>,
+}
+
+/// Per-job state shared between the job's task, the cancellation listener
+/// and the heartbeat loop.
+#[derive(Default)]
+struct JobSignals {
+    cancelled: Notify,
+    /// The fencing token of this worker's claim, set once it moved the job
+    /// to `Running` and holds its lease.
+    lease: OnceLock<i64>,
+    /// Set when `cancelled` fires because the executor is shutting down
+    /// rather than because the job was cancelled.
+    interrupted: AtomicBool,
+}
+
+/// Keeps a job reachable by cancellation signals for as long as it runs.
+struct RunningJob {
+    running: Arc<Mutex<HashMap<String, Arc<JobSignals>>>>,
+    job_id: String,
+    signals: Arc<JobSignals>,
+}
+
+impl Drop for RunningJob {
+    fn drop(&mut self) {
+        self.running.lock().unwrap().remove(&self.job_id);
+    }
 }
 
 impl TaskExecutor {
-    pub async fn new(
-        db: Database,
-        cache: Cache,
-        timeout: Duration,
-        max_memory_mb: u64,
-        max_cpu_percent: u32,
-        concurrency_limit: usize,
-    ) -> Result<Self, Error> {
-        let process_manager = ProcessManager::new(timeout, max_memory_mb, max_cpu_percent);
+    pub async fn new(db: Database, cache: Cache, config: ExecutorConfig) -> Result<Self, Error> {
+        let process_manager = ProcessManager::new(
+            config.timeout,
+            config.kill_grace,
+            config.max_memory_mb,
+            config.max_cpu_percent,
+            config.max_pids,
+            config.cgroup_root.clone(),
+            config.sandbox_profiles.clone(),
+            config.script_interpreters.clone(),
+        );
         process_manager.validate_resources()?;
 
+        let hostname = sys_info::hostname().unwrap_or_else(|_| "unknown".to_string());
+        let worker_id = format!("{}-{}", hostname, uuid::Uuid::new_v4());
+        let registration = WorkerData {
+            id: worker_id.clone(),
+            hostname,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            capabilities: config.capabilities,
+            concurrency_limit: config.concurrency_limit as i32,
+        };
+
         Ok(Self {
             db: Arc::new(db),
             cache: Arc::new(cache),
             process_manager: Arc::new(process_manager),
-            concurrency_limit,
-            semaphore: Arc::new(Semaphore::new(concurrency_limit)),
+            http_client: HttpClient::new(config.timeout),
+            handlers: Arc::new(TaskRegistry::new()),
+            timeout: config.timeout,
+            kill_grace: config.kill_grace,
+            concurrency_limit: config.concurrency_limit,
+            semaphore: Arc::new(Semaphore::new(config.concurrency_limit)),
+            worker_id,
+            registration,
+            visibility_timeout: config.visibility_timeout,
+            lease_ttl: config.lease_ttl,
+            shutdown_grace: config.shutdown_grace,
+            draining: Arc::new(AtomicBool::new(false)),
+            shutdown: Arc::new(watch::channel(false).0),
+            background: Arc::new(Mutex::new(Vec::new())),
+            running: Arc::new(Mutex::new(HashMap::new())),
         })
     }
 
+    /// Runs jobs whose `kind` has a handler in `handlers` in-process, and
+    /// advertises those kinds as capabilities of the worker.
+    pub fn with_handlers(mut self, handlers: TaskRegistry) -> Self {
+        for kind in handlers.kinds() {
+            if !self.registration.capabilities.iter().any(|c| c == kind) {
+                self.registration.capabilities.push(kind.to_string());
+            }
+        }
+        self.handlers = Arc::new(handlers);
+        self
+    }
+
+    pub fn worker_id(&self) -> &str {
+        &self.worker_id
+    }
+
+    /// Removes this executor from the worker registry.
+    pub async fn deregister(&self) -> Result<(), Error> {
+        self.db.deregister_worker(&self.worker_id).await?;
+        Ok(())
+    }
+
     pub async fn start(&self) -> Result<(), Error> {
         info!(
-            "Starting task executor with concurrency limit: {}",
-            self.concurrency_limit
+            "Starting task executor {} with concurrency limit: {}",
+            self.worker_id, self.concurrency_limit
         );
 
+        self.db
+            .register_worker(&self.registration, self.lease_ttl)
+            .await?;
+        self.background.lock().unwrap().extend([
+            tokio::spawn(self.clone().send_heartbeats()),
+            tokio::spawn(self.clone().listen_for_cancellations()),
+        ]);
+
+        let mut shutdown = self.shutdown.subscribe();
         loop {
-            // Wait for a permit before processing next job
-            let permit =
-                self.semaphore.acquire().await.map_err(|e| {
+            // A draining worker only finishes the jobs it already has
+            if self.draining.load(Ordering::SeqCst) {
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_secs(1)) => continue,
+                    _ = shutdown.wait_for(|stopping| *stopping) => break,
+                }
+            }
+
+            // Wait for a permit before processing next job; the job's task
+            // holds it until the job's outcome is persisted
+            let permit = tokio::select! {
+                permit = self.semaphore.clone().acquire_owned() => permit.map_err(|e| {
                     Error::ResourceLimit(format!("Failed to acquire semaphore: {}", e))
-                })?;
+                })?,
+                _ = shutdown.wait_for(|stopping| *stopping) => break,
+            };
 
             // Try to get next job from queue
             match self.get_next_job().await {
                 Ok(Some(job)) => {
                     let executor = self.clone();
                     tokio::spawn(async move {
+                        let job_id = job.id.to_string();
                         if let Err(e) = executor.execute_job(job).await {
                             error!("Failed to execute job: {}", e);
+                            // Hand the job back so another worker can pick it up
+                            if let Err(e) = executor
+                                .cache
+                                .nack(JOB_QUEUE, &executor.worker_id, &job_id)
+                                .await
+                            {
+                                error!("Failed to nack job {}: {}", job_id, e);
+                            }
                         }
-                        // Permit is automatically released when the task completes
+                        drop(permit);
                     });
                 }
                 Ok(None) => {
@@ -77,110 +212,546 @@ impl TaskExecutor {
                 }
             }
         }
+
+        info!("Executor {} stopped taking new jobs", self.worker_id);
+        Ok(())
+    }
+
+    /// Stops taking new jobs and waits up to the grace period for running
+    /// ones. Jobs still running after that are killed and requeued without
+    /// counting the attempt, before the worker leaves the registry.
+    pub async fn shutdown(&self) -> Result<(), Error> {
+        info!(
+            "Shutting down executor {}, waiting up to {:?} for running jobs",
+            self.worker_id, self.shutdown_grace
+        );
+        self.shutdown.send_replace(true);
+
+        // Every permit back means every job task has finished
+        let all_permits = self.concurrency_limit as u32;
+        if tokio::time::timeout(
+            self.shutdown_grace,
+            self.semaphore.acquire_many(all_permits),
+        )
+        .await
+        .is_err()
+        {
+            let running = self.running.lock().unwrap().clone();
+            warn!(
+                "Interrupting {} jobs still running after the grace period",
+                running.len()
+            );
+            for signals in running.values() {
+                signals.interrupted.store(true, Ordering::SeqCst);
+                signals.cancelled.notify_one();
+            }
+            let _ = self.semaphore.acquire_many(all_permits).await;
+        }
+
+        for task in self.background.lock().unwrap().drain(..) {
+            task.abort();
+        }
+        self.deregister().await?;
+        info!("Executor {} shut down", self.worker_id);
+        Ok(())
     }
 
     async fn get_next_job(&self) -> Result<Option<Job>, Error> {
-        if let Some(job_id) = self.cache.pop_from_queue("jobs").await? {
-            if let Some(job_data) = self.db.get_job(&job_id).await? {
-                // Convert HashMap to Job
-                let job = Job {
-                    id: job_data.get("id").unwrap().clone(),
-                    schedule: chrono::DateTime::parse_from_rfc3339(
-                        job_data.get("schedule").unwrap(),
-                    )
-                    .unwrap()
-                    .with_timezone(&chrono::Utc),
-                    payload: serde_json::from_str(job_data.get("payload").unwrap())?,
-                    status: match job_data.get("status").unwrap().as_str() {
-                        "pending" => JobStatus::Pending,
-                        "running" => JobStatus::Running,
-                        "completed" => JobStatus::Completed,
-                        "failed" => JobStatus::Failed,
-                        "retrying" => JobStatus::Retrying,
-                        _ => return Err(Error::Process("Invalid job status".into())),
-                    },
-                    created_at: chrono::DateTime::parse_from_rfc3339(
-                        job_data.get("created_at").unwrap(),
-                    )
-                    .map_err(|e| Error::Process(format!("Invalid created_at: {}", e)))?
-                    .with_timezone(&chrono::Utc),
-                    updated_at: chrono::DateTime::parse_from_rfc3339(
-                        job_data.get("updated_at").unwrap(),
-                    )
-                    .map_err(|e| Error::Process(format!("Invalid updated_at: {}", e)))?
-                    .with_timezone(&chrono::Utc),
-                    retries: job_data
-                        .get("retries")
-                        .unwrap()
-                        .parse()
-                        .map_err(|e| Error::Process(format!("Invalid retries: {}", e)))?,
-                    max_retries: job_data
-                        .get("max_retries")
-                        .unwrap()
-                        .parse()
-                        .map_err(|e| Error::Process(format!("Invalid max_retries: {}", e)))?,
-                };
+        let reserved = self
+            .cache
+            .reserve_from_queue(JOB_QUEUE, &self.worker_id, self.visibility_timeout)
+            .await?;
+
+        if let Some(job_id) = reserved {
+            if let Some(job) = self.db.get_job(&job_id).await? {
                 return Ok(Some(job));
             }
+
+            // The job no longer exists, so there is nothing left to deliver
+            self.cache.ack(JOB_QUEUE, &self.worker_id, &job_id).await?;
         }
         Ok(None)
     }
 
     async fn execute_job(&self, job: Job) -> Result<(), Error> {
+        let job_id = job.id.to_string();
         let mut state = ExecutionState::new(job);
 
-        // Mark job as running
+        // Anything but a queued job was claimed back, cancelled or already
+        // handled elsewhere; drop the stale delivery.
+        if state.job.status != JobStatus::Queued {
+            warn!(
+                "Skipping job {} delivered in status {}",
+                job_id, state.job.status
+            );
+            return self.ack(&job_id).await;
+        }
+
+        // A job delivered while shutting down is left for another worker
+        if *self.shutdown.borrow() {
+            self.cache.nack(JOB_QUEUE, &self.worker_id, &job_id).await?;
+            return Ok(());
+        }
+
+        // Register before the job shows up as running, so a cancel request
+        // that sees it running can always reach it
+        let running_job = self.register_running(&job_id);
+
+        // Mark job as running. Starting claims the job for this attempt; every
+        // later write is fenced by the token it issues, so it is rejected once
+        // the job was reclaimed and handed to another worker.
         state.mark_running()?;
-        let mut updates = std::collections::HashMap::new();
-        updates.insert("status", format!("{:?}", JobStatus::Running));
-        self.db.update_job(&state.job.id, &updates).await?;
+        let fencing_token = state.job.fencing_token + 1;
+        if !self
+            .persist_transition(
+                &job_id,
+                JobStatus::Queued,
+                JobStatus::Running,
+                JobUpdate::new()
+                    .fenced_by(state.job.fencing_token)
+                    .issue_fencing_token()
+                    .last_run_at(Some(state.start_time))
+                    .lease_owner(Some(self.worker_id.clone()))
+                    .lease_expires_at(Some(Utc::now() + self.lease_ttl)),
+            )
+            .await?
+        {
+            return self.ack(&job_id).await;
+        }
+        let _ = running_job.signals.lease.set(fencing_token);
+        let run_id = self
+            .db
+            .start_job_run(&job_id, state.attempt(), &self.worker_id)
+            .await?;
 
-        // Parse job payload
-        let payload: serde_json::Value =
-            serde_json::from_str(&serde_json::to_string(&state.job.payload)?)?;
+        // Run the task; a malformed payload counts as a failed attempt
+        let result = match task_from_payload(&state.job.payload, &self.handlers) {
+            Ok(Task::Handler(handler)) => {
+                let (ctx, stop) = TaskContext::new(
+                    job_id.clone(),
+                    run_id,
+                    state.attempt(),
+                    JobLease {
+                        db: self.db.clone(),
+                        cache: self.cache.clone(),
+                        worker_id: self.worker_id.clone(),
+                        fencing_token,
+                        ttl: self.lease_ttl,
+                        visibility_timeout: self.visibility_timeout,
+                    },
+                );
+                handler::run(
+                    handler.as_ref(),
+                    ctx,
+                    stop,
+                    state.job.payload.clone(),
+                    self.timeout,
+                    self.kill_grace,
+                    running_job.signals.cancelled.notified(),
+                )
+                .await
+                .map(|result| state.record_result(result))
+            }
+            Ok(Task::Shell {
+                command,
+                args,
+                sandbox,
+            }) => {
+                let execution = self
+                    .process_manager
+                    .execute_command(
+                        run_id,
+                        &command,
+                        &args,
+                        &[],
+                        sandbox.as_deref(),
+                        running_job.signals.cancelled.notified(),
+                    )
+                    .await;
+                state.record_usage(&execution.usage);
+                if let Some(output) = &execution.partial_output {
+                    state.record_output(output);
+                }
+                execution.result.and_then(|output| {
+                    state.record_output(&output);
+                    if output.status.success() {
+                        Ok(())
+                    } else {
+                        Err(Error::ExitStatus(output.status.to_string()))
+                    }
+                })
+            }
+            Ok(Task::Script(script)) => {
+                state.record_script(&script);
+                let execution = self
+                    .process_manager
+                    .execute_script(run_id, &script, running_job.signals.cancelled.notified())
+                    .await;
+                state.record_usage(&execution.usage);
+                if let Some(output) = &execution.partial_output {
+                    state.record_output(output);
+                }
+                execution.result.and_then(|output| {
+                    state.record_output(&output);
+                    if output.status.success() {
+                        Ok(())
+                    } else {
+                        Err(Error::ExitStatus(output.status.to_string()))
+                    }
+                })
+            }
+            Ok(Task::Http(request)) => self
+                .http_client
+                .send(&request, running_job.signals.cancelled.notified())
+                .await
+                .and_then(|response| {
+                    state.record_response(&response);
+                    request.rules.check(response.status)
+                }),
+            Err(e) => Err(e),
+        };
+        // A job this worker cannot run fails for good only once no other
+        // worker can take it either
+        let (permanent, bounce) = match &result {
+            Err(Error::Unsupported { capability, .. }) => {
+                let bounce = self.can_bounce(&state.job, capability).await?;
+                (!bounce, bounce)
+            }
+            Err(e) => (e.is_permanent(), false),
+            Ok(()) => (false, false),
+        };
+        let interrupted = running_job.signals.interrupted.load(Ordering::SeqCst);
+        drop(running_job);
 
-        // Extract command and arguments
-        let command = payload["command"]
-            .as_str()
-            .ok_or_else(|| Error::Process("Missing command in payload".into()))?;
-        let args: Vec<String> = payload["args"]
-            .as_array()
-            .ok_or_else(|| Error::Process("Missing args in payload".into()))?
+        match result {
+            Ok(()) => state.mark_completed()?,
+            Err(Error::Cancelled(_)) if interrupted => state.mark_failed(&Error::Interrupted(
+                "Executor shut down before the job finished".into(),
+            ))?,
+            Err(e @ Error::Cancelled(_)) => state.mark_cancelled(&e)?,
+            Err(e) => state.mark_failed(&e)?,
+        }
+
+        if let Err(e) = self.db.finish_job_run(run_id, state.run_outcome()).await {
+            error!("Failed to record run {} of job {}: {}", run_id, job_id, e);
+        }
+
+        match state.job.status {
+            JobStatus::Completed => {
+                self.persist_transition(
+                    &job_id,
+                    JobStatus::Running,
+                    JobStatus::Completed,
+                    JobUpdate::new()
+                        .completed_at(state.end_time)
+                        .fenced_by(fencing_token),
+                )
+                .await?;
+            }
+            // An interrupted attempt did not fail on its own, and a job this
+            // worker cannot run is left for one that can, so either goes
+            // straight back to the queue without using up a retry
+            JobStatus::Failed if interrupted || bounce => {
+                let mut update = JobUpdate::new()
+                    .last_error(state.error.clone())
+                    .last_error_class(state.error_class.map(String::from))
+                    .lease_owner(None)
+                    .lease_expires_at(None)
+                    .fenced_by(fencing_token);
+                if bounce {
+                    update = update.increment_bounces();
+                }
+                let requeued = self
+                    .persist_transition(&job_id, JobStatus::Running, JobStatus::Queued, update)
+                    .await?;
+                if requeued {
+                    if bounce {
+                        info!("Handed job {} back for a worker that can run it", job_id);
+                    } else {
+                        info!("Requeued job {} interrupted by shutdown", job_id);
+                    }
+                    return self.nack(&job_id).await;
+                }
+            }
+            // A permanent failure is dead-lettered whatever attempts it has
+            // left, straight from Running so the failure watcher never sees
+            // it Failed and schedules a retry in between
+            JobStatus::Failed if permanent => {
+                let dead_lettered = self
+                    .db
+                    .dead_letter_job(
+                        &job_id,
+                        JobStatus::Running,
+                        DeadLetterReason::PermanentFailure,
+                        &JobUpdate::new()
+                            .last_error(state.error.clone())
+                            .last_error_class(state.error_class.map(String::from))
+                            .fenced_by(fencing_token),
+                    )
+                    .await;
+                if allow_conflict(dead_lettered)? {
+                    info!("Dead-lettered job {} after a permanent failure", job_id);
+                }
+            }
+            JobStatus::Failed => {
+                let failed = self
+                    .persist_transition(
+                        &job_id,
+                        JobStatus::Running,
+                        JobStatus::Failed,
+                        JobUpdate::new()
+                            .last_error(state.error.clone())
+                            .last_error_class(state.error_class.map(String::from))
+                            .fenced_by(fencing_token),
+                    )
+                    .await?;
+
+                // Check if we should retry; the queue populator claims and
+                // re-enqueues the job once its backoff has elapsed.
+                if failed && state.mark_retrying().is_ok() {
+                    let run_at =
+                        Utc::now() + state.job.retry_policy.delay(state.job.retries as u32);
+                    let scheduled = self.db.schedule_retry(&job_id, fencing_token, run_at).await;
+                    if allow_conflict(scheduled)? {
+                        info!("Scheduled retry of job {} for {}", job_id, run_at);
+                    }
+                }
+            }
+            // The canceller already moved the job to Cancelled
+            _ => {}
+        }
+
+        // Only acknowledge once the outcome is persisted; until then the
+        // reservation keeps the job recoverable if this worker dies.
+        self.ack(&job_id).await
+    }
+
+    fn register_running(&self, job_id: &str) -> RunningJob {
+        let signals = Arc::new(JobSignals::default());
+        self.running
+            .lock()
+            .unwrap()
+            .insert(job_id.to_string(), signals.clone());
+        RunningJob {
+            running: self.running.clone(),
+            job_id: job_id.to_string(),
+            signals,
+        }
+    }
+
+    /// Kills the child process of any running job whose id is published on
+    /// the cancellation channel, resubscribing if the connection drops.
+    async fn listen_for_cancellations(self) {
+        loop {
+            match self.cache.subscribe(CANCEL_CHANNEL).await {
+                Ok(pubsub) => {
+                    let mut messages = pubsub.into_on_message();
+                    while let Some(msg) = messages.next().await {
+                        let Ok(job_id) = msg.get_payload::<String>() else {
+                            continue;
+                        };
+                        if let Some(signals) = self.running.lock().unwrap().get(&job_id) {
+                            info!("Cancelling running job {}", job_id);
+                            signals.cancelled.notify_one();
+                        }
+                    }
+                    warn!("Cancellation subscription closed, resubscribing");
+                }
+                Err(e) => error!("Failed to subscribe to {}: {}", CANCEL_CHANNEL, e),
+            }
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    }
+
+    /// Renews this worker's lease, the leases of its running jobs and their
+    /// queue reservations every third of the lease TTL, reporting the current
+    /// load and picking up drain requests. A job whose lease was taken over
+    /// by the lease monitor is stopped, since its attempt has already been
+    /// written off.
+    async fn send_heartbeats(self) {
+        let mut interval = tokio::time::interval(self.lease_ttl / 3);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.heartbeat().await {
+                error!(
+                    "Failed to send heartbeat for worker {}: {}",
+                    self.worker_id, e
+                );
+            }
+        }
+    }
+
+    async fn heartbeat(&self) -> Result<(), Error> {
+        let (leases, running_jobs) = {
+            let running = self.running.lock().unwrap();
+            let leases: Vec<(String, i64)> = running
+                .iter()
+                .filter_map(|(job_id, signals)| {
+                    signals.lease.get().map(|token| (job_id.clone(), *token))
+                })
+                .collect();
+            (leases, running.len() as i32)
+        };
+        let ids: Vec<(Uuid, i64)> = leases
             .iter()
-            .filter_map(|v| v.as_str().map(String::from))
+            .filter_map(|(id, token)| Uuid::parse_str(id).ok().map(|id| (id, *token)))
             .collect();
 
-        // Execute command
-        match self
-            .process_manager
-            .execute_command(command, &args, &[])
-            .await
-        {
-            Ok(output) => {
-                let output_str = String::from_utf8_lossy(&output.stdout).into();
-                state.mark_completed(output_str)?;
-                let mut updates = std::collections::HashMap::new();
-                updates.insert("status", format!("{:?}", JobStatus::Completed));
-                self.db.update_job(&state.job.id, &updates).await?;
-            }
-            Err(e) => {
-                let error_str = e.to_string();
-                state.mark_failed(error_str.clone())?;
-                let mut updates = std::collections::HashMap::new();
-                updates.insert("status", format!("{:?}", JobStatus::Failed));
-                self.db.update_job(&state.job.id, &updates).await?;
-
-                // Check if we should retry
-                if state.job.retries < state.job.max_retries {
-                    let mut updates = std::collections::HashMap::new();
-                    updates.insert("status", format!("{:?}", JobStatus::Pending));
-                    updates.insert("retries", (state.job.retries + 1).to_string());
-                    self.db.update_job(&state.job.id, &updates).await?;
-                    self.cache.push_to_queue("jobs", &state.job.id).await?;
-                }
+        let heartbeat = self
+            .db
+            .heartbeat(&self.worker_id, &ids, running_jobs, self.lease_ttl)
+            .await?;
+        if heartbeat.draining && !self.draining.swap(true, Ordering::SeqCst) {
+            info!(
+                "Draining worker {}; finishing {} running jobs",
+                self.worker_id, running_jobs
+            );
+        }
+
+        // A reservation that could not be extended only risks the reaper
+        // dropping it; the jobs whose leases were lost still need stopping
+        for (job_id, _) in &leases {
+            if let Err(e) = self
+                .cache
+                .extend_reservation(JOB_QUEUE, &self.worker_id, job_id, self.visibility_timeout)
+                .await
+            {
+                error!("Failed to extend reservation of job {}: {}", job_id, e);
+            }
+        }
+
+        for job_id in heartbeat.lost.iter().map(Uuid::to_string) {
+            if let Some(signals) = self.running.lock().unwrap().get(&job_id) {
+                warn!("Lost the lease on job {}, stopping it", job_id);
+                signals.cancelled.notify_one();
             }
         }
+        Ok(())
+    }
+
+    /// Whether a job this worker cannot run should go back to the queue:
+    /// another live worker advertises `capability`, and the job has not
+    /// bounced between workers [`MAX_BOUNCES`] times yet.
+    async fn can_bounce(&self, job: &Job, capability: &str) -> Result<bool, Error> {
+        if job.bounces >= MAX_BOUNCES {
+            return Ok(false);
+        }
+        Ok(self
+            .db
+            .has_capable_worker(capability, &self.worker_id)
+            .await?)
+    }
+
+    /// Persists a status change, returning `false` when another service moved
+    /// the job first and this worker's view of it is stale.
+    async fn persist_transition(
+        &self,
+        job_id: &str,
+        from: JobStatus,
+        to: JobStatus,
+        update: JobUpdate,
+    ) -> Result<bool, Error> {
+        allow_conflict(self.db.transition_job(job_id, from, to, &update).await)
+    }
 
+    async fn ack(&self, job_id: &str) -> Result<(), Error> {
+        self.cache.ack(JOB_QUEUE, &self.worker_id, job_id).await?;
+        Ok(())
+    }
+
+    /// Hands a reserved job back to the queue, enqueueing it afresh if its
+    /// reservation was already dropped.
+    async fn nack(&self, job_id: &str) -> Result<(), Error> {
+        if !self.cache.nack(JOB_QUEUE, &self.worker_id, job_id).await? {
+            self.cache.push_to_queue(JOB_QUEUE, job_id).await?;
+        }
         Ok(())
     }
 }
+
+/// Turns a lost compare-and-set into `Ok(false)`: another writer, e.g. a
+/// canceller, moved the job first, or the job was reclaimed from this worker,
+/// and the other side's state wins.
+fn allow_conflict(result: anyhow::Result<()>) -> Result<bool, Error> {
+    match result {
+        Ok(()) => Ok(true),
+        Err(e) => match e.downcast_ref::<SchedulerError>() {
+            Some(
+                conflict @ (SchedulerError::TransitionConflict { .. }
+                | SchedulerError::StaleFencingToken { .. }),
+            ) => {
+                warn!("{}", conflict);
+                Ok(false)
+            }
+            _ => Err(e.into()),
+        },
+    }
+}
+
+/// What a job's payload asks for, by its `kind`.
+enum Task {
+    Handler(Arc<dyn TaskHandler>),
+    Shell {
+        command: String,
+        args: Vec<String>,
+        sandbox: Option<String>,
+    },
+    Http(Box<HttpRequest>),
+    Script(Box<Script>),
+}
+
+/// Reads the task from the payload; jobs without a `kind` run commands. A
+/// registered handler takes precedence over the built-in kinds.
+fn task_from_payload(payload: &serde_json::Value, handlers: &TaskRegistry) -> Result<Task, Error> {
+    let kind = match &payload["kind"] {
+        serde_json::Value::Null => "shell",
+        kind => kind
+            .as_str()
+            .ok_or_else(|| Error::Config(format!("Invalid job kind: {}", kind)))?,
+    };
+
+    if let Some(handler) = handlers.get(kind) {
+        return Ok(Task::Handler(handler));
+    }
+    match kind {
+        "shell" => {
+            let (command, args, sandbox) = command_from_payload(payload)?;
+            Ok(Task::Shell {
+                command,
+                args,
+                sandbox,
+            })
+        }
+        "http" => Ok(Task::Http(Box::new(HttpRequest::from_payload(payload)?))),
+        "script" => Ok(Task::Script(Box::new(Script::from_payload(payload)?))),
+        kind => Err(Error::Unsupported {
+            capability: kind.to_string(),
+            message: format!("Job kind {} is not supported on this executor", kind),
+        }),
+    }
+}
+
+/// The command, its arguments and the sandbox profile, if any, to run it in.
+fn command_from_payload(
+    payload: &serde_json::Value,
+) -> Result<(String, Vec<String>, Option<String>), Error> {
+    let command = payload["command"]
+        .as_str()
+        .ok_or_else(|| Error::Process("Missing command in payload".into()))?;
+    let args = payload["args"]
+        .as_array()
+        .ok_or_else(|| Error::Process("Missing args in payload".into()))?
+        .iter()
+        .filter_map(|v| v.as_str().map(String::from))
+        .collect();
+    let sandbox = match &payload["sandbox"] {
+        serde_json::Value::Null => None,
+        sandbox => Some(
+            sandbox
+                .as_str()
+                .ok_or_else(|| Error::Sandbox("Sandbox in payload is not a profile name".into()))?
+                .to_string(),
+        ),
+    };
+    Ok((command.to_string(), args, sandbox))
+}
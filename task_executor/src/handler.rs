@@ -0,0 +1,272 @@
+use async_trait::async_trait;
+use scheduler_core::{
+    cache::{Cache, JOB_QUEUE},
+    db::Database,
+};
+use serde_json::Value;
+use std::{collections::HashMap, future::Future, pin::pin, sync::Arc, time::Duration};
+use thiserror::Error;
+use tokio::{sync::watch, time};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::error::Error;
+
+/// Runs jobs of one `kind` in-process, as Rust code instead of a command.
+///
+/// ```ignore
+/// struct SendInvoice;
+///
+/// #[async_trait]
+/// impl TaskHandler for SendInvoice {
+///     async fn handle(&self, ctx: TaskContext, payload: Value) -> Result<Value, TaskError> {
+///         ctx.progress(json!({"step": "rendering"})).await?;
+///         ...
+///         Ok(json!({"invoice": id}))
+///     }
+/// }
+///
+/// let handlers = TaskRegistry::new().register("send_invoice", SendInvoice);
+/// ```
+#[async_trait]
+pub trait TaskHandler: Send + Sync {
+    /// Runs one attempt of a job with the job's payload. What it returns is
+    /// recorded as the run's result.
+    async fn handle(&self, ctx: TaskContext, payload: Value) -> Result<Value, TaskError>;
+}
+
+/// How a handler's attempt failed.
+#[derive(Error, Debug)]
+pub enum TaskError {
+    /// The job is retried if it has attempts left.
+    #[error("{0}")]
+    Failed(String),
+
+    /// Retrying cannot help, so the job is dead-lettered straight away.
+    #[error("{0}")]
+    Permanent(String),
+
+    /// The handler stopped because its context was cancelled.
+    #[error("{0}")]
+    Cancelled(String),
+}
+
+impl From<anyhow::Error> for TaskError {
+    fn from(e: anyhow::Error) -> Self {
+        TaskError::Failed(format!("{:#}", e))
+    }
+}
+
+/// Handlers by the job `kind` they run.
+#[derive(Clone, Default)]
+pub struct TaskRegistry {
+    handlers: HashMap<String, Arc<dyn TaskHandler>>,
+}
+
+impl TaskRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs jobs of `kind` with `handler`, replacing any handler registered
+    /// for it before. Registering `shell` or `http` takes those kinds over
+    /// from the executor's built-in support.
+    pub fn register(
+        mut self,
+        kind: impl Into<String>,
+        handler: impl TaskHandler + 'static,
+    ) -> Self {
+        self.handlers.insert(kind.into(), Arc::new(handler));
+        self
+    }
+
+    pub fn get(&self, kind: &str) -> Option<Arc<dyn TaskHandler>> {
+        self.handlers.get(kind).cloned()
+    }
+
+    pub fn kinds(&self) -> impl Iterator<Item = &str> {
+        self.handlers.keys().map(String::as_str)
+    }
+}
+
+/// The job a handler's attempt belongs to, and what the handler can tell the
+/// executor while it runs.
+pub struct TaskContext {
+    job_id: String,
+    run_id: Uuid,
+    attempt: i32,
+    stop: watch::Receiver<bool>,
+    /// Unset when the context is [`detached`](Self::detached).
+    lease: Option<JobLease>,
+}
+
+/// What renewing a job's lease from its handler takes.
+pub(crate) struct JobLease {
+    pub db: Arc<Database>,
+    pub cache: Arc<Cache>,
+    pub worker_id: String,
+    pub fencing_token: i64,
+    pub ttl: Duration,
+    pub visibility_timeout: Duration,
+}
+
+impl TaskContext {
+    /// A context for one attempt, and the sender that cancels it.
+    pub(crate) fn new(
+        job_id: String,
+        run_id: Uuid,
+        attempt: i32,
+        lease: JobLease,
+    ) -> (Self, watch::Sender<bool>) {
+        Self::with_lease(job_id, run_id, attempt, Some(lease))
+    }
+
+    /// A context for calling a handler outside an executor, e.g. from its
+    /// tests, and the sender that cancels it. Heartbeats always succeed and
+    /// progress is not recorded anywhere.
+    pub fn detached(job_id: impl Into<String>, attempt: i32) -> (Self, watch::Sender<bool>) {
+        Self::with_lease(job_id.into(), Uuid::new_v4(), attempt, None)
+    }
+
+    fn with_lease(
+        job_id: String,
+        run_id: Uuid,
+        attempt: i32,
+        lease: Option<JobLease>,
+    ) -> (Self, watch::Sender<bool>) {
+        let (stop, stopped) = watch::channel(false);
+        let ctx = Self {
+            job_id,
+            run_id,
+            attempt,
+            stop: stopped,
+            lease,
+        };
+        (ctx, stop)
+    }
+
+    pub fn job_id(&self) -> &str {
+        &self.job_id
+    }
+
+    pub fn run_id(&self) -> Uuid {
+        self.run_id
+    }
+
+    /// Attempt number, counting from 1.
+    pub fn attempt(&self) -> i32 {
+        self.attempt
+    }
+
+    /// Whether the job was cancelled, timed out or the executor is shutting
+    /// down. The handler then has the kill grace period to return before it
+    /// is dropped.
+    pub fn is_cancelled(&self) -> bool {
+        *self.stop.borrow()
+    }
+
+    /// Resolves once [`is_cancelled`](Self::is_cancelled) turns true.
+    pub async fn cancelled(&self) {
+        let mut stop = self.stop.clone();
+        // Only fails once the executor dropped the attempt, i.e. stopped it
+        let _ = stop.wait_for(|stopped| *stopped).await;
+    }
+
+    /// Renews the job's lease now rather than at the worker's next heartbeat,
+    /// and checks it is still this worker's to run. Fails with
+    /// [`TaskError::Cancelled`] once the job was reclaimed, so a handler can
+    /// heartbeat before side effects that must not happen twice.
+    pub async fn heartbeat(&self) -> Result<(), TaskError> {
+        let Some(lease) = &self.lease else {
+            return Ok(());
+        };
+        let renewed = lease
+            .db
+            .renew_job_lease(
+                &self.job_id,
+                &lease.worker_id,
+                lease.fencing_token,
+                lease.ttl,
+            )
+            .await?;
+        if !renewed {
+            return Err(TaskError::Cancelled(format!(
+                "Lost the lease on job {}",
+                self.job_id
+            )));
+        }
+
+        lease
+            .cache
+            .extend_reservation(
+                JOB_QUEUE,
+                &lease.worker_id,
+                &self.job_id,
+                lease.visibility_timeout,
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Records how far along the attempt is on its run record, replacing what
+    /// was reported before.
+    pub async fn progress(&self, progress: Value) -> Result<(), TaskError> {
+        if let Some(lease) = &self.lease {
+            lease
+                .db
+                .update_job_run_progress(self.run_id, &progress)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs a handler's attempt. If it outlives the timeout or `cancelled`
+/// resolves first, its context is cancelled and, once the kill grace period
+/// is up, the attempt is dropped.
+pub(crate) async fn run(
+    handler: &dyn TaskHandler,
+    ctx: TaskContext,
+    stop: watch::Sender<bool>,
+    payload: Value,
+    timeout: Duration,
+    kill_grace: Duration,
+    cancelled: impl Future<Output = ()>,
+) -> Result<Value, Error> {
+    let job_id = ctx.job_id.clone();
+    info!("Running handler for job {}", job_id);
+
+    let mut attempt = pin!(handler.handle(ctx, payload));
+    let stopped = tokio::select! {
+        result = &mut attempt => {
+            return result.map_err(|e| match e {
+                TaskError::Permanent(message) => Error::Task {
+                    message,
+                    permanent: true,
+                },
+                // Nothing asked the handler to stop, so it gave up on its own
+                TaskError::Failed(message) | TaskError::Cancelled(message) => Error::Task {
+                    message,
+                    permanent: false,
+                },
+            });
+        }
+        _ = time::sleep(timeout) => Error::TimedOut(format!(
+            "Handler for job {} ran longer than {:?}",
+            job_id, timeout
+        )),
+        _ = cancelled => Error::Cancelled(format!(
+            "Handler for job {} was cancelled while running",
+            job_id
+        )),
+    };
+
+    let _ = stop.send(true);
+    if time::timeout(kill_grace, &mut attempt).await.is_err() {
+        warn!(
+            "Handler for job {} still running {:?} after being cancelled, dropping it",
+            job_id, kill_grace
+        );
+    }
+    Err(stopped)
+}
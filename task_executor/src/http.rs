@@ -0,0 +1,449 @@
+use hyper::{
+    body::HttpBody,
+    client::HttpConnector,
+    header::{HeaderName, HeaderValue, CONTENT_TYPE},
+    Body, Client, HeaderMap, Method, Request, Uri,
+};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use scheduler_core::db::MAX_RUN_OUTPUT_BYTES;
+use serde_json::Value;
+use std::{future::Future, str::FromStr, time::Duration};
+use tokio::time;
+use tracing::{error, info};
+
+use crate::error::Error;
+
+/// A request an `http` job makes, read from its payload:
+///
+/// ```json
+/// {
+///     "kind": "http",
+///     "method": "POST",
+///     "url": "https://billing.internal/invoices/run",
+///     "headers": {"Authorization": "Bearer ..."},
+///     "body": {"merchant": "..."},
+///     "timeout_secs": 30,
+///     "success_statuses": ["2xx"],
+///     "retry_statuses": ["408", "429", "5xx"]
+/// }
+/// ```
+///
+/// Only `url` is required. The method defaults to GET, the timeout to the
+/// executor's, and the status lists to those of [`StatusRules::default`]. A
+/// string body is sent as is; any other JSON body is serialized and sent as
+/// `application/json` unless the headers say otherwise.
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: Method,
+    pub url: Uri,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+    pub timeout: Option<Duration>,
+    pub rules: StatusRules,
+}
+
+impl HttpRequest {
+    pub fn from_payload(payload: &Value) -> Result<Self, Error> {
+        let method = match &payload["method"] {
+            Value::Null => Method::GET,
+            method => method
+                .as_str()
+                .and_then(|method| Method::from_str(&method.to_uppercase()).ok())
+                .ok_or_else(|| Error::Http(format!("Invalid method in payload: {}", method)))?,
+        };
+        let url = payload["url"]
+            .as_str()
+            .ok_or_else(|| Error::Http("Missing url in payload".into()))?;
+        let url = Uri::from_str(url)
+            .map_err(|e| Error::Http(format!("Invalid url in payload: {}", e)))?;
+
+        let mut headers = HeaderMap::new();
+        if let Some(fields) = payload["headers"].as_object() {
+            for (name, value) in fields {
+                let value = value
+                    .as_str()
+                    .ok_or_else(|| Error::Http(format!("Header {} is not a string", name)))?;
+                headers.append(
+                    HeaderName::from_str(name)
+                        .map_err(|e| Error::Http(format!("Invalid header {}: {}", name, e)))?,
+                    HeaderValue::from_str(value)
+                        .map_err(|e| Error::Http(format!("Invalid header {}: {}", name, e)))?,
+                );
+            }
+        }
+
+        let body = match &payload["body"] {
+            Value::Null => Vec::new(),
+            Value::String(body) => body.clone().into_bytes(),
+            body => {
+                if !headers.contains_key(CONTENT_TYPE) {
+                    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+                }
+                serde_json::to_vec(body)?
+            }
+        };
+
+        let timeout = match &payload["timeout_secs"] {
+            Value::Null => None,
+            timeout => Some(Duration::from_secs(timeout.as_u64().ok_or_else(|| {
+                Error::Http(format!("Invalid timeout_secs in payload: {}", timeout))
+            })?)),
+        };
+
+        let mut rules = StatusRules::default();
+        if let Some(success) = status_ranges(payload, "success_statuses")? {
+            rules.success = success;
+        }
+        if let Some(retry) = status_ranges(payload, "retry_statuses")? {
+            rules.retry = retry;
+        }
+
+        Ok(Self {
+            method,
+            url,
+            headers,
+            body,
+            timeout,
+            rules,
+        })
+    }
+}
+
+fn status_ranges(payload: &Value, field: &str) -> Result<Option<Vec<StatusRange>>, Error> {
+    let ranges = match &payload[field] {
+        Value::Null => return Ok(None),
+        ranges => ranges,
+    };
+    let ranges = ranges
+        .as_array()
+        .ok_or_else(|| Error::Http(format!("{} is not a list", field)))?
+        .iter()
+        .map(|range| {
+            range
+                .as_str()
+                .ok_or_else(|| Error::Http(format!("Invalid status in {}: {}", field, range)))?
+                .parse()
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(Some(ranges))
+}
+
+/// Which response statuses an `http` job succeeds with and which are worth
+/// retrying. Any other status fails the job for good, skipping the attempts
+/// it has left.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusRules {
+    pub success: Vec<StatusRange>,
+    pub retry: Vec<StatusRange>,
+}
+
+impl Default for StatusRules {
+    /// Succeeds on 2xx and retries timeouts, rate limiting and server errors.
+    fn default() -> Self {
+        Self {
+            success: vec![StatusRange::new(200, 299)],
+            retry: vec![
+                StatusRange::new(408, 408),
+                StatusRange::new(429, 429),
+                StatusRange::new(500, 599),
+            ],
+        }
+    }
+}
+
+impl StatusRules {
+    /// Whether a job whose endpoint answered `status` succeeded.
+    pub fn check(&self, status: u16) -> Result<(), Error> {
+        let matches = |ranges: &[StatusRange]| ranges.iter().any(|range| range.contains(status));
+        if matches(&self.success) {
+            return Ok(());
+        }
+
+        Err(Error::HttpStatus {
+            status,
+            retryable: matches(&self.retry),
+        })
+    }
+}
+
+/// An inclusive range of status codes, written `404`, `5xx` or `500-503`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatusRange {
+    first: u16,
+    last: u16,
+}
+
+impl StatusRange {
+    pub fn new(first: u16, last: u16) -> Self {
+        Self { first, last }
+    }
+
+    pub fn contains(&self, status: u16) -> bool {
+        (self.first..=self.last).contains(&status)
+    }
+}
+
+impl FromStr for StatusRange {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::Http(format!("Invalid status range: {}", s));
+        let status = |s: &str| {
+            s.trim()
+                .parse::<u16>()
+                .ok()
+                .filter(|status| (100..=599).contains(status))
+                .ok_or_else(invalid)
+        };
+
+        let s = s.trim();
+        let range = if let Some(class) = s.strip_suffix("xx").or_else(|| s.strip_suffix("XX")) {
+            let class = status(&format!("{}00", class))?;
+            Self::new(class, class + 99)
+        } else if let Some((first, last)) = s.split_once('-') {
+            Self::new(status(first)?, status(last)?)
+        } else {
+            let status = status(s)?;
+            Self::new(status, status)
+        };
+
+        if range.first > range.last {
+            return Err(invalid());
+        }
+        Ok(range)
+    }
+}
+
+/// What the endpoint answered. Only the start of a long body is kept, as
+/// much as a run records.
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+/// Makes `http` jobs' requests in-process, sharing connections between jobs.
+#[derive(Clone)]
+pub struct HttpClient {
+    client: Client<HttpsConnector<HttpConnector>>,
+    /// For requests that do not set their own.
+    timeout: Duration,
+}
+
+impl HttpClient {
+    pub fn new(timeout: Duration) -> Self {
+        let connector = HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .https_or_http()
+            .enable_http1()
+            .enable_http2()
+            .build();
+
+        Self {
+            client: Client::builder().build(connector),
+            timeout,
+        }
+    }
+
+    /// Sends the request and reads the response, whatever its status. Fails
+    /// if no response arrives, the request or reading the body outlasts the
+    /// timeout, or `cancelled` resolves first.
+    pub async fn send(
+        &self,
+        request: &HttpRequest,
+        cancelled: impl Future<Output = ()>,
+    ) -> Result<HttpResponse, Error> {
+        let timeout = request.timeout.unwrap_or(self.timeout);
+        let mut builder = Request::builder()
+            .method(request.method.clone())
+            .uri(request.url.clone());
+        if let Some(headers) = builder.headers_mut() {
+            headers.extend(request.headers.clone());
+        }
+        let http_request = builder
+            .body(Body::from(request.body.clone()))
+            .map_err(|e| Error::Http(format!("Invalid request: {}", e)))?;
+
+        info!("Sending request: {} {}", request.method, request.url);
+
+        let response = tokio::select! {
+            response = time::timeout(timeout, self.exchange(http_request)) => response,
+            _ = cancelled => {
+                return Err(Error::Cancelled(format!(
+                    "{} {} was cancelled while in flight",
+                    request.method, request.url
+                )));
+            }
+        };
+        let response = response.map_err(|_| {
+            Error::TimedOut(format!(
+                "{} {} took longer than {:?}",
+                request.method, request.url, timeout
+            ))
+        })??;
+
+        if !(200..300).contains(&response.status) {
+            error!(
+                "Request {} {} answered with status {}",
+                request.method, request.url, response.status
+            );
+        }
+
+        Ok(response)
+    }
+
+    async fn exchange(&self, request: Request<Body>) -> Result<HttpResponse, Error> {
+        let response = self
+            .client
+            .request(request)
+            .await
+            .map_err(|e| Error::Http(format!("Request failed: {}", e)))?;
+        let status = response.status().as_u16();
+
+        // Stop reading past what the run record keeps; it marks the cut
+        let mut body = response.into_body();
+        let mut bytes = Vec::new();
+        while bytes.len() <= MAX_RUN_OUTPUT_BYTES {
+            match body.data().await {
+                Some(chunk) => bytes.extend_from_slice(
+                    &chunk.map_err(|e| Error::Http(format!("Failed to read response: {}", e)))?,
+                ),
+                None => break,
+            }
+        }
+
+        Ok(HttpResponse {
+            status,
+            body: String::from_utf8_lossy(&bytes).into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn range(s: &str) -> StatusRange {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_statuses_classes_and_ranges() {
+        assert_eq!(range("404"), StatusRange::new(404, 404));
+        assert_eq!(range("5xx"), StatusRange::new(500, 599));
+        assert_eq!(range("2XX"), StatusRange::new(200, 299));
+        assert_eq!(range("500-503"), StatusRange::new(500, 503));
+        assert_eq!(range(" 100 - 599 "), StatusRange::new(100, 599));
+    }
+
+    #[test]
+    fn rejects_invalid_ranges() {
+        for s in [
+            "600", "99", "0xx", "6xx", "5xx-", "-500", "503-500", "2xx-3xx", "", "abc",
+        ] {
+            assert!(
+                matches!(s.parse::<StatusRange>(), Err(Error::Http(_))),
+                "{s:?} parsed"
+            );
+        }
+    }
+
+    #[test]
+    fn ranges_are_inclusive() {
+        let range = range("500-503");
+        assert!(range.contains(500));
+        assert!(range.contains(503));
+        assert!(!range.contains(499));
+        assert!(!range.contains(504));
+    }
+
+    #[test]
+    fn default_rules_succeed_on_2xx_and_retry_transient_errors() {
+        let rules = StatusRules::default();
+        assert!(rules.check(200).is_ok());
+        assert!(rules.check(204).is_ok());
+        for status in [408, 429, 500, 503] {
+            let error = rules.check(status).unwrap_err();
+            assert!(
+                matches!(
+                    error,
+                    Error::HttpStatus {
+                        retryable: true,
+                        ..
+                    }
+                ),
+                "{status}"
+            );
+            assert!(!error.is_permanent());
+        }
+        for status in [301, 400, 404] {
+            let error = rules.check(status).unwrap_err();
+            assert!(
+                matches!(
+                    error,
+                    Error::HttpStatus {
+                        retryable: false,
+                        ..
+                    }
+                ),
+                "{status}"
+            );
+            assert!(error.is_permanent());
+        }
+    }
+
+    #[test]
+    fn success_wins_over_retry() {
+        let rules = StatusRules {
+            success: vec![range("2xx"), range("404")],
+            retry: vec![range("4xx")],
+        };
+        assert!(rules.check(404).is_ok());
+        assert!(matches!(
+            rules.check(409),
+            Err(Error::HttpStatus {
+                status: 409,
+                retryable: true
+            })
+        ));
+    }
+
+    #[test]
+    fn reads_status_lists_from_the_payload() {
+        let request = HttpRequest::from_payload(&json!({
+            "url": "http://localhost/",
+            "success_statuses": ["2xx", "304"],
+            "retry_statuses": ["503"],
+        }))
+        .unwrap();
+        assert_eq!(request.rules.success, [range("2xx"), range("304")]);
+        assert_eq!(request.rules.retry, [range("503")]);
+    }
+
+    #[test]
+    fn missing_or_null_status_lists_keep_the_defaults() {
+        for payload in [
+            json!({"url": "http://localhost/"}),
+            json!({
+                "url": "http://localhost/",
+                "success_statuses": null,
+                "retry_statuses": null,
+            }),
+        ] {
+            let request = HttpRequest::from_payload(&payload).unwrap();
+            assert_eq!(request.rules, StatusRules::default());
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_status_lists() {
+        for statuses in [json!("2xx"), json!([200]), json!(["600"])] {
+            let payload = json!({"url": "http://localhost/", "success_statuses": statuses});
+            assert!(
+                matches!(HttpRequest::from_payload(&payload), Err(Error::Http(_))),
+                "{payload}"
+            );
+        }
+    }
+}
@@ -1,42 +1,152 @@
+pub mod cgroup;
+pub mod config;
 pub mod error;
 pub mod executor;
+pub mod handler;
+pub mod http;
 pub mod process;
+pub mod sandbox;
+pub mod script;
 pub mod state;
 
+pub use config::ExecutorConfig;
 pub use error::Error;
 pub use executor::TaskExecutor;
-pub use process::ProcessManager;
+pub use handler::{TaskContext, TaskError, TaskHandler, TaskRegistry};
+pub use process::{Execution, ProcessManager};
+pub use scheduler_macros::task;
 pub use state::ExecutionState;
 
 use anyhow::Result;
+use scheduler_core::{
+    cache::{Cache, CacheConfig},
+    config::Config,
+    db::Database,
+};
+use tokio::task::JoinHandle;
+use tracing::error;
 
+/// A task executor configured from the environment, for embedding in other
+/// binaries.
 pub struct Executor {
-    // Add necessary fields here
+    executor: TaskExecutor,
 }
 
 impl Executor {
     pub async fn new() -> Result<Self> {
-        // Initialize the executor
-        Ok(Self {
-            // Initialize fields
+        Self::with_handlers(TaskRegistry::new()).await
+    }
+
+    /// Like [`Executor::new`], additionally running jobs whose `kind` has a
+    /// handler in `handlers`.
+    pub async fn with_handlers(handlers: TaskRegistry) -> Result<Self> {
+        let config = Config::from_env()?;
+        let db = Database::new(&config.database_url).await?;
+        let cache = Cache::new(CacheConfig {
+            url: config.redis_url.clone(),
+            max_connections: 10,
         })
+        .await?;
+        let executor = TaskExecutor::new(db, cache, ExecutorConfig::from_core_config(&config))
+            .await?
+            .with_handlers(handlers);
+
+        Ok(Self { executor })
     }
 
+    /// Runs the executor in the background.
     pub async fn start(&self) -> Result<ExecutorHandle> {
-        // Start the executor and return a handle
+        let executor = self.executor.clone();
+        let task = tokio::spawn(async move {
+            if let Err(e) = executor.start().await {
+                error!("Task executor failed: {}", e);
+            }
+        });
+
         Ok(ExecutorHandle {
-            // Initialize handle fields
+            executor: self.executor.clone(),
+            task: tokio::sync::Mutex::new(Some(task)),
         })
     }
 }
 
 pub struct ExecutorHandle {
-    // Add necessary fields here
+    executor: TaskExecutor,
+    task: tokio::sync::Mutex<Option<JoinHandle<()>>>,
 }
 
 impl ExecutorHandle {
+    /// Shuts the executor down gracefully, see [`TaskExecutor::shutdown`].
     pub async fn shutdown(&self) -> Result<()> {
-        // Implement shutdown logic
+        self.executor.shutdown().await?;
+        if let Some(task) = self.task.lock().await.take() {
+            task.await?;
+        }
         Ok(())
     }
 }
+
+/// What code generated by [`task`] refers to. Not public API.
+#[doc(hidden)]
+pub mod __private {
+    pub use anyhow;
+    pub use async_trait::async_trait;
+    pub use chrono;
+    pub use scheduler_core;
+    pub use serde_json;
+
+    use chrono::{DateTime, Utc};
+    use scheduler_core::{
+        db::{CreatedJob, JobDependencies, JobOrigin, JobRetry},
+        task::TaskManager,
+    };
+    use serde::{de::DeserializeOwned, Serialize};
+    use serde_json::{json, Value};
+
+    use crate::TaskError;
+
+    /// Reads a task's arguments from its job's payload. Arguments that do not
+    /// fit the task never will, so the job is not retried.
+    pub fn args<T: DeserializeOwned>(kind: &str, mut payload: Value) -> Result<T, TaskError> {
+        serde_json::from_value(payload["args"].take())
+            .map_err(|e| TaskError::Permanent(format!("Invalid arguments for {} job: {}", kind, e)))
+    }
+
+    /// What a task function may return.
+    pub trait TaskOutput {
+        fn into_output(self) -> Result<Value, TaskError>;
+    }
+
+    impl<T: Serialize, E: Into<TaskError>> TaskOutput for Result<T, E> {
+        fn into_output(self) -> Result<Value, TaskError> {
+            let output = self.map_err(Into::into)?;
+            serde_json::to_value(output)
+                .map_err(|e| TaskError::Permanent(format!("Failed to serialize result: {}", e)))
+        }
+    }
+
+    pub async fn enqueue<T: Serialize>(
+        client: &TaskManager,
+        kind: &str,
+        args: &T,
+        at: DateTime<Utc>,
+        max_retries: Option<i32>,
+        origin: JobOrigin,
+    ) -> anyhow::Result<CreatedJob> {
+        let mut retry = JobRetry::default();
+        if let Some(max_retries) = max_retries {
+            retry.max_retries = max_retries;
+        }
+
+        client
+            .create_one_time_job(
+                Some(at),
+                0,
+                json!({ "kind": kind, "args": args }),
+                origin,
+                JobDependencies::default(),
+                retry,
+            )
+            .await
+    }
+}
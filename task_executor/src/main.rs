@@ -3,8 +3,8 @@ use scheduler_core::{
     config::Config,
     db::Database,
 };
-use std::time::Duration;
-use task_executor::TaskExecutor;
+use task_executor::{ExecutorConfig, TaskExecutor};
+use tokio::signal;
 use tracing::{error, info};
 
 #[tokio::main]
@@ -18,29 +18,61 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Initialize database and cache
     let db = Database::new(&config.database_url).await?;
     let cache = Cache::new(CacheConfig {
-        url: config.redis_url,
+        url: config.redis_url.clone(),
         max_connections: 10,
     })
     .await?;
 
     // Create task executor
-    let executor = TaskExecutor::new(
-        db,
-        cache,
-        Duration::from_secs(300), // 5 minute timeout
-        1024,                     // 1GB memory limit
-        50,                       // 50% CPU limit
-        10,                       // 10 concurrent jobs
-    )
-    .await?;
+    let executor = TaskExecutor::new(db, cache, ExecutorConfig::from_core_config(&config)).await?;
 
     info!("Starting task executor");
 
-    // Start the executor
-    if let Err(e) = executor.start().await {
-        error!("Task executor failed: {}", e);
-        return Err(e.into());
+    // Take jobs until the executor fails or a shutdown signal arrives
+    let mut run = tokio::spawn({
+        let executor = executor.clone();
+        async move { executor.start().await }
+    });
+
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("Failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("Failed to install signal handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        result = &mut run => {
+            if let Err(e) = executor.deregister().await {
+                error!("Failed to deregister worker {}: {}", executor.worker_id(), e);
+            }
+            if let Err(e) = result? {
+                error!("Task executor failed: {}", e);
+                return Err(e.into());
+            }
+            return Ok(());
+        }
+        _ = ctrl_c => {
+            info!("Received Ctrl+C, shutting down...");
+        }
+        _ = terminate => {
+            info!("Received termination signal, shutting down...");
+        }
     }
 
+    executor.shutdown().await?;
+    run.await??;
+
+    info!("Task executor shutdown complete");
     Ok(())
 }
@@ -1,84 +1,477 @@
 use std::{
-    process::{Command, Output},
+    collections::HashMap,
+    future::Future,
+    os::fd::RawFd,
+    path::PathBuf,
+    process::{Output, Stdio},
+    sync::{Arc, Mutex},
     time::Duration,
 };
 use sys_info;
-use tokio::time;
-use tracing::{error, info};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    process::Command,
+    signal::unix::{signal, SignalKind},
+    task::JoinHandle,
+    time,
+};
+use tracing::{error, info, warn};
+use uuid::Uuid;
 
+use crate::cgroup::{CgroupLimits, CgroupManager, JobCgroup, ResourceUsage};
 use crate::error::Error;
+use crate::sandbox::{Sandbox, SandboxProfile};
+use crate::script::{Script, ScriptFile};
+
+/// Variables of the executor's environment a sandboxed command still gets.
+const SANDBOX_ENV: [&str; 2] = ["PATH", "LANG"];
 
 pub struct ProcessManager {
     pub timeout: Duration,
+    /// Time between SIGTERM and SIGKILL when stopping a command.
+    pub kill_grace: Duration,
     pub max_memory_mb: u64,
     pub max_cpu_percent: u32,
+    pub max_pids: u64,
+    /// Set when commands run in their own cgroup; otherwise only memory is
+    /// limited, with `RLIMIT_AS`.
+    cgroups: Option<CgroupManager>,
+    /// Profiles jobs are allowed to ask for, by name.
+    sandboxes: HashMap<String, Arc<Sandbox>>,
+    /// Interpreters script jobs are allowed to ask for.
+    interpreters: Vec<String>,
+}
+
+/// How a command ended, and what it consumed getting there.
+pub struct Execution {
+    pub result: Result<Output, Error>,
+    /// What the command wrote before it was stopped, or before its output
+    /// was given up on; `result` holds the output of any other run.
+    pub partial_output: Option<Output>,
+    pub usage: ResourceUsage,
+}
+
+/// Why a run failed, with what the command wrote if it got to run.
+struct Failure {
+    error: Error,
+    output: Option<Output>,
+}
+
+impl From<Error> for Failure {
+    fn from(error: Error) -> Self {
+        Self {
+            error,
+            output: None,
+        }
+    }
+}
+
+/// Why a command was stopped before it exited on its own.
+enum Stop {
+    TimedOut,
+    Cancelled,
 }
 
 impl ProcessManager {
-    pub fn new(timeout: Duration, max_memory_mb: u64, max_cpu_percent: u32) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        timeout: Duration,
+        kill_grace: Duration,
+        max_memory_mb: u64,
+        max_cpu_percent: u32,
+        max_pids: u64,
+        cgroup_root: Option<PathBuf>,
+        sandbox_profiles: Vec<SandboxProfile>,
+        interpreters: Vec<String>,
+    ) -> Self {
+        let cgroups = cgroup_root.and_then(|root| match CgroupManager::new(&root) {
+            Ok(cgroups) => {
+                info!("Running jobs in cgroups under {}", root.display());
+                Some(cgroups)
+            }
+            Err(e) => {
+                warn!(
+                    "Cannot use cgroups under {} ({}); limiting job memory with rlimits, \
+                     CPU and process limits are not enforced",
+                    root.display(),
+                    e
+                );
+                None
+            }
+        });
+
+        let sandboxes: HashMap<_, _> = sandbox_profiles
+            .into_iter()
+            .filter_map(|profile| {
+                let name = profile.name.clone();
+                match Sandbox::new(profile) {
+                    Ok(sandbox) => Some((name, Arc::new(sandbox))),
+                    Err(e) => {
+                        warn!("Sandbox profile {} is unavailable: {}", name, e);
+                        None
+                    }
+                }
+            })
+            .collect();
+        // SAFETY: geteuid has no memory safety requirements
+        if !sandboxes.is_empty() && unsafe { libc::geteuid() } != 0 {
+            warn!(
+                "Sandboxed jobs will fail to start: sandboxing needs the executor to run as root"
+            );
+        }
+
         Self {
             timeout,
+            kill_grace,
             max_memory_mb,
             max_cpu_percent,
+            max_pids,
+            cgroups,
+            sandboxes,
+            interpreters,
         }
     }
 
+    /// Runs the command to completion and returns its output whatever the exit
+    /// status, in a cgroup of its own named after `run_id` when cgroups are
+    /// in use. The command runs in its own process group; if it outlives the
+    /// timeout or `cancelled` resolves first, the group gets SIGTERM and, after
+    /// the kill grace period, SIGKILL. Processes the command left behind in its
+    /// group or cgroup are killed once it exits, and its output is read for no
+    /// longer than the timeout plus the kill grace period; what a command that
+    /// was stopped wrote is kept in [`Execution::partial_output`]. A command
+    /// run in the `sandbox` profile fails unless that profile is allowed, and
+    /// only sees `PATH`, `LANG` and `env_vars` of its environment.
     pub async fn execute_command(
         &self,
+        run_id: Uuid,
         command: &str,
         args: &[String],
         env_vars: &[(String, String)],
-    ) -> Result<Output, Error> {
+        sandbox: Option<&str>,
+        cancelled: impl Future<Output = ()>,
+    ) -> Execution {
+        self.execute(run_id, command, args, env_vars, sandbox, None, cancelled)
+            .await
+    }
+
+    /// Runs a script job's script like [`execute_command`](Self::execute_command)
+    /// runs a command, with its interpreter, which must be one this executor
+    /// allows. The script is written to a private file for the run and removed
+    /// once it is over.
+    pub async fn execute_script(
+        &self,
+        run_id: Uuid,
+        script: &Script,
+        cancelled: impl Future<Output = ()>,
+    ) -> Execution {
+        let file = if !self.interpreters.contains(&script.interpreter) {
+            Err(Error::Unsupported {
+                capability: format!("script:{}", script.interpreter),
+                message: format!(
+                    "Interpreter {} is not allowed on this executor",
+                    script.interpreter
+                ),
+            })
+        } else {
+            ScriptFile::create(run_id, &script.body)
+                .map_err(|e| Error::Script(format!("Failed to write script: {}", e)))
+        };
+        let file = match file {
+            Ok(file) => file,
+            Err(e) => {
+                return Execution {
+                    result: Err(e),
+                    partial_output: None,
+                    usage: ResourceUsage::default(),
+                }
+            }
+        };
+
+        let mut args = vec![file.path()];
+        args.extend(script.args.iter().cloned());
+        self.execute(
+            run_id,
+            &script.interpreter,
+            &args,
+            &[],
+            script.sandbox.as_deref(),
+            Some(file.fd()),
+            cancelled,
+        )
+        .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn execute(
+        &self,
+        run_id: Uuid,
+        command: &str,
+        args: &[String],
+        env_vars: &[(String, String)],
+        sandbox: Option<&str>,
+        inherited_fd: Option<RawFd>,
+        cancelled: impl Future<Output = ()>,
+    ) -> Execution {
+        let sandbox = match sandbox.map(|name| self.sandbox(name)).transpose() {
+            Ok(sandbox) => sandbox,
+            Err(e) => {
+                return Execution {
+                    result: Err(e),
+                    partial_output: None,
+                    usage: ResourceUsage::default(),
+                }
+            }
+        };
+        let cgroup = self.create_cgroup(run_id);
+        let run = self
+            .run(
+                command,
+                args,
+                env_vars,
+                cgroup.as_ref(),
+                sandbox,
+                inherited_fd,
+                cancelled,
+            )
+            .await;
+        let usage = cgroup.as_ref().map(JobCgroup::usage).unwrap_or_default();
+        if let Some(cgroup) = cgroup {
+            cgroup.remove().await;
+        }
+
+        let (result, partial_output) = match run {
+            Ok(output) => (Ok(output), None),
+            Err(Failure { error, output }) => (Err(error), output),
+        };
+        Execution {
+            result,
+            partial_output,
+            usage,
+        }
+    }
+
+    fn sandbox(&self, name: &str) -> Result<Arc<Sandbox>, Error> {
+        self.sandboxes
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Error::Unsupported {
+                capability: format!("sandbox:{}", name),
+                message: format!("Sandbox profile {} is not allowed on this executor", name),
+            })
+    }
+
+    fn create_cgroup(&self, run_id: Uuid) -> Option<JobCgroup> {
+        let cgroups = self.cgroups.as_ref()?;
+        let limits = CgroupLimits {
+            memory_max_bytes: (self.max_memory_mb > 0).then(|| self.max_memory_mb * 1024 * 1024),
+            cpu_percent: (self.max_cpu_percent > 0).then_some(self.max_cpu_percent),
+            pids_max: (self.max_pids > 0).then_some(self.max_pids),
+        };
+        match cgroups.create(&format!("run-{}", run_id), &limits) {
+            Ok(cgroup) => Some(cgroup),
+            Err(e) => {
+                warn!(
+                    "Failed to create cgroup for run {}, falling back to rlimits: {}",
+                    run_id, e
+                );
+                None
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn run(
+        &self,
+        command: &str,
+        args: &[String],
+        env_vars: &[(String, String)],
+        cgroup: Option<&JobCgroup>,
+        sandbox: Option<Arc<Sandbox>>,
+        inherited_fd: Option<RawFd>,
+        cancelled: impl Future<Output = ()>,
+    ) -> Result<Output, Failure> {
         let mut cmd = Command::new(command);
-        cmd.args(args);
+        cmd.args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        // Lead a new process group, so the command and everything it spawns
+        // can be signalled together
+        #[cfg(unix)]
+        cmd.process_group(0);
+
+        // A sandboxed command does not inherit the executor's environment,
+        // which can hold its credentials; it only gets what it needs to find
+        // programs and decode text, and the job's own variables
+        if sandbox.is_some() {
+            cmd.env_clear();
+            for key in SANDBOX_ENV {
+                if let Some(value) = std::env::var_os(key) {
+                    cmd.env(key, value);
+                }
+            }
+        }
 
         // Set environment variables
         for (key, value) in env_vars {
             cmd.env(key, value);
         }
 
-        // Set resource limits
+        // Set resource limits: join the run's cgroup, or limit the address
+        // space when there is none, then enter the sandbox. The cgroup is
+        // joined first so that it covers the sandbox's processes too, and is
+        // kept open until the child has spawned.
+        #[cfg(target_os = "linux")]
+        let procs = cgroup
+            .map(JobCgroup::procs_file)
+            .transpose()
+            .map_err(|e| Error::ResourceLimit(format!("Failed to open cgroup: {}", e)))?;
         #[cfg(target_os = "linux")]
         {
-            use std::os::unix::process::CommandExt;
-            cmd.pre_exec(|| {
-                // Set memory limit
-                if self.max_memory_mb > 0 {
-                    let rlimit = libc::rlimit {
-                        rlim_cur: self.max_memory_mb * 1024 * 1024,
-                        rlim_max: self.max_memory_mb * 1024 * 1024,
-                    };
-                    unsafe {
+            use std::os::fd::AsRawFd;
+
+            let procs_fd = procs.as_ref().map(|file| file.as_raw_fd());
+            let max_memory_mb = self.max_memory_mb;
+            let sandbox = sandbox.clone();
+            unsafe {
+                cmd.pre_exec(move || {
+                    if let Some(fd) = procs_fd {
+                        // "0" moves the writing process, i.e. the child
+                        if libc::write(fd, b"0".as_ptr().cast(), 1) < 0 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                    } else if max_memory_mb > 0 {
+                        // Set memory limit
+                        let rlimit = libc::rlimit {
+                            rlim_cur: max_memory_mb * 1024 * 1024,
+                            rlim_max: max_memory_mb * 1024 * 1024,
+                        };
                         libc::setrlimit(libc::RLIMIT_AS, &rlimit);
                     }
-                }
-                Ok(())
-            });
+                    // Keep the descriptor open across exec, at the same number
+                    if let Some(fd) = inherited_fd {
+                        if libc::fcntl(fd, libc::F_SETFD, 0) < 0 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                    }
+                    if let Some(sandbox) = &sandbox {
+                        sandbox.enter()?;
+                    }
+                    Ok(())
+                });
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = (cgroup, inherited_fd);
+
+        match sandbox.as_deref() {
+            Some(sandbox) => info!(
+                "Executing command: {} {:?} in sandbox {}",
+                command,
+                args,
+                sandbox.profile().name
+            ),
+            None => info!("Executing command: {} {:?}", command, args),
+        }
+
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| Error::Process(format!("Failed to execute command: {}", e)))?;
+        let pgid = child.id();
+        let deadline = time::Instant::now() + self.timeout + self.kill_grace;
+
+        // Drain the pipes concurrently so a chatty command cannot block on a
+        // full pipe while we wait for it
+        let stdout = Capture::start(child.stdout.take());
+        let stderr = Capture::start(child.stderr.take());
+
+        let stop = tokio::select! {
+            _ = exited(pgid) => None,
+            _ = time::sleep(self.timeout) => Some(Stop::TimedOut),
+            _ = cancelled => Some(Stop::Cancelled),
+        };
+        if stop.is_some() {
+            self.terminate(pgid).await;
         }
 
-        info!("Executing command: {} {:?}", command, args);
+        // Whatever the command left behind could keep its pipes open, so it
+        // goes before they are drained. The command is not reaped yet, so
+        // its process group id cannot have been handed out again.
+        kill_group(pgid);
+        if let Some(cgroup) = cgroup {
+            cgroup.kill();
+        }
+        let status = child
+            .wait()
+            .await
+            .map_err(|e| Error::Process(format!("Failed to execute command: {}", e)));
 
-        // Execute with timeout
-        let output = time::timeout(self.timeout, async {
-            cmd.output()
-                .map_err(|e| Error::Process(format!("Failed to execute command: {}", e)))
+        // Without a cgroup, a process that left the group can still hold
+        // the pipes; stop reading once the run is out of time
+        let output = match status {
+            Ok(status) => {
+                drain(stdout, stderr, deadline)
+                    .await
+                    .map(|(stdout, stderr, complete)| {
+                        let output = Output {
+                            status,
+                            stdout,
+                            stderr,
+                        };
+                        (output, complete)
+                    })
+            }
+            Err(e) => Err(e),
+        };
+        let error = match stop {
+            Some(Stop::TimedOut) => {
+                Error::TimedOut(format!("{} ran longer than {:?}", command, self.timeout))
+            }
+            Some(Stop::Cancelled) => {
+                Error::Cancelled(format!("{} was cancelled while running", command))
+            }
+            None => match output? {
+                (output, true) => {
+                    if !output.status.success() {
+                        error!(
+                            "Command failed with status {}: {}",
+                            output.status,
+                            String::from_utf8_lossy(&output.stderr)
+                        );
+                    }
+                    return Ok(output);
+                }
+                (output, false) => {
+                    return Err(Failure {
+                        error: Error::TimedOut(format!(
+                            "{} kept its output open longer than {:?}",
+                            command,
+                            self.timeout + self.kill_grace
+                        )),
+                        output: Some(output),
+                    })
+                }
+            },
+        };
+        Err(Failure {
+            error,
+            output: output.ok().map(|(output, _)| output),
         })
-        .await
-        .map_err(|_| Error::Timeout(format!("Command timed out after {:?}", self.timeout)))??;
+    }
 
-        if !output.status.success() {
-            error!(
-                "Command failed with status {}: {}",
-                output.status,
-                String::from_utf8_lossy(&output.stderr)
+    /// Asks the command's process group to exit with SIGTERM and waits out
+    /// the kill grace period for the command to do so, without reaping it.
+    async fn terminate(&self, pgid: Option<u32>) {
+        signal_group(pgid, libc::SIGTERM);
+        if time::timeout(self.kill_grace, exited(pgid)).await.is_err() {
+            warn!(
+                "Process group {:?} still running {:?} after SIGTERM, sending SIGKILL",
+                pgid, self.kill_grace
             );
-            return Err(Error::Process(format!(
-                "Command failed with status {}",
-                output.status
-            )));
         }
-
-        Ok(output)
     }
 
     pub fn validate_resources(&self) -> Result<(), Error> {
@@ -100,3 +493,130 @@ impl ProcessManager {
         Ok(())
     }
 }
+
+/// A command's output pipe, read to the end by a task of its own into a
+/// buffer that keeps what was read even if the task is given up on.
+struct Capture {
+    buf: Arc<Mutex<Vec<u8>>>,
+    reader: JoinHandle<std::io::Result<()>>,
+}
+
+impl Capture {
+    fn start<R>(pipe: Option<R>) -> Self
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+    {
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let shared = buf.clone();
+        let reader = tokio::spawn(async move {
+            let Some(mut pipe) = pipe else {
+                return Ok(());
+            };
+            let mut chunk = [0; 8192];
+            loop {
+                let read = pipe.read(&mut chunk).await?;
+                if read == 0 {
+                    return Ok(());
+                }
+                shared.lock().unwrap().extend_from_slice(&chunk[..read]);
+            }
+        });
+        Self { buf, reader }
+    }
+
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.buf.lock().unwrap())
+    }
+}
+
+/// Reads both pipes until they close or `deadline` passes, and returns what
+/// was read and whether both were read to the end.
+async fn drain(
+    mut stdout: Capture,
+    mut stderr: Capture,
+    deadline: time::Instant,
+) -> Result<(Vec<u8>, Vec<u8>, bool), Error> {
+    let read = time::timeout_at(deadline, async {
+        let stdout = (&mut stdout.reader).await;
+        let stderr = (&mut stderr.reader).await;
+        (stdout, stderr)
+    })
+    .await;
+    let complete = match read {
+        Ok((out, err)) => {
+            for result in [out, err] {
+                result
+                    .map_err(|e| Error::Process(format!("Failed to read command output: {}", e)))?
+                    .map_err(|e| Error::Process(format!("Failed to read command output: {}", e)))?;
+            }
+            true
+        }
+        Err(_) => {
+            stdout.reader.abort();
+            stderr.reader.abort();
+            false
+        }
+    };
+    Ok((stdout.take(), stderr.take(), complete))
+}
+
+/// Resolves once the process `pid` has exited, without reaping it: until
+/// [`Child::wait`](tokio::process::Child::wait) does, neither its process
+/// id nor its process group id can be reused.
+async fn exited(pid: Option<u32>) {
+    let Some(pid) = pid else {
+        return;
+    };
+    // Listen before checking, so an exit in between still wakes us up
+    let mut children = match signal(SignalKind::child()) {
+        Ok(children) => children,
+        Err(e) => {
+            error!("Failed to wait for process {}: {}", pid, e);
+            return;
+        }
+    };
+    loop {
+        // SAFETY: siginfo_t is plain data, for waitid to fill in
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        // SAFETY: info is a valid siginfo_t; WNOWAIT leaves the process to
+        // be reaped by Child::wait
+        let waited = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                pid as libc::id_t,
+                &mut info,
+                libc::WEXITED | libc::WNOWAIT | libc::WNOHANG,
+            )
+        };
+        // The process cannot be waited for, so Child::wait reports why
+        if waited != 0 {
+            return;
+        }
+        // SAFETY: waitid filled in info; si_pid stays 0 while pid is running
+        if unsafe { info.si_pid() } != 0 {
+            return;
+        }
+        if children.recv().await.is_none() {
+            return;
+        }
+    }
+}
+
+fn kill_group(pgid: Option<u32>) {
+    signal_group(pgid, libc::SIGKILL);
+}
+
+/// Signals every process in the group led by `pgid`. A group that is already
+/// gone is not an error.
+fn signal_group(pgid: Option<u32>, signal: libc::c_int) {
+    let Some(pgid) = pgid else {
+        return;
+    };
+    // SAFETY: killpg has no memory safety requirements
+    if unsafe { libc::killpg(pgid as libc::pid_t, signal) } != 0 {
+        let err = std::io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::ESRCH) {
+            warn!("Failed to signal process group {}: {}", pgid, err);
+        }
+    }
+}
@@ -0,0 +1,434 @@
+use std::io;
+
+/// Isolation a job asks for by name, with the `sandbox` field of its payload.
+/// Entering any of it needs the executor to run as root.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SandboxProfile {
+    pub name: String,
+    /// User and group to run as; `None` keeps the executor's. Supplementary
+    /// groups are dropped whenever either is set.
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// Needed for `read_only_root` and `private_tmp`.
+    pub mount_namespace: bool,
+    /// The command runs as PID 1 of the namespace, so it only gets SIGTERM
+    /// if it handles it; otherwise it is killed once the kill grace is up.
+    pub pid_namespace: bool,
+    /// No network at all, not even loopback.
+    pub network_namespace: bool,
+    pub read_only_root: bool,
+    /// A fresh tmpfs on /tmp.
+    pub private_tmp: bool,
+    /// Applies the default seccomp filter, see [`DENIED_SYSCALLS`].
+    pub seccomp: bool,
+}
+
+impl SandboxProfile {
+    /// Names of the built-in profiles, from least to most isolated.
+    pub const BUILTIN: [&'static str; 3] = ["unprivileged", "isolated", "offline"];
+
+    /// The built-in profile called `name`, running jobs as `uid` and `gid`:
+    ///
+    /// - `unprivileged` only drops to that user under the default seccomp
+    ///   filter
+    /// - `isolated` adds mount and PID namespaces, a read-only root and a
+    ///   private /tmp
+    /// - `offline` also takes the job off the network
+    pub fn builtin(name: &str, uid: u32, gid: u32) -> Option<Self> {
+        let profile = match name {
+            "unprivileged" => Self {
+                uid: Some(uid),
+                gid: Some(gid),
+                seccomp: true,
+                ..Self::default()
+            },
+            "isolated" => Self {
+                mount_namespace: true,
+                pid_namespace: true,
+                read_only_root: true,
+                private_tmp: true,
+                ..Self::builtin("unprivileged", uid, gid)?
+            },
+            "offline" => Self {
+                network_namespace: true,
+                ..Self::builtin("isolated", uid, gid)?
+            },
+            _ => return None,
+        };
+
+        Some(Self {
+            name: name.to_string(),
+            ..profile
+        })
+    }
+}
+
+/// A profile prepared to be entered between fork and exec, where nothing may
+/// allocate.
+pub struct Sandbox {
+    profile: SandboxProfile,
+    #[cfg(target_os = "linux")]
+    filter: Vec<libc::sock_filter>,
+}
+
+impl Sandbox {
+    pub fn new(profile: SandboxProfile) -> io::Result<Self> {
+        if (profile.read_only_root || profile.private_tmp) && !profile.mount_namespace {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "sandbox profile {} needs a mount namespace for a read-only root \
+                     or private /tmp",
+                    profile.name
+                ),
+            ));
+        }
+
+        #[cfg(target_os = "linux")]
+        {
+            let filter = if profile.seccomp {
+                seccomp::filter()?
+            } else {
+                Vec::new()
+            };
+            Ok(Self { profile, filter })
+        }
+        #[cfg(not(target_os = "linux"))]
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "sandbox profile {}: sandboxing is only supported on Linux",
+                profile.name
+            ),
+        ))
+    }
+
+    pub fn profile(&self) -> &SandboxProfile {
+        &self.profile
+    }
+
+    /// Moves the calling process into the sandbox: new namespaces first, then
+    /// the mounts, then the user and group, and the seccomp filter last.
+    ///
+    /// # Safety
+    ///
+    /// Only to be called in a forked child about to exec. With a PID namespace
+    /// the calling process forks again and never returns; it waits for the
+    /// command and exits with its status.
+    #[cfg(target_os = "linux")]
+    pub unsafe fn enter(&self) -> io::Result<()> {
+        use std::ptr::null;
+
+        let profile = &self.profile;
+        let mut namespaces = 0;
+        if profile.mount_namespace {
+            namespaces |= libc::CLONE_NEWNS;
+        }
+        if profile.pid_namespace {
+            namespaces |= libc::CLONE_NEWPID;
+        }
+        if profile.network_namespace {
+            namespaces |= libc::CLONE_NEWNET;
+        }
+        if namespaces != 0 {
+            check(libc::unshare(namespaces))?;
+        }
+
+        if profile.mount_namespace {
+            // Keep the mounts below from propagating back to the host
+            check(libc::mount(
+                null(),
+                c"/".as_ptr(),
+                null(),
+                libc::MS_REC | libc::MS_PRIVATE,
+                null(),
+            ))?;
+            if profile.read_only_root {
+                make_root_read_only()?;
+            }
+            if profile.private_tmp {
+                check(libc::mount(
+                    c"tmpfs".as_ptr(),
+                    c"/tmp".as_ptr(),
+                    c"tmpfs".as_ptr(),
+                    libc::MS_NOSUID | libc::MS_NODEV,
+                    c"mode=1777".as_ptr().cast(),
+                ))?;
+            }
+        }
+
+        if profile.pid_namespace {
+            become_pid_one()?;
+            if profile.mount_namespace {
+                // Show the namespace's own processes in /proc
+                check(libc::mount(
+                    c"proc".as_ptr(),
+                    c"/proc".as_ptr(),
+                    c"proc".as_ptr(),
+                    libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                    null(),
+                ))?;
+            }
+        }
+
+        if profile.uid.is_some() || profile.gid.is_some() {
+            check(libc::setgroups(0, null()))?;
+        }
+        if let Some(gid) = profile.gid {
+            check(libc::setgid(gid))?;
+        }
+        if let Some(uid) = profile.uid {
+            check(libc::setuid(uid))?;
+        }
+
+        // Also keeps setuid binaries from regaining what was dropped
+        check(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))?;
+        if profile.seccomp {
+            let program = libc::sock_fprog {
+                len: self.filter.len() as libc::c_ushort,
+                filter: self.filter.as_ptr().cast_mut(),
+            };
+            check(libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                &program as *const libc::sock_fprog,
+            ))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn check(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+/// Makes every mount in the (new) mount namespace read-only.
+#[cfg(target_os = "linux")]
+unsafe fn make_root_read_only() -> io::Result<()> {
+    let attr = libc::mount_attr {
+        attr_set: libc::MOUNT_ATTR_RDONLY,
+        attr_clr: 0,
+        propagation: 0,
+        userns_fd: 0,
+    };
+    let ret = libc::syscall(
+        libc::SYS_mount_setattr,
+        libc::AT_FDCWD,
+        c"/".as_ptr(),
+        libc::AT_RECURSIVE,
+        &attr as *const libc::mount_attr,
+        std::mem::size_of::<libc::mount_attr>(),
+    );
+    if ret == 0 {
+        return Ok(());
+    }
+    let err = io::Error::last_os_error();
+    if err.raw_os_error() != Some(libc::ENOSYS) {
+        return Err(err);
+    }
+
+    // Before Linux 5.12 only the root mount itself can be made read-only
+    check(libc::mount(
+        std::ptr::null(),
+        c"/".as_ptr(),
+        std::ptr::null(),
+        libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY,
+        std::ptr::null(),
+    ))?;
+    Ok(())
+}
+
+/// `unshare` only puts the caller's children in a new PID namespace, so fork
+/// its first process and return in that to exec the command. The caller
+/// stays behind for the executor to wait on and exits the way the command
+/// does.
+#[cfg(target_os = "linux")]
+unsafe fn become_pid_one() -> io::Result<()> {
+    let pid = check(libc::fork())?;
+    if pid == 0 {
+        return Ok(());
+    }
+
+    // Never exec'ing, this process would hold on to every descriptor it was
+    // forked with, the one the spawning side waits to see closed on exec
+    // included. The command's process still has them.
+    if libc::syscall(libc::SYS_close_range, 3, libc::c_uint::MAX, 0) != 0 {
+        for fd in 3..libc::sysconf(libc::_SC_OPEN_MAX) as libc::c_int {
+            libc::close(fd);
+        }
+    }
+
+    // Termination is up to the command; the process group gets SIGKILL once
+    // it had its chance
+    libc::signal(libc::SIGTERM, libc::SIG_IGN);
+    let mut status = 0;
+    while libc::waitpid(pid, &mut status, 0) < 0 {
+        if io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+            libc::_exit(127);
+        }
+    }
+    if libc::WIFSIGNALED(status) {
+        let signal = libc::WTERMSIG(status);
+        libc::signal(signal, libc::SIG_DFL);
+        libc::kill(libc::getpid(), signal);
+    }
+    libc::_exit(libc::WEXITSTATUS(status));
+}
+
+/// Syscalls the default seccomp filter fails with EPERM: changing mounts,
+/// namespaces, the clock or the kernel, and reaching into other processes.
+#[cfg(target_os = "linux")]
+pub const DENIED_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_pivot_root,
+    libc::SYS_chroot,
+    libc::SYS_fsopen,
+    libc::SYS_fsconfig,
+    libc::SYS_fsmount,
+    libc::SYS_fspick,
+    libc::SYS_move_mount,
+    libc::SYS_open_tree,
+    libc::SYS_mount_setattr,
+    libc::SYS_unshare,
+    libc::SYS_setns,
+    libc::SYS_open_by_handle_at,
+    libc::SYS_name_to_handle_at,
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+    libc::SYS_kcmp,
+    libc::SYS_pidfd_getfd,
+    libc::SYS_bpf,
+    libc::SYS_perf_event_open,
+    libc::SYS_userfaultfd,
+    libc::SYS_keyctl,
+    libc::SYS_add_key,
+    libc::SYS_request_key,
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+    libc::SYS_kexec_load,
+    libc::SYS_kexec_file_load,
+    libc::SYS_reboot,
+    libc::SYS_swapon,
+    libc::SYS_swapoff,
+    libc::SYS_acct,
+    libc::SYS_quotactl,
+    libc::SYS_syslog,
+    libc::SYS_settimeofday,
+    libc::SYS_clock_settime,
+    libc::SYS_clock_adjtime,
+    libc::SYS_adjtimex,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_iopl,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_ioperm,
+];
+
+/// Builds the default seccomp filter as classic BPF.
+#[cfg(target_os = "linux")]
+mod seccomp {
+    use super::DENIED_SYSCALLS;
+    use libc::{seccomp_data, sock_filter};
+    use std::{io, mem::offset_of};
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: Option<u32> = Some(0xC000_003E);
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: Option<u32> = Some(0xC000_00B7);
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    const AUDIT_ARCH: Option<u32> = None;
+
+    /// Set in the numbers of x32 syscalls, which would dodge the deny list.
+    #[cfg(target_arch = "x86_64")]
+    const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+    const CLONE_NAMESPACES: u32 = (libc::CLONE_NEWNS
+        | libc::CLONE_NEWPID
+        | libc::CLONE_NEWNET
+        | libc::CLONE_NEWUSER
+        | libc::CLONE_NEWIPC
+        | libc::CLONE_NEWUTS
+        | libc::CLONE_NEWCGROUP) as u32;
+
+    pub(super) fn filter() -> io::Result<Vec<sock_filter>> {
+        let arch = AUDIT_ARCH.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                "seccomp filters are not supported on this architecture",
+            )
+        })?;
+
+        // Syscall numbers differ between ABIs, so kill anything calling in
+        // with another one
+        let mut filter = vec![
+            load(offset_of!(seccomp_data, arch)),
+            jump(libc::BPF_JEQ, arch, 1, 0),
+            ret(libc::SECCOMP_RET_KILL_PROCESS),
+            load(offset_of!(seccomp_data, nr)),
+        ];
+        #[cfg(target_arch = "x86_64")]
+        filter.extend([
+            jump(libc::BPF_JGE, X32_SYSCALL_BIT, 0, 1),
+            ret(errno(libc::EPERM)),
+        ]);
+        for &nr in DENIED_SYSCALLS {
+            filter.extend([
+                jump(libc::BPF_JEQ, nr as u32, 0, 1),
+                ret(errno(libc::EPERM)),
+            ]);
+        }
+        // clone3 passes its flags in memory the filter cannot read; ENOSYS
+        // makes libc fall back to clone, whose flags it can
+        filter.extend([
+            jump(libc::BPF_JEQ, libc::SYS_clone3 as u32, 0, 1),
+            ret(errno(libc::ENOSYS)),
+            jump(libc::BPF_JEQ, libc::SYS_clone as u32, 0, 3),
+            // The low half of the flags, on little-endian targets
+            load(offset_of!(seccomp_data, args)),
+            jump(libc::BPF_JSET, CLONE_NAMESPACES, 0, 1),
+            ret(errno(libc::EPERM)),
+            ret(libc::SECCOMP_RET_ALLOW),
+        ]);
+
+        Ok(filter)
+    }
+
+    fn load(offset: usize) -> sock_filter {
+        sock_filter {
+            code: (libc::BPF_LD | libc::BPF_W | libc::BPF_ABS) as u16,
+            jt: 0,
+            jf: 0,
+            k: offset as u32,
+        }
+    }
+
+    fn jump(op: u32, k: u32, jt: u8, jf: u8) -> sock_filter {
+        sock_filter {
+            code: (libc::BPF_JMP | op | libc::BPF_K) as u16,
+            jt,
+            jf,
+            k,
+        }
+    }
+
+    fn ret(action: u32) -> sock_filter {
+        sock_filter {
+            code: (libc::BPF_RET | libc::BPF_K) as u16,
+            jt: 0,
+            jf: 0,
+            k: action,
+        }
+    }
+
+    fn errno(errno: i32) -> u32 {
+        libc::SECCOMP_RET_ERRNO | errno as u32
+    }
+}
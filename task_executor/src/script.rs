@@ -0,0 +1,139 @@
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, DirBuilder, File, OpenOptions},
+    io::{self, Write},
+    os::{
+        fd::{AsRawFd, RawFd},
+        unix::fs::{DirBuilderExt, OpenOptionsExt},
+    },
+    path::{Path, PathBuf},
+};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::error::Error;
+
+/// A script a `script` job runs, read from its payload:
+///
+/// ```json
+/// {
+///     "kind": "script",
+///     "interpreter": "python3",
+///     "script": "import sys\nprint(sys.argv[1:])",
+///     "args": ["--dry-run"],
+///     "sandbox": "isolated"
+/// }
+/// ```
+///
+/// `interpreter` and `script` are required, and the interpreter must be one
+/// the executor allows. `args` are passed to the script and `sandbox` works
+/// as for commands.
+#[derive(Debug, Clone)]
+pub struct Script {
+    pub interpreter: String,
+    pub body: String,
+    pub args: Vec<String>,
+    pub sandbox: Option<String>,
+}
+
+impl Script {
+    pub fn from_payload(payload: &Value) -> Result<Self, Error> {
+        let interpreter = payload["interpreter"]
+            .as_str()
+            .ok_or_else(|| Error::Script("Missing interpreter in payload".into()))?;
+        let body = payload["script"]
+            .as_str()
+            .ok_or_else(|| Error::Script("Missing script in payload".into()))?;
+        let args = match &payload["args"] {
+            Value::Null => Vec::new(),
+            args => args
+                .as_array()
+                .ok_or_else(|| Error::Script("args is not a list".into()))?
+                .iter()
+                .map(|arg| {
+                    arg.as_str()
+                        .map(String::from)
+                        .ok_or_else(|| Error::Script(format!("Invalid argument: {}", arg)))
+                })
+                .collect::<Result<_, _>>()?,
+        };
+        let sandbox = match &payload["sandbox"] {
+            Value::Null => None,
+            sandbox => Some(
+                sandbox
+                    .as_str()
+                    .ok_or_else(|| {
+                        Error::Sandbox("Sandbox in payload is not a profile name".into())
+                    })?
+                    .to_string(),
+            ),
+        };
+
+        Ok(Self {
+            interpreter: interpreter.to_string(),
+            body: body.to_string(),
+            args,
+            sandbox,
+        })
+    }
+
+    /// Hex-encoded SHA-256 of the script body, recorded with each run.
+    pub fn hash(&self) -> String {
+        format!("{:x}", Sha256::digest(self.body.as_bytes()))
+    }
+}
+
+/// A script written out for one run, in a directory only the executor's user
+/// can enter. The interpreter reads it through a descriptor it inherits, so
+/// it can do so from inside a sandbox, as another user or behind a private
+/// `/tmp`. Both are removed when this is dropped.
+pub struct ScriptFile {
+    dir: PathBuf,
+    file: File,
+}
+
+impl ScriptFile {
+    pub fn create(run_id: Uuid, body: &str) -> io::Result<Self> {
+        let dir = std::env::temp_dir().join(format!("task-executor-script-{}", run_id));
+        DirBuilder::new().mode(0o700).create(&dir)?;
+        match Self::write(&dir, body) {
+            Ok(file) => Ok(Self { dir, file }),
+            Err(e) => {
+                let _ = fs::remove_dir_all(&dir);
+                Err(e)
+            }
+        }
+    }
+
+    fn write(dir: &Path, body: &str) -> io::Result<File> {
+        // Readable by whoever the interpreter runs as, but only reachable
+        // through the directory or the descriptor
+        let path = dir.join("script");
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o444)
+            .open(&path)?
+            .write_all(body.as_bytes())?;
+        File::open(&path)
+    }
+
+    /// The descriptor the interpreter inherits.
+    pub fn fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+
+    /// The path the interpreter opens the script by.
+    pub fn path(&self) -> String {
+        format!("/proc/self/fd/{}", self.fd())
+    }
+}
+
+impl Drop for ScriptFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.dir) {
+            warn!("Failed to remove script {}: {}", self.dir.display(), e);
+        }
+    }
+}
@@ -1,15 +1,27 @@
 use chrono::Utc;
-use scheduler_core::models::{Job, JobStatus};
+use scheduler_core::{
+    db::JobRunOutcome,
+    models::{Job, JobStatus},
+};
+use std::process::Output;
 use tracing::{error, info};
 
-use crate::error::Error;
+use crate::{cgroup::ResourceUsage, error::Error, http::HttpResponse, script::Script};
 
 pub struct ExecutionState {
     pub job: Job,
     pub start_time: chrono::DateTime<Utc>,
     pub end_time: Option<chrono::DateTime<Utc>>,
     pub output: Option<String>,
+    pub stderr: Option<String>,
+    pub exit_code: Option<i32>,
+    pub http_status: Option<u16>,
+    pub response_body: Option<String>,
+    pub result: Option<serde_json::Value>,
+    pub script_hash: Option<String>,
     pub error: Option<String>,
+    pub error_class: Option<&'static str>,
+    pub usage: ResourceUsage,
 }
 
 impl ExecutionState {
@@ -19,61 +31,92 @@ impl ExecutionState {
             start_time: Utc::now(),
             end_time: None,
             output: None,
+            stderr: None,
+            exit_code: None,
+            http_status: None,
+            response_body: None,
+            result: None,
+            script_hash: None,
             error: None,
+            error_class: None,
+            usage: ResourceUsage::default(),
         }
     }
 
-    pub fn mark_running(&mut self) -> Result<(), Error> {
-        if self.job.status != JobStatus::Pending && self.job.status != JobStatus::Retrying {
+    /// Applies `next` using the same transition table the database enforces.
+    fn transition(&mut self, next: JobStatus) -> Result<(), Error> {
+        if !self.job.status.can_transition_to(next) {
             return Err(Error::StateTransition(format!(
-                "Cannot transition to running from {:?}",
-                self.job.status
+                "Cannot transition to {} from {}",
+                next, self.job.status
             )));
         }
 
-        self.job.status = JobStatus::Running;
+        self.job.status = next;
+        Ok(())
+    }
+
+    /// Attempt number of the run about to start, counting from 1.
+    pub fn attempt(&self) -> i32 {
+        self.job.retries + 1
+    }
+
+    pub fn mark_running(&mut self) -> Result<(), Error> {
+        self.transition(JobStatus::Running)?;
+        self.start_time = Utc::now();
         info!("Job {} marked as running", self.job.id);
         Ok(())
     }
 
-    pub fn mark_completed(&mut self, output: String) -> Result<(), Error> {
-        if self.job.status != JobStatus::Running {
-            return Err(Error::StateTransition(format!(
-                "Cannot transition to completed from {:?}",
-                self.job.status
-            )));
-        }
+    pub fn record_output(&mut self, output: &Output) {
+        self.output = Some(String::from_utf8_lossy(&output.stdout).into());
+        self.stderr = Some(String::from_utf8_lossy(&output.stderr).into());
+        self.exit_code = output.status.code();
+    }
+
+    pub fn record_response(&mut self, response: &HttpResponse) {
+        self.http_status = Some(response.status);
+        self.response_body = Some(response.body.clone());
+    }
+
+    pub fn record_result(&mut self, result: serde_json::Value) {
+        self.result = Some(result);
+    }
+
+    pub fn record_script(&mut self, script: &Script) {
+        self.script_hash = Some(script.hash());
+    }
 
-        self.job.status = JobStatus::Completed;
+    pub fn record_usage(&mut self, usage: &ResourceUsage) {
+        self.usage = *usage;
+    }
+
+    pub fn mark_completed(&mut self) -> Result<(), Error> {
+        self.transition(JobStatus::Completed)?;
         self.end_time = Some(Utc::now());
-        self.output = Some(output);
         info!("Job {} marked as completed", self.job.id);
         Ok(())
     }
 
-    pub fn mark_failed(&mut self, error: String) -> Result<(), Error> {
-        if self.job.status != JobStatus::Running {
-            return Err(Error::StateTransition(format!(
-                "Cannot transition to failed from {:?}",
-                self.job.status
-            )));
-        }
-
-        self.job.status = JobStatus::Failed;
+    pub fn mark_failed(&mut self, error: &Error) -> Result<(), Error> {
+        self.transition(JobStatus::Failed)?;
         self.end_time = Some(Utc::now());
-        self.error = Some(error.clone());
+        self.error = Some(error.to_string());
+        self.error_class = Some(error.class());
         error!("Job {} failed: {}", self.job.id, error);
         Ok(())
     }
 
-    pub fn mark_retrying(&mut self) -> Result<(), Error> {
-        if self.job.status != JobStatus::Failed {
-            return Err(Error::StateTransition(format!(
-                "Cannot transition to retrying from {:?}",
-                self.job.status
-            )));
-        }
+    pub fn mark_cancelled(&mut self, error: &Error) -> Result<(), Error> {
+        self.transition(JobStatus::Cancelled)?;
+        self.end_time = Some(Utc::now());
+        self.error = Some(error.to_string());
+        self.error_class = Some(error.class());
+        info!("Job {} cancelled while running", self.job.id);
+        Ok(())
+    }
 
+    pub fn mark_retrying(&mut self) -> Result<(), Error> {
         if self.job.retries >= self.job.max_retries {
             return Err(Error::StateTransition(format!(
                 "Max retries ({}) exceeded for job {}",
@@ -81,7 +124,7 @@ impl ExecutionState {
             )));
         }
 
-        self.job.status = JobStatus::Pending;
+        self.transition(JobStatus::Retrying)?;
         self.job.retries += 1;
         info!(
             "Job {} marked for retry (attempt {}/{})",
@@ -89,4 +132,21 @@ impl ExecutionState {
         );
         Ok(())
     }
+
+    /// What gets written to this attempt's `job_runs` row.
+    pub fn run_outcome(&self) -> JobRunOutcome {
+        JobRunOutcome {
+            exit_code: self.exit_code,
+            stdout: self.output.clone(),
+            stderr: self.stderr.clone(),
+            error_class: self.error_class.map(String::from),
+            error_message: self.error.clone(),
+            peak_memory_bytes: self.usage.peak_memory_bytes.map(|bytes| bytes as i64),
+            cpu_time_ms: self.usage.cpu_time.map(|cpu| cpu.as_millis() as i64),
+            http_status: self.http_status.map(i32::from),
+            response_body: self.response_body.clone(),
+            result: self.result.clone(),
+            script_hash: self.script_hash.clone(),
+        }
+    }
 }
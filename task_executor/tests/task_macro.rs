@@ -0,0 +1,123 @@
+use chrono::Utc;
+use scheduler_core::{
+    db::{Database, JobOrigin},
+    task::TaskManager,
+};
+use serde_json::json;
+use task_executor::{task, TaskContext, TaskError, TaskRegistry};
+use uuid::Uuid;
+
+#[task]
+async fn add(args: (i64, i64)) -> anyhow::Result<i64> {
+    Ok(args.0 + args.1)
+}
+
+#[task(name = "describe_attempt", retries = 5)]
+async fn attempt(ctx: TaskContext, label: String) -> Result<String, TaskError> {
+    ctx.heartbeat().await?;
+    Ok(format!("{} #{} of {}", label, ctx.attempt(), ctx.job_id()))
+}
+
+fn registry() -> TaskRegistry {
+    attempt::register(add::register(TaskRegistry::new()))
+}
+
+#[test]
+fn names_default_to_the_function() {
+    assert_eq!(add::NAME, "add");
+    assert_eq!(attempt::NAME, "describe_attempt");
+
+    let registry = registry();
+    let mut kinds: Vec<_> = registry.kinds().collect();
+    kinds.sort();
+    assert_eq!(kinds, ["add", "describe_attempt"]);
+}
+
+#[tokio::test]
+async fn registered_handlers_run_the_function() {
+    let registry = registry();
+
+    let (ctx, _stop) = TaskContext::detached("job-1", 1);
+    let handler = registry.get(add::NAME).unwrap();
+    let result = handler
+        .handle(ctx, json!({ "kind": add::NAME, "args": [2, 3] }))
+        .await
+        .unwrap();
+    assert_eq!(result, json!(5));
+
+    let (ctx, _stop) = TaskContext::detached("job-2", 3);
+    let handler = registry.get(attempt::NAME).unwrap();
+    let result = handler
+        .handle(ctx, json!({ "kind": attempt::NAME, "args": "nightly" }))
+        .await
+        .unwrap();
+    assert_eq!(result, json!("nightly #3 of job-2"));
+}
+
+#[tokio::test]
+async fn arguments_of_the_wrong_shape_fail_for_good() {
+    let (ctx, _stop) = TaskContext::detached("job-3", 1);
+    let handler = registry().get(add::NAME).unwrap();
+    let error = handler
+        .handle(ctx, json!({ "kind": add::NAME, "args": "two" }))
+        .await
+        .unwrap_err();
+    assert!(matches!(error, TaskError::Permanent(_)), "{:?}", error);
+}
+
+#[tokio::test]
+async fn enqueue_with_origin_is_idempotent() {
+    let db = Database::new(&std::env::var("DATABASE_URL").expect("DATABASE_URL"))
+        .await
+        .unwrap();
+    let client = TaskManager::new(db.clone());
+    let merchant_id: Uuid =
+        sqlx::query_scalar("INSERT INTO merchants (name) VALUES ($1) RETURNING id")
+            .bind(format!("task-macro-{}", Uuid::new_v4()))
+            .fetch_one(db.pool())
+            .await
+            .unwrap();
+    let origin = JobOrigin {
+        merchant_id: Some(merchant_id),
+        reference_id: Some(format!("task-macro-{}", Uuid::new_v4())),
+    };
+
+    let created = attempt::enqueue_with_origin(&client, "once".into(), Utc::now(), origin.clone())
+        .await
+        .unwrap();
+    let replayed =
+        attempt::enqueue_with_origin(&client, "twice".into(), Utc::now(), origin.clone())
+            .await
+            .unwrap();
+    assert!(!created.replayed);
+    assert!(replayed.replayed);
+    assert_eq!(replayed.id, created.id);
+
+    let job = client
+        .get_job(&created.id.to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        job.payload,
+        json!({ "kind": attempt::NAME, "args": "once" })
+    );
+    assert_eq!(job.max_retries, 5);
+    assert_eq!(job.merchant_id, origin.merchant_id);
+
+    sqlx::query("DELETE FROM idempotency_keys WHERE merchant_id = $1")
+        .bind(origin.merchant_id)
+        .execute(db.pool())
+        .await
+        .unwrap();
+    sqlx::query("DELETE FROM jobs WHERE id = $1")
+        .bind(created.id)
+        .execute(db.pool())
+        .await
+        .unwrap();
+    sqlx::query("DELETE FROM merchants WHERE id = $1")
+        .bind(merchant_id)
+        .execute(db.pool())
+        .await
+        .unwrap();
+}
@@ -1,5 +1,5 @@
 use async_trait::async_trait;
-use scheduler_core::task::Job;
+use scheduler_core::Job;
 use std::collections::HashMap;
 use tokio::sync::Mutex;
 use tracing::{error, info};
@@ -37,7 +37,8 @@ impl AlertManager {
         let mut last_alert_time = self.last_alert_time.lock().await;
         let now = chrono::Utc::now();
 
-        if let Some(last_time) = last_alert_time.get(&job.id) {
+        let job_id = job.id.to_string();
+        if let Some(last_time) = last_alert_time.get(&job_id) {
             if now - *last_time < self.cooldown_period {
                 info!("Skipping alert for job {} due to cooldown period", job.id);
                 return;
@@ -50,7 +51,7 @@ impl AlertManager {
             }
         }
 
-        last_alert_time.insert(job.id.clone(), now);
+        last_alert_time.insert(job_id, now);
     }
 
     pub async fn alert_dead_letter(&self, job: &Job) {
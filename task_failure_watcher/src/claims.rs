@@ -0,0 +1,230 @@
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use scheduler_core::{
+    cache::{Cache, JOB_QUEUE},
+    db::JobUpdate,
+    task::TaskManager,
+    Job, JobStatus, SchedulerError,
+};
+use std::time::Duration as StdDuration;
+use tokio::time::sleep;
+use tracing::{error, info, warn};
+
+/// Stale claims checked per sweep.
+const BATCH_SIZE: i64 = 100;
+
+/// Hands back jobs the queue populator claimed that never made it into the
+/// queue, e.g. because it crashed between the claim and the push or Redis
+/// lost the message. A `Queued` job whose claim is older than `stale_after`
+/// and that is neither waiting in the queue nor reserved by a worker goes
+/// back to `Pending`, for the populator to claim and push again.
+pub struct ClaimSweeper {
+    task_manager: TaskManager,
+    cache: Cache,
+    stale_after: Duration,
+    check_interval: StdDuration,
+}
+
+impl ClaimSweeper {
+    pub fn new(
+        task_manager: TaskManager,
+        cache: Cache,
+        stale_after: Duration,
+        check_interval: StdDuration,
+    ) -> Self {
+        Self {
+            task_manager,
+            cache,
+            stale_after,
+            check_interval,
+        }
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        info!("Starting claim sweeper");
+        loop {
+            if let Err(e) = self.sweep().await {
+                error!("Error sweeping stale claims: {}", e);
+            }
+            sleep(self.check_interval).await;
+        }
+    }
+
+    async fn sweep(&self) -> Result<()> {
+        let cutoff = Utc::now() - self.stale_after;
+        let jobs = self
+            .task_manager
+            .get_stale_claims(cutoff, BATCH_SIZE)
+            .await?;
+        if jobs.is_empty() {
+            return Ok(());
+        }
+
+        let ids: Vec<String> = jobs.iter().map(|job| job.id.to_string()).collect();
+        let enqueued = self.cache.enqueued(JOB_QUEUE, &ids).await?;
+        let released = self.release_lost(jobs, &enqueued).await;
+        if released > 0 {
+            info!(
+                "Released {} claimed jobs that never reached the queue",
+                released
+            );
+        }
+        Ok(())
+    }
+
+    /// Moves the jobs that are not `enqueued` back to `Pending` and returns
+    /// how many it moved.
+    async fn release_lost(&self, jobs: Vec<Job>, enqueued: &[String]) -> usize {
+        let mut released = 0;
+        for job in jobs {
+            let job_id = job.id.to_string();
+            if enqueued.contains(&job_id) {
+                continue;
+            }
+            match self.release(&job).await {
+                Ok(true) => released += 1,
+                Ok(false) => {}
+                Err(e) => error!("Error releasing job {}: {}", job_id, e),
+            }
+        }
+        released
+    }
+
+    /// Fenced by the token the claim issued, so a job that was claimed again
+    /// since it was read is left alone.
+    async fn release(&self, job: &Job) -> Result<bool> {
+        let update = JobUpdate::new()
+            .claimed_by(None)
+            .claimed_at(None)
+            .fenced_by(job.fencing_token);
+        match self
+            .task_manager
+            .transition_job_with(
+                &job.id.to_string(),
+                JobStatus::Queued,
+                JobStatus::Pending,
+                &update,
+            )
+            .await
+        {
+            Ok(()) => {
+                warn!(
+                    "Released job {}: claimed by {} at {:?} but never queued",
+                    job.id,
+                    job.claimed_by.as_deref().unwrap_or("unknown"),
+                    job.claimed_at
+                );
+                Ok(true)
+            }
+            Err(e) => match e.downcast_ref::<SchedulerError>() {
+                // Started, cancelled or claimed again in the meantime
+                Some(
+                    SchedulerError::TransitionConflict { .. }
+                    | SchedulerError::StaleFencingToken { .. },
+                ) => Ok(false),
+                _ => Err(e),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scheduler_core::{cache::CacheConfig, db::Database};
+
+    const CLAIMANT: &str = "claim-sweeper-test";
+
+    async fn sweeper() -> (ClaimSweeper, Database) {
+        let db = Database::new(&std::env::var("DATABASE_URL").expect("DATABASE_URL"))
+            .await
+            .unwrap();
+        // Never connected to: the test hands the sweeper what Redis holds
+        let cache = Cache::new(CacheConfig {
+            url: "redis://127.0.0.1:6379".to_string(),
+            max_connections: 1,
+        })
+        .await
+        .unwrap();
+        let sweeper = ClaimSweeper::new(
+            TaskManager::new(db.clone()),
+            cache,
+            Duration::minutes(5),
+            StdDuration::from_secs(60),
+        );
+        (sweeper, db)
+    }
+
+    async fn claimed_job(db: &Database, claimed_mins_ago: i32) -> String {
+        sqlx::query_scalar(
+            r#"
+            INSERT INTO jobs (id, status, priority, scheduled_at, max_retries, retries, payload,
+                              fencing_token, claimed_by, claimed_at, created_at, updated_at)
+            VALUES (gen_random_uuid(), 'queued', 0, NOW(), 3, 0, '{}', 1, $1,
+                    NOW() - make_interval(mins => $2), NOW(), NOW() - make_interval(mins => $2))
+            RETURNING id::text
+            "#,
+        )
+        .bind(CLAIMANT)
+        .bind(claimed_mins_ago)
+        .fetch_one(db.pool())
+        .await
+        .unwrap()
+    }
+
+    async fn status(sweeper: &ClaimSweeper, id: &str) -> (JobStatus, Option<String>) {
+        let job = sweeper.task_manager.get_job(id).await.unwrap().unwrap();
+        (job.status, job.claimed_by)
+    }
+
+    #[tokio::test]
+    async fn releases_only_lost_claims() {
+        let (sweeper, db) = sweeper().await;
+        let lost = claimed_job(&db, 60).await;
+        let enqueued = claimed_job(&db, 60).await;
+        let reclaimed = claimed_job(&db, 60).await;
+        let fresh = claimed_job(&db, 1).await;
+        let ours = [&lost, &enqueued, &reclaimed, &fresh];
+
+        let cutoff = Utc::now() - sweeper.stale_after;
+        let stale: Vec<Job> = sweeper
+            .task_manager
+            .get_stale_claims(cutoff, i64::MAX)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|job| ours.contains(&&job.id.to_string()))
+            .collect();
+        let mut stale_ids: Vec<String> = stale.iter().map(|job| job.id.to_string()).collect();
+        stale_ids.sort();
+        let mut expected = vec![lost.clone(), enqueued.clone(), reclaimed.clone()];
+        expected.sort();
+        assert_eq!(stale_ids, expected);
+
+        // Claimed again after the sweep read it
+        sweeper
+            .task_manager
+            .update_job(&reclaimed, &JobUpdate::new().issue_fencing_token())
+            .await
+            .unwrap();
+
+        let released = sweeper
+            .release_lost(stale, std::slice::from_ref(&enqueued))
+            .await;
+        assert_eq!(released, 1);
+        assert_eq!(status(&sweeper, &lost).await, (JobStatus::Pending, None));
+        for id in [&enqueued, &reclaimed, &fresh] {
+            assert_eq!(
+                status(&sweeper, id).await,
+                (JobStatus::Queued, Some(CLAIMANT.to_string()))
+            );
+        }
+
+        sqlx::query("DELETE FROM jobs WHERE claimed_by = $1 OR id = $2::uuid")
+            .bind(CLAIMANT)
+            .bind(&lost)
+            .execute(db.pool())
+            .await
+            .unwrap();
+    }
+}
@@ -1,30 +1,40 @@
 use anyhow::Result;
 use chrono::{Duration, Utc};
 use scheduler_core::{
-    task::{Job, TaskManager},
-    JobStatus,
+    task::TaskManager,
+    DeadLetterReason, Job, JobStatus,
 };
 use tracing::{error, info};
 
+/// Jobs moved to, or purged from, the archive per transaction.
+const ARCHIVE_BATCH_SIZE: i64 = 500;
+
 pub struct CleanupManager {
     task_manager: TaskManager,
     cleanup_interval: Duration,
     max_age: Duration,
+    archive_retention: Duration,
 }
 
 impl CleanupManager {
-    pub fn new(task_manager: TaskManager, cleanup_interval: Duration, max_age: Duration) -> Self {
+    pub fn new(
+        task_manager: TaskManager,
+        cleanup_interval: Duration,
+        max_age: Duration,
+        archive_retention: Duration,
+    ) -> Self {
         Self {
             task_manager,
             cleanup_interval,
             max_age,
+            archive_retention,
         }
     }
 
     pub async fn start(&self) -> Result<()> {
         info!("Starting cleanup manager");
         loop {
-            if let Err(e) = self.cleanup_old_jobs().await {
+            if let Err(e) = self.cleanup().await {
                 error!("Error during cleanup: {}", e);
             }
             tokio::time::sleep(self.cleanup_interval.to_std().unwrap()).await;
@@ -33,16 +43,24 @@ impl CleanupManager {
 
     async fn cleanup(&self) -> Result<()> {
         self.cleanup_orphaned_jobs().await?;
-        self.cleanup_old_jobs().await?;
+        self.archive_old_jobs().await?;
+        self.purge_archive().await?;
         Ok(())
     }
 
+    /// Fallback for running jobs that never held a lease, e.g. ones started
+    /// before leases existed. Leased jobs are recovered by the lease monitor.
     async fn cleanup_orphaned_jobs(&self) -> Result<()> {
-        let cutoff_time = Utc::now() - Duration::hours(24); // Jobs older than 24 hours
+        let cutoff_time = Utc::now() - Duration::hours(24); // Running for over 24 hours
         let orphaned_jobs = self
             .task_manager
             .get_jobs_by_status_and_time(JobStatus::Running, cutoff_time)
-            .await?;
+            .await?
+            .into_iter()
+            .filter(|job| {
+                job.lease_expires_at.is_none()
+                    && job.last_run_at.unwrap_or(job.created_at) < cutoff_time
+            });
 
         for job in orphaned_jobs {
             info!("Cleaning up orphaned job: {}", job.id);
@@ -52,40 +70,61 @@ impl CleanupManager {
         Ok(())
     }
 
-    async fn cleanup_old_jobs(&self) -> Result<()> {
+    /// Moves terminal jobs older than `max_age` into the archive, one batch
+    /// per transaction, until none are left.
+    async fn archive_old_jobs(&self) -> Result<()> {
         let cutoff_time = Utc::now() - self.max_age;
-        let jobs = self.task_manager.get_jobs_older_than(cutoff_time).await?;
+        let mut total = 0;
+        loop {
+            let archived = self
+                .task_manager
+                .archive_jobs(cutoff_time, ARCHIVE_BATCH_SIZE)
+                .await?;
+            total += archived;
+            if archived < ARCHIVE_BATCH_SIZE as u64 {
+                break;
+            }
+        }
+
+        if total > 0 {
+            info!("Archived {} jobs", total);
+        }
+        Ok(())
+    }
 
-        for job in jobs {
-            if let Err(e) = self.archive_job(job).await {
-                error!("Error archiving job: {}", e);
+    async fn purge_archive(&self) -> Result<()> {
+        let cutoff_time = Utc::now() - self.archive_retention;
+        let mut total = 0;
+        loop {
+            let purged = self
+                .task_manager
+                .purge_archived_jobs(cutoff_time, ARCHIVE_BATCH_SIZE)
+                .await?;
+            total += purged;
+            if purged < ARCHIVE_BATCH_SIZE as u64 {
+                break;
             }
         }
 
+        if total > 0 {
+            info!("Purged {} archived jobs", total);
+        }
         Ok(())
     }
 
     async fn mark_job_as_failed(&self, job: Job) -> Result<()> {
         // Update job status to failed
         self.task_manager
-            .update_job_status(&job.id, JobStatus::Failed)
+            .transition_job(&job.id.to_string(), JobStatus::Running, JobStatus::Failed)
             .await?;
 
         // If job has exceeded max retries, move to dead letter queue
         if job.retries >= job.max_retries {
-            let dead_letter_queue = "dead_letter".to_string();
             self.task_manager
-                .move_to_dead_letter_queue(&job.id, &dead_letter_queue)
+                .dead_letter_job(&job.id.to_string(), DeadLetterReason::Orphaned)
                 .await?;
         }
 
         Ok(())
     }
-
-    async fn archive_job(&self, job: Job) -> Result<()> {
-        // Move job to archive table
-        self.task_manager.archive_job(&job.id).await?;
-        info!("Archived job: {}", job.id);
-        Ok(())
-    }
 }
@@ -0,0 +1,134 @@
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use scheduler_core::{
+    cache::{Cache, JOB_QUEUE},
+    db::JobUpdate,
+    task::TaskManager,
+    DeadLetterReason, Job, JobStatus, SchedulerError,
+};
+use std::time::Duration as StdDuration;
+use tokio::time::sleep;
+use tracing::{error, info, warn};
+
+/// Jobs whose lease expired that are recovered per check.
+const BATCH_SIZE: i64 = 100;
+
+/// How long a worker that stopped heartbeating stays in the registry.
+const WORKER_RETENTION_HOURS: i64 = 1;
+
+/// Recovers running jobs whose executor stopped heartbeating. A lost attempt
+/// counts against the job's retries: the job is requeued straight away while
+/// it has retries left and dead-lettered as orphaned otherwise.
+pub struct LeaseMonitor {
+    task_manager: TaskManager,
+    cache: Cache,
+    check_interval: StdDuration,
+}
+
+impl LeaseMonitor {
+    pub fn new(task_manager: TaskManager, cache: Cache, check_interval: StdDuration) -> Self {
+        Self {
+            task_manager,
+            cache,
+            check_interval,
+        }
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        info!("Starting lease monitor");
+        loop {
+            if let Err(e) = self.recover_expired().await {
+                error!("Error recovering expired leases: {}", e);
+            }
+            if let Err(e) = self.prune_workers().await {
+                error!("Error pruning dead workers: {}", e);
+            }
+            sleep(self.check_interval).await;
+        }
+    }
+
+    async fn recover_expired(&self) -> Result<()> {
+        for job in self.task_manager.get_expired_leases(BATCH_SIZE).await? {
+            let job_id = job.id;
+            if let Err(e) = self.recover(job).await {
+                error!("Error recovering job {}: {}", job_id, e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn prune_workers(&self) -> Result<()> {
+        let cutoff = Utc::now() - Duration::hours(WORKER_RETENTION_HOURS);
+        let pruned = self.task_manager.prune_workers(cutoff).await?;
+        if pruned > 0 {
+            info!("Removed {} dead workers from the registry", pruned);
+        }
+        Ok(())
+    }
+
+    async fn recover(&self, job: Job) -> Result<()> {
+        let job_id = job.id.to_string();
+        let worker_id = job.lease_owner.clone().unwrap_or_default();
+        let reason = format!("Lease expired on worker {}", worker_id);
+
+        let requeue = job.retries < job.max_retries;
+        let (to, update) = if requeue {
+            (JobStatus::Queued, JobUpdate::new().increment_retries())
+        } else {
+            (JobStatus::Failed, JobUpdate::new())
+        };
+        // Fenced, so a job that was reclaimed and restarted since it was read
+        // keeps its new attempt
+        let update = update
+            .last_error(Some(reason.clone()))
+            .last_error_class(Some("lease_expired".to_string()))
+            .lease_expires_at(None)
+            .fenced_by(job.fencing_token);
+
+        match self
+            .task_manager
+            .transition_job_with(&job_id, JobStatus::Running, to, &update)
+            .await
+        {
+            Ok(()) => {}
+            Err(e) => match e.downcast_ref::<SchedulerError>() {
+                // The worker finished or the job was cancelled after all
+                Some(
+                    SchedulerError::TransitionConflict { .. }
+                    | SchedulerError::StaleFencingToken { .. },
+                ) => return Ok(()),
+                _ => return Err(e),
+            },
+        }
+
+        if let Err(e) = self
+            .task_manager
+            .abandon_job_runs(&job_id, &worker_id, &reason)
+            .await
+        {
+            error!("Failed to close run of job {}: {}", job_id, e);
+        }
+
+        if requeue {
+            // Move the message out of the lost worker's processing list, or
+            // enqueue it afresh if the worker never reserved it there.
+            if !self.cache.nack(JOB_QUEUE, &worker_id, &job_id).await? {
+                self.cache.push_to_queue(JOB_QUEUE, &job_id).await?;
+            }
+            warn!(
+                "Requeued job {} after worker {} lost its lease",
+                job_id, worker_id
+            );
+        } else {
+            self.cache.ack(JOB_QUEUE, &worker_id, &job_id).await?;
+            self.task_manager
+                .dead_letter_job(&job_id, DeadLetterReason::Orphaned)
+                .await?;
+            warn!(
+                "Dead-lettered job {} after worker {} lost its lease on the last attempt",
+                job_id, worker_id
+            );
+        }
+        Ok(())
+    }
+}
@@ -1,7 +1,11 @@
 use alerting::{AlertManager, LogNotificationChannel};
 use anyhow::Result;
 use chrono::Duration;
+use claims::ClaimSweeper;
 use cleanup::CleanupManager;
+use lease::LeaseMonitor;
+use partition::PartitionManager;
+use reaper::QueueReaper;
 use scheduler_core::{
     cache::{Cache, CacheConfig},
     config::Config,
@@ -15,13 +19,17 @@ use tracing_subscriber::FmtSubscriber;
 use watcher::TaskFailureWatcher;
 
 mod alerting;
+mod claims;
 mod cleanup;
+mod lease;
+mod partition;
+mod reaper;
 mod watcher;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     // Initialize logging
-    let subscriber = FmtSubscriber::builder()
+    FmtSubscriber::builder()
         .with_max_level(Level::INFO)
         .with_target(false)
         .with_thread_ids(true)
@@ -54,18 +62,47 @@ async fn main() -> Result<()> {
     // Initialize failure watcher with core library types
     let failure_watcher = TaskFailureWatcher::new(
         task_manager.clone(),
-        cache.clone(),
-        StdDuration::from_secs(60),   // Check every minute
-        config.max_retries as i32,    // Use configurable max retries
-        StdDuration::from_secs(60),   // Initial backoff of 1 minute
-        StdDuration::from_secs(3600), // Max backoff of 1 hour
+        StdDuration::from_secs(60), // Check every minute
     );
 
     // Initialize cleanup manager
     let cleanup_manager = CleanupManager::new(
-        task_manager,
+        task_manager.clone(),
         Duration::hours(1), // Cleanup every hour
-        Duration::days(30), // Keep tasks for 30 days
+        Duration::days(config.archive_after_days),
+        Duration::days(config.archive_retention_days),
+    );
+
+    // Initialize partition manager for the range-partitioned jobs table
+    let partition_manager = PartitionManager::new(
+        task_manager.clone(),
+        StdDuration::from_secs(3600), // Check every hour
+        config.partition_interval,
+        config.partition_premake,
+        Duration::days(config.partition_retention_days),
+        config.drop_expired_partitions,
+    );
+
+    // Initialize lease monitor for jobs whose executor stopped heartbeating
+    let lease_monitor = LeaseMonitor::new(
+        task_manager.clone(),
+        cache.clone(),
+        StdDuration::from_secs(5), // Check every 5 seconds
+    );
+
+    // Initialize claim sweeper for claimed jobs that never reached the queue
+    let claim_sweeper = ClaimSweeper::new(
+        task_manager.clone(),
+        cache.clone(),
+        Duration::seconds(config.stale_claim_secs as i64),
+        StdDuration::from_secs(60), // Check every minute
+    );
+
+    // Initialize queue reaper for reservations that were never acknowledged
+    let queue_reaper = QueueReaper::new(
+        task_manager,
+        cache.clone(),
+        StdDuration::from_secs(10), // Check every 10 seconds
     );
 
     // Start all components
@@ -81,6 +118,30 @@ async fn main() -> Result<()> {
         }
     });
 
+    let partition_manager_handle = tokio::spawn(async move {
+        if let Err(e) = partition_manager.start().await {
+            error!("Partition manager error: {}", e);
+        }
+    });
+
+    let lease_monitor_handle = tokio::spawn(async move {
+        if let Err(e) = lease_monitor.start().await {
+            error!("Lease monitor error: {}", e);
+        }
+    });
+
+    let claim_sweeper_handle = tokio::spawn(async move {
+        if let Err(e) = claim_sweeper.start().await {
+            error!("Claim sweeper error: {}", e);
+        }
+    });
+
+    let queue_reaper_handle = tokio::spawn(async move {
+        if let Err(e) = queue_reaper.start().await {
+            error!("Queue reaper error: {}", e);
+        }
+    });
+
     // Handle shutdown signals
     let ctrl_c = async {
         signal::ctrl_c()
@@ -111,6 +172,10 @@ async fn main() -> Result<()> {
     // Wait for all components to finish
     failure_watcher_handle.abort();
     cleanup_manager_handle.abort();
+    partition_manager_handle.abort();
+    lease_monitor_handle.abort();
+    claim_sweeper_handle.abort();
+    queue_reaper_handle.abort();
 
     info!("Task Failure Watcher shutdown complete");
     Ok(())
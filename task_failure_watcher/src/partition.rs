@@ -0,0 +1,135 @@
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use scheduler_core::{models::PartitionInterval, task::TaskManager, JobPartition};
+use std::time::Duration as StdDuration;
+use tracing::{error, info, warn};
+
+/// Keeps the range-partitioned `jobs` table supplied with partitions: creates
+/// the current and upcoming ones ahead of time, detaches (or drops) those
+/// past retention once the archiver emptied them, and logs the size of each.
+pub struct PartitionManager {
+    task_manager: TaskManager,
+    check_interval: StdDuration,
+    interval: PartitionInterval,
+    premake: u32,
+    retention: Duration,
+    drop_expired: bool,
+}
+
+impl PartitionManager {
+    pub fn new(
+        task_manager: TaskManager,
+        check_interval: StdDuration,
+        interval: PartitionInterval,
+        premake: u32,
+        retention: Duration,
+        drop_expired: bool,
+    ) -> Self {
+        Self {
+            task_manager,
+            check_interval,
+            interval,
+            premake,
+            retention,
+            drop_expired,
+        }
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        info!("Starting partition manager");
+        loop {
+            if let Err(e) = self.maintain().await {
+                error!("Error maintaining job partitions: {}", e);
+            }
+            tokio::time::sleep(self.check_interval).await;
+        }
+    }
+
+    async fn maintain(&self) -> Result<()> {
+        let partitions = self.task_manager.get_job_partitions().await?;
+        self.create_upcoming(&partitions).await?;
+        self.expire_old(&partitions).await?;
+        self.report().await
+    }
+
+    /// Creates the partition for the current period plus `premake` more.
+    /// Ranges that overlap an existing partition, e.g. after switching from
+    /// monthly to weekly, are left alone.
+    async fn create_upcoming(&self, partitions: &[JobPartition]) -> Result<()> {
+        let mut from = self.interval.floor(Utc::now());
+        for _ in 0..=self.premake {
+            let to = self.interval.next(from);
+            let taken = partitions
+                .iter()
+                .any(|p| match (p.range_start, p.range_end) {
+                    (Some(start), Some(end)) => start < to && from < end,
+                    _ => false,
+                });
+            if !taken {
+                let name = format!("jobs_p{}", from.format("%Y%m%d"));
+                let moved = self
+                    .task_manager
+                    .create_job_partition(&name, from, to)
+                    .await?;
+                info!(
+                    "Created job partition {} for [{}, {}), moved {} rows from the default partition",
+                    name, from, to, moved
+                );
+            }
+            from = to;
+        }
+        Ok(())
+    }
+
+    async fn expire_old(&self, partitions: &[JobPartition]) -> Result<()> {
+        let cutoff = Utc::now() - self.retention;
+        for partition in partitions {
+            let Some(end) = partition.range_end else {
+                continue;
+            };
+            if end > cutoff {
+                continue;
+            }
+            match self
+                .task_manager
+                .detach_job_partition(&partition.name, self.drop_expired)
+                .await
+            {
+                Ok(true) => {}
+                Ok(false) => {
+                    warn!(
+                        "Keeping expired job partition {}: it still holds jobs that are \
+                         not terminal or not archived yet",
+                        partition.name
+                    );
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Could not expire job partition {}: {}", partition.name, e);
+                    continue;
+                }
+            }
+            info!(
+                "{} job partition {} ({} bytes)",
+                if self.drop_expired {
+                    "Dropped"
+                } else {
+                    "Detached"
+                },
+                partition.name,
+                partition.total_bytes
+            );
+        }
+        Ok(())
+    }
+
+    async fn report(&self) -> Result<()> {
+        for partition in self.task_manager.get_job_partitions().await? {
+            info!(
+                "Job partition {}: ~{} rows, {} bytes",
+                partition.name, partition.estimated_rows, partition.total_bytes
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+use anyhow::Result;
+use scheduler_core::{
+    cache::{Cache, Reservation, JOB_QUEUE},
+    task::TaskManager,
+    JobStatus,
+};
+use std::time::Duration as StdDuration;
+use tokio::time::sleep;
+use tracing::{error, info, warn};
+
+/// Returns jobs that executors reserved but never started, e.g. because the
+/// worker crashed right after reserving them, back to the job queue.
+pub struct QueueReaper {
+    task_manager: TaskManager,
+    cache: Cache,
+    check_interval: StdDuration,
+}
+
+impl QueueReaper {
+    pub fn new(task_manager: TaskManager, cache: Cache, check_interval: StdDuration) -> Self {
+        Self {
+            task_manager,
+            cache,
+            check_interval,
+        }
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        info!("Starting queue reaper for queue: {}", JOB_QUEUE);
+        loop {
+            if let Err(e) = self.reap_expired().await {
+                error!("Error reaping queue {}: {}", JOB_QUEUE, e);
+            }
+            sleep(self.check_interval).await;
+        }
+    }
+
+    async fn reap_expired(&self) -> Result<()> {
+        for reservation in self.cache.expired_reservations(JOB_QUEUE).await? {
+            if let Err(e) = self.reap(&reservation).await {
+                error!("Error reaping job {}: {}", reservation.value, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes a reserved message for a `Queued` job visible again. A job that
+    /// got as far as `Running` holds a lease in the database, and recovering
+    /// it is up to the lease monitor, which fences it and counts the lost
+    /// attempt; here its reservation is only dropped, as is that of any job
+    /// that moved on in the meantime.
+    async fn reap(&self, reservation: &Reservation) -> Result<()> {
+        let job_id = &reservation.value;
+        let status = self
+            .task_manager
+            .get_job(job_id)
+            .await?
+            .map(|job| job.status);
+        let requeue = status == Some(JobStatus::Queued);
+
+        if requeue {
+            self.cache
+                .nack(JOB_QUEUE, &reservation.worker_id, job_id)
+                .await?;
+            warn!(
+                "Requeued job {} after worker {} let its reservation expire",
+                job_id, reservation.worker_id
+            );
+        } else {
+            self.cache
+                .ack(JOB_QUEUE, &reservation.worker_id, job_id)
+                .await?;
+        }
+        Ok(())
+    }
+}
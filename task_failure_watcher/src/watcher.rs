@@ -1,38 +1,19 @@
 use anyhow::Result;
-use scheduler_core::{
-    cache::Cache,
-    task::{Job, TaskManager},
-    JobStatus,
-};
+use scheduler_core::{task::TaskManager, DeadLetterReason, Job, JobStatus};
 use std::time::Duration as StdDuration;
 use tokio::time::sleep;
 use tracing::{error, info};
 
 pub struct TaskFailureWatcher {
     task_manager: TaskManager,
-    cache: Cache,
     check_interval: StdDuration,
-    max_retries: i32,
-    initial_backoff: StdDuration,
-    max_backoff: StdDuration,
 }
 
 impl TaskFailureWatcher {
-    pub fn new(
-        task_manager: TaskManager,
-        cache: Cache,
-        check_interval: StdDuration,
-        max_retries: i32,
-        initial_backoff: StdDuration,
-        max_backoff: StdDuration,
-    ) -> Self {
+    pub fn new(task_manager: TaskManager, check_interval: StdDuration) -> Self {
         Self {
             task_manager,
-            cache,
             check_interval,
-            max_retries,
-            initial_backoff,
-            max_backoff,
         }
     }
 
@@ -74,51 +55,20 @@ impl TaskFailureWatcher {
         Ok(())
     }
 
-    fn calculate_backoff(&self, retry_count: u32) -> StdDuration {
-        let backoff = self.initial_backoff.as_secs() * 2u64.pow(retry_count);
-        StdDuration::from_secs(backoff.min(self.max_backoff.as_secs()))
-    }
-
-    async fn retry_job(&self, mut job: Job) -> Result<()> {
-        // Calculate backoff delay
-        let backoff = self.calculate_backoff(job.retries as u32);
-
-        // Update job status to retrying
-        self.task_manager
-            .update_job_status(&job.id, JobStatus::Retrying)
-            .await?;
-
-        // Wait for backoff period
-        sleep(backoff).await;
-
-        // Update job status back to pending for retry
-        self.task_manager
-            .update_job_status(&job.id, JobStatus::Pending)
-            .await?;
-
-        // Increment attempts counter
-        self.task_manager.increment_job_attempts(&job.id).await?;
-
+    /// Schedules the next attempt according to the job's retry policy and
+    /// returns straight away; the populator picks the job up once it is due.
+    async fn retry_job(&self, job: Job) -> Result<()> {
+        let run_at = self.task_manager.schedule_retry(&job).await?;
+        info!("Scheduled retry of job {} for {}", job.id, run_at);
         Ok(())
     }
 
     async fn move_to_dead_letter_queue(&self, job: Job) -> Result<()> {
-        // Update job status to indicate it's in dead letter queue
         self.task_manager
-            .update_job_status(&job.id, JobStatus::Failed)
-            .await?;
-
-        // Store job in dead letter queue
-        let dead_letter_queue = "dead_letter".to_string();
-        self.cache
-            .push_to_queue(&dead_letter_queue, &job.id)
+            .dead_letter_job(&job.id.to_string(), DeadLetterReason::RetriesExhausted)
             .await?;
 
         info!("Moved job {} to dead letter queue", job.id);
         Ok(())
     }
-
-    fn get_queue_name(&self, job: &Job) -> Option<String> {
-        Some("one_time_jobs".to_string())
-    }
 }
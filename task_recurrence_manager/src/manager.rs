@@ -2,7 +2,7 @@ use anyhow;
 use chrono::{DateTime, Duration, Utc};
 use scheduler_core::{
     cache::Cache,
-    db::{Database, JobData},
+    db::{Database, JobData, JobDependencies, JobUpdate},
     models::Template,
 };
 use tracing::{info, warn};
@@ -74,8 +74,8 @@ impl RecurrenceManager {
             for job in &batch {
                 let job_data = JobData {
                     status: job.status,
-                    priority: 0, // Default priority since it's not in the Job struct
-                    schedule_at: Some(job.schedule),
+                    priority: job.priority,
+                    schedule_at: Some(job.scheduled_at),
                     cron: None,
                     interval: None,
                     parent_job_id: None,
@@ -89,13 +89,17 @@ impl RecurrenceManager {
                     max_attempts: 3,
                     metadata: None,
                     name: None,
+                    merchant_id: None,
+                    reference_id: None,
+                    dependencies: JobDependencies::default(),
+                    retry_policy: job.retry_policy.clone(),
                 };
                 self.db.create_job(job_data).await?;
             }
 
             // Queue jobs for execution
             for job in batch {
-                if let Err(e) = self.cache.push_to_queue("default", &job.id).await {
+                if let Err(e) = self.cache.push_to_queue("default", &job.id.to_string()).await {
                     warn!("Failed to queue job {}: {}", job.id, e);
                 }
             }
@@ -129,9 +133,8 @@ impl RecurrenceManager {
             for job in jobs {
                 let adjusted_time = self.expander.handle_daylight_saving(job.created_at);
                 if adjusted_time != job.created_at {
-                    let mut updates = std::collections::HashMap::new();
-                    updates.insert("scheduled_at", adjusted_time.to_rfc3339());
-                    self.db.update_job(&job.id, &updates).await?;
+                    let update = JobUpdate::new().scheduled_at(adjusted_time);
+                    self.db.update_job(&job.id.to_string(), &update).await?;
                 }
             }
         }
@@ -39,14 +39,17 @@ impl ScheduleExpander {
                     }
 
                     let job = Job {
-                        id: uuid::Uuid::new_v4().to_string(),
-                        schedule: next_time,
+                        id: uuid::Uuid::new_v4(),
+                        scheduled_at: next_time,
                         payload: template.payload.clone(),
                         status: scheduler_core::models::JobStatus::Pending,
                         created_at: Utc::now(),
                         updated_at: Utc::now(),
                         retries: 0,
                         max_retries: 3,
+                        template_id: Some(template.id),
+                        retry_policy: template.retry_policy.clone(),
+                        ..Default::default()
                     };
 
                     jobs.push(job);
@@ -88,7 +91,7 @@ impl ScheduleOptimizer {
         let mut seen = HashSet::new();
 
         for job in jobs {
-            let key = format!("{}-{}", job.schedule, job.payload);
+            let key = format!("{}-{}", job.scheduled_at, job.payload);
             if !seen.contains(&key) {
                 seen.insert(key);
                 optimized.push(job);
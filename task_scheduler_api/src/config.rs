@@ -1,6 +1,6 @@
 use env_logger::Builder;
 use log::LevelFilter;
-use scheduler_core::{db::Database, task::TaskManager};
+use scheduler_core::{cache::Cache, db::Database, task::TaskManager, workflow::WorkflowManager};
 use serde_yaml::Value;
 use std::collections::HashMap;
 
@@ -9,13 +9,17 @@ use crate::guard::basic_auth::BasicAuth;
 #[derive(Debug, Clone)]
 pub struct AppConfig {
     pub task_manager: TaskManager,
+    pub workflow_manager: WorkflowManager,
+    pub cache: Cache,
     pub config: HashMap<String, Value>,
 }
 
 impl AppConfig {
-    pub fn new(db: Database) -> Self {
+    pub fn new(db: Database, cache: Cache) -> Self {
         Self {
-            task_manager: TaskManager::new(db),
+            task_manager: TaskManager::new(db.clone()),
+            workflow_manager: WorkflowManager::new(db),
+            cache,
             config: HashMap::new(),
         }
     }
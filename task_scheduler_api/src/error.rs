@@ -39,6 +39,10 @@ pub enum ApiError {
     RedisError(String),
     #[error("Validation Error: {0}")]
     ValidationError(String),
+    #[error("Conflict: {0}")]
+    Conflict(String),
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
     #[error("Missing API key")]
     MissingApiKey,
     #[error("Invalid API key")]
@@ -58,6 +62,18 @@ impl From<SchedulerError> for ApiError {
             SchedulerError::NotFound(e) => ApiError::NotFound(e),
             SchedulerError::BadRequest(e) => ApiError::BadRequest(e),
             SchedulerError::InternalServerError(e) => ApiError::InternalServerError(e),
+            e @ SchedulerError::InvalidTransition { .. } => ApiError::BadRequest(e.to_string()),
+            e @ SchedulerError::TransitionConflict { .. } => ApiError::Conflict(e.to_string()),
+            e @ SchedulerError::StaleFencingToken { .. } => ApiError::Conflict(e.to_string()),
+        }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(error: anyhow::Error) -> Self {
+        match error.downcast::<SchedulerError>() {
+            Ok(e) => e.into(),
+            Err(e) => ApiError::InternalServerError(e.to_string()),
         }
     }
 }
@@ -71,6 +87,8 @@ impl<'r> Responder<'r, 'static> for ApiError {
             ApiError::DatabaseError(msg) => (Status::InternalServerError, msg),
             ApiError::RedisError(msg) => (Status::InternalServerError, msg),
             ApiError::ValidationError(msg) => (Status::BadRequest, msg),
+            ApiError::Conflict(msg) => (Status::Conflict, msg),
+            ApiError::Unauthorized(msg) => (Status::Unauthorized, msg),
             ApiError::MissingApiKey => (Status::BadRequest, "Missing API key".to_string()),
             ApiError::InvalidApiKey => (Status::BadRequest, "Invalid API key".to_string()),
         };
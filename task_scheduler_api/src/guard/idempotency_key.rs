@@ -0,0 +1,22 @@
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+
+/// The optional `Idempotency-Key` header of a request.
+#[derive(Debug)]
+pub struct IdempotencyKey(pub Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IdempotencyKey {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let key = request
+            .headers()
+            .get_one("Idempotency-Key")
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .map(String::from);
+
+        Outcome::Success(IdempotencyKey(key))
+    }
+}
@@ -1,3 +1,4 @@
 pub mod api_key;
 pub mod basic_auth;
+pub mod idempotency_key;
 pub mod jwt_auth;
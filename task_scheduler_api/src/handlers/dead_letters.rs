@@ -0,0 +1,122 @@
+use crate::config::AppConfig;
+use crate::error::ApiError;
+use rocket::delete;
+use rocket::get;
+use rocket::post;
+use rocket::serde::json::{self, Json};
+use rocket::State;
+use scheduler_core::api_models::{DeadLetterReplay, DeleteResponse, JobResponse};
+use scheduler_core::models::{DeadLetterDetails, DeadLetterEntry};
+use uuid::Uuid;
+
+const DEFAULT_PAGE_SIZE: i64 = 100;
+
+fn parse_merchant_id(merchant_id: Option<String>) -> Result<Option<Uuid>, ApiError> {
+    merchant_id
+        .map(|id| Uuid::parse_str(&id))
+        .transpose()
+        .map_err(|e| ApiError::BadRequest(format!("Invalid merchant id: {}", e)))
+}
+
+fn not_found(id: &str) -> ApiError {
+    ApiError::NotFound(format!("Dead letter entry for job {} not found", id))
+}
+
+#[get("/dead-letters?<merchant_id>&<limit>&<offset>")]
+pub async fn list_dead_letters(
+    state: &State<AppConfig>,
+    merchant_id: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Result<Json<Vec<DeadLetterEntry>>, ApiError> {
+    let entries = state
+        .task_manager
+        .get_dead_letters(
+            parse_merchant_id(merchant_id)?,
+            limit.unwrap_or(DEFAULT_PAGE_SIZE),
+            offset.unwrap_or(0),
+        )
+        .await?;
+    Ok(Json(entries))
+}
+
+#[get("/dead-letters/<id>?<merchant_id>")]
+pub async fn get_dead_letter(
+    state: &State<AppConfig>,
+    id: String,
+    merchant_id: Option<String>,
+) -> Result<Json<DeadLetterDetails>, ApiError> {
+    match state
+        .task_manager
+        .get_dead_letter(&id, parse_merchant_id(merchant_id)?)
+        .await?
+    {
+        Some(details) => Ok(Json(details)),
+        None => Err(not_found(&id)),
+    }
+}
+
+/// Sends the job back to `Pending` with its retries reset. A JSON body with
+/// a `payload` replaces the job's payload first; an empty body keeps it, and
+/// a body that isn't a valid replay is rejected.
+#[post("/dead-letters/<id>/replay?<merchant_id>", data = "<replay>")]
+pub async fn replay_dead_letter(
+    state: &State<AppConfig>,
+    id: String,
+    merchant_id: Option<String>,
+    replay: Result<Json<DeadLetterReplay>, json::Error<'_>>,
+) -> Result<Json<JobResponse>, ApiError> {
+    let job_id =
+        Uuid::parse_str(&id).map_err(|e| ApiError::BadRequest(format!("Invalid job id: {}", e)))?;
+    let payload = match replay {
+        Ok(replay) => replay.into_inner().payload,
+        Err(json::Error::Parse(body, _)) if body.trim().is_empty() => None,
+        Err(e) => return Err(ApiError::BadRequest(format!("Invalid replay: {}", e))),
+    };
+    let replayed = state
+        .task_manager
+        .replay_dead_letter(&id, parse_merchant_id(merchant_id)?, payload)
+        .await?;
+    if !replayed {
+        return Err(not_found(&id));
+    }
+
+    Ok(Json(JobResponse {
+        message: "Job replayed successfully".to_string(),
+        job_id,
+    }))
+}
+
+#[delete("/dead-letters/<id>?<merchant_id>")]
+pub async fn purge_dead_letter(
+    state: &State<AppConfig>,
+    id: String,
+    merchant_id: Option<String>,
+) -> Result<Json<DeleteResponse>, ApiError> {
+    if !state
+        .task_manager
+        .purge_dead_letter(&id, parse_merchant_id(merchant_id)?)
+        .await?
+    {
+        return Err(not_found(&id));
+    }
+
+    Ok(Json(DeleteResponse {
+        message: format!("Dead letter entry for job {} purged successfully", id),
+    }))
+}
+
+#[delete("/dead-letters?<merchant_id>")]
+pub async fn purge_dead_letters(
+    state: &State<AppConfig>,
+    merchant_id: Option<String>,
+) -> Result<Json<DeleteResponse>, ApiError> {
+    let purged = state
+        .task_manager
+        .purge_dead_letters(parse_merchant_id(merchant_id)?)
+        .await?;
+
+    Ok(Json(DeleteResponse {
+        message: format!("Purged {} dead letter entries", purged),
+    }))
+}
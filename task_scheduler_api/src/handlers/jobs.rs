@@ -1,6 +1,7 @@
 use crate::config::AppConfig;
 use crate::error::ApiError;
-use chrono::Utc;
+use crate::guard::api_key::ApiKeyGuard;
+use crate::guard::idempotency_key::IdempotencyKey;
 use rocket::delete;
 use rocket::get;
 use rocket::post;
@@ -8,95 +9,160 @@ use rocket::put;
 use rocket::serde::json::Json;
 use rocket::State;
 use scheduler_core::api_models::{DeleteResponse, JobCreate, JobResponse, JobUpdate};
-use scheduler_core::models::{Job as CoreJob, JobStatus, JobType};
-use scheduler_core::task::Job as TaskJob;
-use std::collections::HashMap;
+use scheduler_core::cache::{CANCEL_CHANNEL, JOB_QUEUE};
+use scheduler_core::db::{JobDependencies, JobOrigin, JobRetry};
+use scheduler_core::models::{Job, JobRun, JobStatus, JobType};
 use uuid::Uuid;
 
-fn convert_task_job_to_core_job(task_job: TaskJob) -> CoreJob {
-    CoreJob {
-        id: task_job.id,
-        schedule: task_job.scheduled_at,
-        payload: serde_json::to_value(task_job.payload).unwrap(),
-        status: task_job.status,
-        created_at: Utc::now(),
-        updated_at: Utc::now(),
-        retries: task_job.retries,
-        max_retries: task_job.max_retries,
-    }
-}
-
 #[post("/jobs", format = "json", data = "<job>")]
 pub async fn create_job(
     state: &State<AppConfig>,
+    auth: Result<ApiKeyGuard, ApiError>,
+    idempotency_key: IdempotencyKey,
     job: Json<JobCreate>,
 ) -> Result<Json<JobResponse>, ApiError> {
     let job = job.into_inner();
-    let job_id = Uuid::new_v4();
 
-    // Convert payload to HashMap
-    let payload = serde_json::from_value::<HashMap<String, String>>(job.payload)
-        .map_err(|e| ApiError::ValidationError(format!("Invalid payload format: {}", e)))?;
+    // The API key is optional, but a key that was sent must be valid
+    let auth = match auth {
+        Ok(auth) => Some(auth),
+        Err(ApiError::MissingApiKey) => None,
+        Err(_) => return Err(ApiError::Unauthorized("Invalid API key".into())),
+    };
+
+    let reference_id = match (idempotency_key.0, job.reference_id) {
+        (Some(header), Some(body)) if header != body => {
+            return Err(ApiError::BadRequest(
+                "Idempotency-Key header and reference_id differ".into(),
+            ));
+        }
+        (header, body) => header.or(body),
+    };
+
+    // Jobs created with an API key, and the idempotency keys they are created
+    // under, belong to the merchant the key was issued to
+    let merchant_id = match auth {
+        Some(auth) => {
+            let merchant_id = auth.0.merchant.id;
+            if job.merchant_id.is_some_and(|id| id != merchant_id) {
+                return Err(ApiError::BadRequest(
+                    "merchant_id differs from the API key's merchant".into(),
+                ));
+            }
+            Some(merchant_id)
+        }
+        None if reference_id.is_some() => {
+            return Err(ApiError::Unauthorized(
+                "Idempotency keys need an API key".into(),
+            ));
+        }
+        None => job.merchant_id,
+    };
+
+    let origin = JobOrigin {
+        merchant_id,
+        reference_id,
+    };
+
+    if !job.depends_on.is_empty() && job.schedule_type != JobType::OneTime {
+        return Err(ApiError::ValidationError(
+            "depends_on is only supported for one-time jobs".into(),
+        ));
+    }
+    let dependencies = JobDependencies {
+        depends_on: job.depends_on,
+        on_upstream_failure: job.on_upstream_failure.unwrap_or_default(),
+    };
+
+    let retry_policy = job.retry_policy.unwrap_or_default();
+    retry_policy
+        .validate()
+        .map_err(|e| ApiError::ValidationError(e.to_string()))?;
+    let retry = JobRetry {
+        max_retries: job.max_retries.unwrap_or(3) as i32,
+        policy: retry_policy,
+    };
+
+    // The executor checks the fields a job's kind needs when it runs it
+    if !job.payload.is_object() {
+        return Err(ApiError::ValidationError(
+            "Invalid payload format: expected a JSON object".to_string(),
+        ));
+    }
+    let payload = job.payload;
 
-    match job.schedule_type {
+    let created = match job.schedule_type {
         JobType::OneTime => {
             state
                 .task_manager
-                .create_one_time_job(job.schedule_at, 0, payload)
-                .await
-                .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+                .create_one_time_job(job.schedule_at, 0, payload, origin, dependencies, retry)
+                .await?
         }
         JobType::Recurring => {
             state
                 .task_manager
-                .create_recurring_job(job_id.clone(), job.cron, 0, payload)
-                .await
-                .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+                .create_recurring_job(Uuid::new_v4(), job.cron, 0, payload, origin, retry)
+                .await?
         }
         JobType::Polling => {
             state
                 .task_manager
-                .create_polling_job(
-                    job.interval,
-                    0,
-                    job.schedule_at,
-                    job.max_retries.unwrap_or(3) as i32,
-                    payload,
-                )
-                .await
-                .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+                .create_polling_job(job.interval, 0, job.schedule_at, payload, origin, retry)
+                .await?
         }
-    }
+    };
+
+    let message = if created.replayed {
+        "Job already exists for this idempotency key"
+    } else {
+        "Job created successfully"
+    };
 
     Ok(Json(JobResponse {
-        message: "Job created successfully".to_string(),
-        job_id,
+        message: message.to_string(),
+        job_id: created.id,
     }))
 }
 
-#[get("/jobs/<id>")]
-pub async fn get_job(state: &State<AppConfig>, id: String) -> Result<Json<CoreJob>, ApiError> {
-    match state
-        .task_manager
-        .get_job(&id)
-        .await
-        .map_err(|e| ApiError::InternalServerError(e.to_string()))?
-    {
-        Some(job) => Ok(Json(convert_task_job_to_core_job(job))),
+#[get("/jobs/<id>?<include_archived>")]
+pub async fn get_job(
+    state: &State<AppConfig>,
+    id: String,
+    include_archived: Option<bool>,
+) -> Result<Json<Job>, ApiError> {
+    let job = if include_archived.unwrap_or(false) {
+        state.task_manager.get_job_including_archived(&id).await
+    } else {
+        state.task_manager.get_job(&id).await
+    };
+
+    match job.map_err(|e| ApiError::InternalServerError(e.to_string()))? {
+        Some(job) => Ok(Json(job)),
         None => Err(ApiError::NotFound(format!("Job with id {} not found", id))),
     }
 }
 
+#[get("/jobs/<id>/runs")]
+pub async fn get_job_runs(
+    state: &State<AppConfig>,
+    id: String,
+) -> Result<Json<Vec<JobRun>>, ApiError> {
+    if state.task_manager.get_job(&id).await?.is_none() {
+        return Err(ApiError::NotFound(format!("Job with id {} not found", id)));
+    }
+
+    let runs = state.task_manager.get_job_runs(&id).await?;
+    Ok(Json(runs))
+}
+
 #[get("/jobs")]
-pub async fn list_jobs(state: &State<AppConfig>) -> Result<Json<Vec<CoreJob>>, ApiError> {
+pub async fn list_jobs(state: &State<AppConfig>) -> Result<Json<Vec<Job>>, ApiError> {
     let jobs = state
         .task_manager
         .get_due_jobs(100)
         .await
         .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
-    Ok(Json(
-        jobs.into_iter().map(convert_task_job_to_core_job).collect(),
-    ))
+    Ok(Json(jobs))
 }
 
 #[put("/jobs/<id>", format = "json", data = "<job>")]
@@ -104,7 +170,7 @@ pub async fn update_job(
     state: &State<AppConfig>,
     id: String,
     job: Json<JobUpdate>,
-) -> Result<Json<CoreJob>, ApiError> {
+) -> Result<Json<Job>, ApiError> {
     let job_update = job.into_inner();
 
     match state
@@ -113,13 +179,12 @@ pub async fn update_job(
         .await
         .map_err(|e| ApiError::InternalServerError(e.to_string()))?
     {
-        Some(_existing_job) => {
+        Some(existing_job) => {
             if let Some(_schedule_type) = job_update.schedule_type {
                 state
                     .task_manager
-                    .update_job_status(&id, JobStatus::Pending)
-                    .await
-                    .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+                    .transition_job(&id, existing_job.status, JobStatus::Pending)
+                    .await?;
             }
 
             if let Some(_schedule) = job_update.schedule_at {
@@ -149,7 +214,7 @@ pub async fn update_job(
                 .await
                 .map_err(|e| ApiError::InternalServerError(e.to_string()))?
             {
-                Some(updated_job) => Ok(Json(convert_task_job_to_core_job(updated_job))),
+                Some(updated_job) => Ok(Json(updated_job)),
                 None => Err(ApiError::NotFound(format!("Job with id {} not found", id))),
             }
         }
@@ -162,22 +227,43 @@ pub async fn delete_job(
     state: &State<AppConfig>,
     id: String,
 ) -> Result<Json<DeleteResponse>, ApiError> {
-    match state
+    cancel(state, &id).await?;
+    Ok(Json(DeleteResponse {
+        message: format!("Job {} cancelled successfully", id),
+    }))
+}
+
+#[post("/jobs/<id>/cancel")]
+pub async fn cancel_job(state: &State<AppConfig>, id: String) -> Result<Json<Job>, ApiError> {
+    Ok(Json(cancel(state, &id).await?))
+}
+
+/// Moves the job to `Cancelled`, then pulls it off the job queue if it was
+/// waiting there or tells its executor to kill it if it was running.
+async fn cancel(state: &State<AppConfig>, id: &str) -> Result<Job, ApiError> {
+    let job = state
         .task_manager
-        .get_job(&id)
-        .await
-        .map_err(|e| ApiError::InternalServerError(e.to_string()))?
-    {
-        Some(_) => {
-            state
-                .task_manager
-                .update_job_status(&id, JobStatus::Failed)
-                .await
-                .map_err(|e| ApiError::InternalServerError(e.to_string()))?;
-            Ok(Json(DeleteResponse {
-                message: format!("Job {} deleted successfully", id),
-            }))
+        .get_job(id)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Job with id {} not found", id)))?;
+
+    state
+        .task_manager
+        .transition_job(id, job.status, JobStatus::Cancelled)
+        .await?;
+
+    match job.status {
+        JobStatus::Queued => {
+            state.cache.remove_from_queue(JOB_QUEUE, id).await?;
         }
-        None => Err(ApiError::NotFound(format!("Job with id {} not found", id))),
+        JobStatus::Running => {
+            state.cache.publish(CANCEL_CHANNEL, id).await?;
+        }
+        _ => {}
     }
+
+    Ok(Job {
+        status: JobStatus::Cancelled,
+        ..job
+    })
 }
@@ -1,5 +1,8 @@
+mod dead_letters;
 mod jobs;
 mod ping;
+mod workers;
+mod workflows;
 
 pub fn ping_routes() -> Vec<rocket::Route> {
     routes![
@@ -15,8 +18,32 @@ pub fn jobs_routes() -> Vec<rocket::Route> {
     routes![
         jobs::create_job,
         jobs::get_job,
+        jobs::get_job_runs,
         jobs::list_jobs,
         jobs::update_job,
-        jobs::delete_job
+        jobs::delete_job,
+        jobs::cancel_job
+    ]
+}
+
+pub fn workflows_routes() -> Vec<rocket::Route> {
+    routes![workflows::create_workflow, workflows::get_workflow]
+}
+
+pub fn dead_letters_routes() -> Vec<rocket::Route> {
+    routes![
+        dead_letters::list_dead_letters,
+        dead_letters::get_dead_letter,
+        dead_letters::replay_dead_letter,
+        dead_letters::purge_dead_letter,
+        dead_letters::purge_dead_letters
+    ]
+}
+
+pub fn workers_routes() -> Vec<rocket::Route> {
+    routes![
+        workers::list_workers,
+        workers::get_worker,
+        workers::drain_worker
     ]
 }
@@ -0,0 +1,41 @@
+use crate::config::AppConfig;
+use crate::error::ApiError;
+use rocket::get;
+use rocket::post;
+use rocket::serde::json::Json;
+use rocket::State;
+use scheduler_core::api_models::WorkerResponse;
+use scheduler_core::models::WorkerDetails;
+
+#[get("/workers")]
+pub async fn list_workers(state: &State<AppConfig>) -> Result<Json<Vec<WorkerDetails>>, ApiError> {
+    let workers = state.task_manager.get_workers().await?;
+    Ok(Json(workers))
+}
+
+#[get("/workers/<id>")]
+pub async fn get_worker(
+    state: &State<AppConfig>,
+    id: String,
+) -> Result<Json<WorkerDetails>, ApiError> {
+    match state.task_manager.get_worker(&id).await? {
+        Some(worker) => Ok(Json(worker)),
+        None => Err(ApiError::NotFound(format!("Worker {} not found", id))),
+    }
+}
+
+/// Stops the worker from taking new jobs. Jobs it is running finish normally.
+#[post("/workers/<id>/drain")]
+pub async fn drain_worker(
+    state: &State<AppConfig>,
+    id: String,
+) -> Result<Json<WorkerResponse>, ApiError> {
+    if !state.task_manager.drain_worker(&id).await? {
+        return Err(ApiError::NotFound(format!("Worker {} not found", id)));
+    }
+
+    Ok(Json(WorkerResponse {
+        message: "Worker is draining".to_string(),
+        worker_id: id,
+    }))
+}
@@ -0,0 +1,42 @@
+use crate::config::AppConfig;
+use crate::error::ApiError;
+use rocket::get;
+use rocket::post;
+use rocket::serde::json::Json;
+use rocket::State;
+use scheduler_core::api_models::WorkflowResponse;
+use scheduler_core::workflow::{WorkflowDefinition, WorkflowRunDetails};
+use uuid::Uuid;
+
+#[post("/workflows", format = "json", data = "<workflow>")]
+pub async fn create_workflow(
+    state: &State<AppConfig>,
+    workflow: Json<WorkflowDefinition>,
+) -> Result<Json<WorkflowResponse>, ApiError> {
+    let workflow_run_id = state
+        .workflow_manager
+        .create_workflow(&workflow.into_inner())
+        .await?;
+
+    Ok(Json(WorkflowResponse {
+        message: "Workflow created successfully".to_string(),
+        workflow_run_id,
+    }))
+}
+
+#[get("/workflows/<id>")]
+pub async fn get_workflow(
+    state: &State<AppConfig>,
+    id: String,
+) -> Result<Json<WorkflowRunDetails>, ApiError> {
+    let run_id = Uuid::parse_str(&id)
+        .map_err(|e| ApiError::BadRequest(format!("Invalid workflow run id: {}", e)))?;
+
+    match state.workflow_manager.get_workflow(run_id).await? {
+        Some(workflow) => Ok(Json(workflow)),
+        None => Err(ApiError::NotFound(format!(
+            "Workflow run with id {} not found",
+            id
+        ))),
+    }
+}
@@ -4,7 +4,10 @@ extern crate rocket;
 use crate::config::AppConfig;
 use middleware::logging::LoggerFairing;
 use rocket::{Build, Rocket};
-use scheduler_core::{config::Config, init::init_database};
+use scheduler_core::{
+    config::Config,
+    init::{init_cache, init_database},
+};
 use security::jwt::JWTAuthenticator;
 
 mod config;
@@ -25,12 +28,22 @@ async fn rocket() -> Rocket<Build> {
         .await
         .expect("Failed to initialize database connection");
 
-    let app_config = AppConfig::new(db);
+    let cache = init_cache(&config)
+        .await
+        .expect("Failed to initialize cache connection");
+
+    // For ApiKeyGuard
+    let pool = db.pool().clone();
+    let app_config = AppConfig::new(db, cache);
 
     rocket::build()
         .manage(JWTAuthenticator::new())
+        .manage(pool)
         .manage(app_config)
         .attach(LoggerFairing)
         .mount("/", handlers::ping_routes())
         .mount("/", handlers::jobs_routes())
+        .mount("/", handlers::workflows_routes())
+        .mount("/", handlers::dead_letters_routes())
+        .mount("/", handlers::workers_routes())
 }
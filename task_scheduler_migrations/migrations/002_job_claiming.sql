@@ -0,0 +1,9 @@
+-- Jobs handed to the queue by a populator are 'queued' until an executor picks them up
+ALTER TYPE job_status ADD VALUE IF NOT EXISTS 'queued' AFTER 'pending';
+
+-- Which populator instance claimed the job, and when
+ALTER TABLE jobs ADD COLUMN claimed_by TEXT;
+ALTER TABLE jobs ADD COLUMN claimed_at TIMESTAMP WITH TIME ZONE;
+
+-- Supports the due-job claim query
+CREATE INDEX idx_jobs_status_scheduled_at ON jobs (status, scheduled_at);
@@ -0,0 +1,4 @@
+-- Every job carries a payload object so rows always map onto the job model
+UPDATE jobs SET payload = '{}'::jsonb WHERE payload IS NULL;
+ALTER TABLE jobs ALTER COLUMN payload SET DEFAULT '{}'::jsonb;
+ALTER TABLE jobs ALTER COLUMN payload SET NOT NULL;
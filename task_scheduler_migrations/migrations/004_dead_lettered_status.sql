@@ -0,0 +1,2 @@
+-- Jobs that exhausted their retries are parked as 'dead_lettered' instead of staying 'failed'
+ALTER TYPE job_status ADD VALUE IF NOT EXISTS 'dead_lettered';
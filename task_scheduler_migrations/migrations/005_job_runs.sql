@@ -0,0 +1,18 @@
+-- One row per execution attempt of a job
+CREATE TABLE job_runs (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    job_id UUID NOT NULL,
+    attempt INTEGER NOT NULL,
+    worker_id TEXT NOT NULL,
+    started_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    finished_at TIMESTAMP WITH TIME ZONE,
+    duration_ms BIGINT,
+    exit_code INTEGER,
+    stdout TEXT,
+    stderr TEXT,
+    error_class TEXT,
+    error_message TEXT
+);
+
+-- jobs is partitioned on (id, created_at), so job_id cannot be a foreign key
+CREATE INDEX idx_job_runs_job_id ON job_runs (job_id, started_at);
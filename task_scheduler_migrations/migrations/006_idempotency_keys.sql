@@ -0,0 +1,11 @@
+-- Client-supplied idempotency keys for job creation, unique per merchant. A
+-- unique index on the partitioned jobs table would have to include created_at,
+-- so keys live in their own table. Only requests authenticated with an API key
+-- can use one, and the key is scoped to that key's merchant.
+CREATE TABLE idempotency_keys (
+    merchant_id UUID NOT NULL,
+    idempotency_key TEXT NOT NULL,
+    resource_id UUID NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (merchant_id, idempotency_key)
+);
@@ -0,0 +1,14 @@
+-- What a job does when one of its upstream jobs is cancelled or dead-lettered
+CREATE TYPE upstream_failure_policy AS ENUM ('cascade_cancel', 'run_anyway');
+
+ALTER TABLE jobs ADD COLUMN on_upstream_failure upstream_failure_policy NOT NULL DEFAULT 'cascade_cancel';
+
+-- job_id only becomes due once every depends_on job has completed
+CREATE TABLE job_dependencies (
+    job_id UUID NOT NULL,
+    depends_on UUID NOT NULL,
+    PRIMARY KEY (job_id, depends_on),
+    CHECK (job_id <> depends_on)
+);
+
+CREATE INDEX idx_job_dependencies_depends_on ON job_dependencies (depends_on);
@@ -0,0 +1,32 @@
+CREATE TYPE workflow_status AS ENUM ('running', 'completed', 'failed');
+
+-- One submitted workflow, tracked as a unit
+CREATE TABLE workflow_runs (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name TEXT NOT NULL,
+    merchant_id UUID REFERENCES merchants(id),
+    status workflow_status NOT NULL DEFAULT 'running',
+    input JSONB NOT NULL DEFAULT '{}',
+    definition JSONB NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    completed_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX idx_workflow_runs_status ON workflow_runs (status);
+
+-- The jobs a workflow run expanded into. Fan-out steps have one row per item.
+-- Steps with a `when` condition are held back from the queue while
+-- awaiting_condition is set, and skipped if the condition does not hold.
+CREATE TABLE workflow_steps (
+    workflow_run_id UUID NOT NULL REFERENCES workflow_runs(id) ON DELETE CASCADE,
+    step_name TEXT NOT NULL,
+    job_id UUID NOT NULL,
+    fan_out_index INTEGER,
+    awaiting_condition BOOLEAN NOT NULL DEFAULT false,
+    skipped BOOLEAN NOT NULL DEFAULT false,
+    PRIMARY KEY (workflow_run_id, job_id)
+);
+
+CREATE UNIQUE INDEX idx_workflow_steps_job_id ON workflow_steps (job_id);
+CREATE INDEX idx_workflow_steps_awaiting ON workflow_steps (job_id) WHERE awaiting_condition;
@@ -0,0 +1,9 @@
+-- Terminal jobs are moved here once they age out of the live jobs table. The
+-- columns mirror jobs, so any column added to jobs must be added here too.
+CREATE TABLE jobs_archive (
+    LIKE jobs INCLUDING DEFAULTS,
+    archived_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (id)
+);
+
+CREATE INDEX idx_jobs_archive_archived_at ON jobs_archive (archived_at);
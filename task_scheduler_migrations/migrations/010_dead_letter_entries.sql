@@ -0,0 +1,15 @@
+-- Why a job ended up dead-lettered
+CREATE TYPE dead_letter_reason AS ENUM ('retries_exhausted', 'orphaned');
+
+-- One entry per dead-lettered job, removed again when the job is replayed or
+-- the entry is purged.
+CREATE TABLE dead_letter_entries (
+    job_id UUID PRIMARY KEY,
+    merchant_id UUID,
+    reason dead_letter_reason NOT NULL,
+    last_error TEXT,
+    attempts INTEGER NOT NULL,
+    dead_lettered_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_dead_letter_entries_merchant ON dead_letter_entries (merchant_id, dead_lettered_at);
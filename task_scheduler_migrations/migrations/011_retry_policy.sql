@@ -0,0 +1,11 @@
+-- Per-job retry backoff; see scheduler_core::retry::RetryPolicy. The default
+-- matches the backoff the failure watcher used before policies existed.
+ALTER TABLE jobs ADD COLUMN retry_policy JSONB NOT NULL
+    DEFAULT '{"strategy": "exponential", "initial_delay_secs": 60, "max_delay_secs": 3600}';
+
+ALTER TABLE jobs_archive ADD COLUMN retry_policy JSONB NOT NULL
+    DEFAULT '{"strategy": "exponential", "initial_delay_secs": 60, "max_delay_secs": 3600}';
+
+-- Jobs spawned from a template inherit its policy
+ALTER TABLE templates ADD COLUMN retry_policy JSONB NOT NULL
+    DEFAULT '{"strategy": "exponential", "initial_delay_secs": 60, "max_delay_secs": 3600}';
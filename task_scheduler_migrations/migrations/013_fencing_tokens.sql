@@ -0,0 +1,5 @@
+-- Incremented by every claim of a job. Writes made on behalf of an attempt
+-- carry the token it was issued and are rejected once a newer claim exists.
+ALTER TABLE jobs ADD COLUMN fencing_token BIGINT NOT NULL DEFAULT 0;
+
+ALTER TABLE jobs_archive ADD COLUMN fencing_token BIGINT NOT NULL DEFAULT 0;
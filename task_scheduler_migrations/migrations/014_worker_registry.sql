@@ -0,0 +1,9 @@
+-- What each executor is and how busy it is, refreshed with every heartbeat.
+-- A draining worker finishes its in-flight jobs but takes no new ones.
+ALTER TABLE workers ADD COLUMN version TEXT NOT NULL DEFAULT '';
+ALTER TABLE workers ADD COLUMN capabilities TEXT[] NOT NULL DEFAULT '{}';
+ALTER TABLE workers ADD COLUMN concurrency_limit INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE workers ADD COLUMN running_jobs INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE workers ADD COLUMN draining BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX idx_jobs_running_lease_owner ON jobs (lease_owner) WHERE status = 'running';
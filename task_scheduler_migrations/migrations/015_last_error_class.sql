@@ -0,0 +1,5 @@
+-- How the job's last failed attempt ended, e.g. 'timed_out' or 'exit_status',
+-- matching the error_class of its job_runs row.
+ALTER TABLE jobs ADD COLUMN last_error_class TEXT;
+
+ALTER TABLE jobs_archive ADD COLUMN last_error_class TEXT;
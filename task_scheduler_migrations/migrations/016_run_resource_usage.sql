@@ -0,0 +1,4 @@
+-- Resources an attempt consumed, read from its cgroup. NULL when the executor
+-- ran the job without cgroup accounting.
+ALTER TABLE job_runs ADD COLUMN peak_memory_bytes BIGINT;
+ALTER TABLE job_runs ADD COLUMN cpu_time_ms BIGINT;
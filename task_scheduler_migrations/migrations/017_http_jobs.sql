@@ -0,0 +1,8 @@
+-- What an http job's endpoint answered, NULL for other kinds of jobs. The
+-- body is cut off like stdout and stderr.
+ALTER TABLE job_runs ADD COLUMN http_status INTEGER;
+ALTER TABLE job_runs ADD COLUMN response_body TEXT;
+
+-- Jobs that failed in a way retrying cannot fix, e.g. an endpoint rejecting
+-- the request, skip their remaining attempts
+ALTER TYPE dead_letter_reason ADD VALUE 'permanent_failure';
@@ -0,0 +1,4 @@
+-- What an in-process task handler returned, and the progress it last
+-- reported while running. NULL for commands and http jobs.
+ALTER TABLE job_runs ADD COLUMN result JSONB;
+ALTER TABLE job_runs ADD COLUMN progress JSONB;
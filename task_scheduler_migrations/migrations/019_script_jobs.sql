@@ -0,0 +1,3 @@
+-- SHA-256 of the script a script job ran, hex-encoded, so a run can be
+-- matched to the exact script body. NULL for other job kinds.
+ALTER TABLE job_runs ADD COLUMN script_hash TEXT;
@@ -0,0 +1,6 @@
+-- Times a worker that could not run the job, e.g. because it lacks the job's
+-- kind or sandbox profile, handed it back to the queue for another worker.
+-- Bounces do not use up retries; past a cap the job is dead-lettered.
+ALTER TABLE jobs ADD COLUMN bounces INTEGER NOT NULL DEFAULT 0;
+
+ALTER TABLE jobs_archive ADD COLUMN bounces INTEGER NOT NULL DEFAULT 0;